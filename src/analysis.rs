@@ -0,0 +1,284 @@
+use crate::graph::dataflow::{solve, DataflowAnalysis, DataflowResult, Direction};
+use crate::instruction::Instruction;
+use crate::ir::ENTRY_BLOCK;
+use crate::op::Op;
+use crate::resolver::{Block, BranchKind};
+use crate::{DecompileError, Graph};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Slot definition, pc of the writing instruction and the slot
+pub type Def = (u32, u16);
+
+// ISTC and ISFC copy value on the jump edge only, so old slot value is still available
+#[inline(always)]
+fn kills_defs(ins: &Instruction) -> bool {
+    !matches!(ins.op, Op::ISTC(..) | Op::ISFC(..))
+}
+
+fn decode_blocks(
+    graph: &Graph<Block, BranchKind>,
+) -> Result<HashMap<u32, Vec<Instruction>>, DecompileError> {
+    graph
+        .iter_node_weights()
+        .map(|(idx, block)| Ok((idx, Instruction::decode_all(idx, block.data())?)))
+        .collect()
+}
+
+// live slots before instruction from live slots after it
+fn step_liveness(ins: &Instruction, live: &mut BTreeSet<u16>) {
+    if kills_defs(ins) {
+        for slot in ins.defs() {
+            live.remove(&slot);
+        }
+    }
+
+    live.extend(ins.uses());
+}
+
+// reaching definitions after instruction from reaching definitions before it
+fn step_reaching(ins: &Instruction, reaching: &mut BTreeSet<Def>) {
+    let defs = ins.defs();
+
+    if kills_defs(ins) {
+        reaching.retain(|(_, slot)| !defs.contains(slot));
+    }
+
+    reaching.extend(defs.into_iter().map(|slot| (ins.pc, slot)));
+}
+
+/// Backward analysis of slots which are read before being overwritten
+pub struct Liveness {
+    blocks: HashMap<u32, Vec<Instruction>>,
+}
+
+impl Liveness {
+    pub fn new(graph: &Graph<Block, BranchKind>) -> Result<Self, DecompileError> {
+        Ok(Liveness {
+            blocks: decode_blocks(graph)?,
+        })
+    }
+
+    /// Returns live slots at the start and at the end of every basic block
+    pub fn analyze(
+        graph: &Graph<Block, BranchKind>,
+    ) -> Result<DataflowResult<BTreeSet<u16>>, DecompileError> {
+        Ok(solve(graph, ENTRY_BLOCK, &Liveness::new(graph)?))
+    }
+
+    /// Returns slots live after every instruction of the block, `live_out` is the block exit fact
+    pub fn live_after(&self, block_idx: u32, live_out: &BTreeSet<u16>) -> Vec<BTreeSet<u16>> {
+        let mut live = live_out.clone();
+        let mut result = vec![];
+
+        for ins in self.blocks[&block_idx].iter().rev() {
+            result.push(live.clone());
+            step_liveness(ins, &mut live);
+        }
+
+        result.reverse();
+        result
+    }
+}
+
+impl DataflowAnalysis<Block, BranchKind> for Liveness {
+    type Fact = BTreeSet<u16>;
+
+    fn direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn boundary(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn initial(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.extend(other.iter().cloned());
+    }
+
+    fn transfer(&self, index: u32, _: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut live = fact.clone();
+
+        for ins in self.blocks[&index].iter().rev() {
+            step_liveness(ins, &mut live);
+        }
+
+        live
+    }
+}
+
+/// Forward analysis of slot definitions which can reach a point without being overwritten.
+/// Parameters and slots read before any write have no definitions.
+pub struct ReachingDefinitions {
+    blocks: HashMap<u32, Vec<Instruction>>,
+}
+
+impl ReachingDefinitions {
+    pub fn new(graph: &Graph<Block, BranchKind>) -> Result<Self, DecompileError> {
+        Ok(ReachingDefinitions {
+            blocks: decode_blocks(graph)?,
+        })
+    }
+
+    pub fn analyze(
+        graph: &Graph<Block, BranchKind>,
+    ) -> Result<DataflowResult<BTreeSet<Def>>, DecompileError> {
+        Ok(solve(graph, ENTRY_BLOCK, &ReachingDefinitions::new(graph)?))
+    }
+}
+
+impl DataflowAnalysis<Block, BranchKind> for ReachingDefinitions {
+    type Fact = BTreeSet<Def>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn initial(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.extend(other.iter().cloned());
+    }
+
+    fn transfer(&self, index: u32, _: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut reaching = fact.clone();
+
+        for ins in &self.blocks[&index] {
+            step_reaching(ins, &mut reaching);
+        }
+
+        reaching
+    }
+}
+
+/// Links every slot definition with instructions reading it and vice versa
+#[derive(Debug, Default)]
+pub struct DefUseChains {
+    /// pcs of instructions reading the definition
+    pub uses: BTreeMap<Def, Vec<u32>>,
+    /// pcs of definitions reaching the read of slot `(pc, slot)`, empty for parameters
+    pub defs: BTreeMap<(u32, u16), Vec<u32>>,
+}
+
+impl DefUseChains {
+    pub fn build(graph: &Graph<Block, BranchKind>) -> Result<Self, DecompileError> {
+        let analysis = ReachingDefinitions::new(graph)?;
+        let reaching_defs = solve(graph, ENTRY_BLOCK, &analysis);
+
+        let mut chains = DefUseChains::default();
+
+        for (block_idx, instructions) in &analysis.blocks {
+            let mut reaching = reaching_defs.entry[block_idx].clone();
+
+            for ins in instructions {
+                for slot in ins.uses() {
+                    let defs: Vec<u32> = reaching
+                        .iter()
+                        .filter(|(_, def_slot)| *def_slot == slot)
+                        .map(|&(def_pc, _)| def_pc)
+                        .collect();
+
+                    for &def_pc in &defs {
+                        chains.uses.entry((def_pc, slot)).or_default().push(ins.pc);
+                    }
+
+                    chains.defs.insert((ins.pc, slot), defs);
+                }
+
+                for slot in ins.defs() {
+                    chains.uses.entry((ins.pc, slot)).or_default();
+                }
+
+                step_reaching(ins, &mut reaching);
+            }
+        }
+
+        for uses in chains.uses.values_mut() {
+            uses.sort_unstable();
+        }
+
+        Ok(chains)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::{DefUseChains, Liveness, ReachingDefinitions};
+    use crate::disasm::{ins_abc, ins_ad};
+    use crate::resolver::resolve_basic_blocks;
+    use crate::DecompileError;
+    use std::collections::BTreeSet;
+
+    fn set<T: Ord + Copy>(items: &[T]) -> BTreeSet<T> {
+        items.iter().cloned().collect()
+    }
+
+    // local a, b, c = 1, nil, nil; if not (a < b) then a = 2 end; return a .. b .. c
+    fn test_bc() -> Vec<u32> {
+        vec![
+            ins_ad(0x29, 0, 1),      // KSHORT 0 1
+            ins_ad(0x2c, 1, 2),      // KNIL 1 2
+            ins_ad(0x00, 0, 1),      // ISLT 0 1
+            ins_ad(0x58, 3, 0x8001), // JMP => 5
+            ins_ad(0x29, 0, 2),      // KSHORT 0 2
+            ins_abc(0x26, 3, 0, 2),  // CAT 3 0 2
+            ins_ad(0x4c, 3, 2),      // RET1 3 2
+        ]
+    }
+
+    #[test]
+    fn register_liveness() -> Result<(), DecompileError> {
+        let graph = resolve_basic_blocks(&test_bc())?;
+        let result = Liveness::analyze(&graph)?;
+
+        assert_eq!(result.entry[&0], set(&[]));
+        assert_eq!(result.exit[&0], set(&[0, 1, 2]));
+        assert_eq!(result.entry[&4], set(&[1, 2]));
+        assert_eq!(result.entry[&5], set(&[0, 1, 2]));
+
+        let liveness = Liveness::new(&graph)?;
+        assert_eq!(
+            liveness.live_after(0, &result.exit[&0]),
+            vec![set(&[0]), set(&[0, 1, 2]), set(&[0, 1, 2]), set(&[0, 1, 2])]
+        );
+        assert_eq!(liveness.live_after(5, &result.exit[&5]), vec![set(&[3]), set(&[])]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reaching_definitions() -> Result<(), DecompileError> {
+        let graph = resolve_basic_blocks(&test_bc())?;
+        let result = ReachingDefinitions::analyze(&graph)?;
+
+        assert_eq!(result.entry[&0], set(&[]));
+        assert_eq!(result.exit[&4], set(&[(4, 0), (1, 1), (1, 2)]));
+        assert_eq!(result.entry[&5], set(&[(0, 0), (1, 1), (1, 2), (4, 0)]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn def_use_chains() -> Result<(), DecompileError> {
+        let graph = resolve_basic_blocks(&test_bc())?;
+        let chains = DefUseChains::build(&graph)?;
+
+        assert_eq!(chains.defs[&(5, 0)], vec![0, 4]);
+        assert_eq!(chains.defs[&(5, 2)], vec![1]);
+        assert_eq!(chains.uses[&(0, 0)], vec![2, 5]);
+        assert_eq!(chains.uses[&(1, 2)], vec![5]);
+        assert_eq!(chains.uses[&(4, 0)], vec![5]);
+        assert_eq!(chains.uses[&(5, 3)], vec![6]);
+
+        Ok(())
+    }
+}
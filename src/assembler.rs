@@ -0,0 +1,458 @@
+use std::collections::BTreeMap;
+
+use crate::bytecode_reader::{
+    write_bytecode_dump, GlobalConst, NumConst, BC_F_STRIP, PROTO_CHILD, PROTO_FFI, PROTO_VARARG,
+};
+use crate::op::{OpMode, OP_INFO};
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+
+// lua frame slots limit
+const MAX_SLOTS: u32 = 250;
+
+// global constant referenced by listing instruction
+enum ListingConst {
+    Str(Vec<u8>),
+    // string is cut in listing comment
+    TruncatedStr,
+    Child,
+    // template tables and cdata have no listing comments
+    Unknown,
+}
+
+#[derive(Default)]
+struct ListingProto {
+    bc_raw: Vec<u32>,
+    global_consts: BTreeMap<u16, ListingConst>,
+    num_consts: BTreeMap<u16, f64>,
+    uses_up_values: bool,
+    flags: u8,
+    frame_size: u32,
+}
+
+#[inline(always)]
+fn listing_error(line_no: usize, msg: &'static str) -> DecompileError {
+    DecompileError::InvalidListing(line_no, msg)
+}
+
+// parse quoted string comment, control chars are escaped as `\n`, `\r`, `\t` and `\ddd`
+fn parse_str_comment(comment: &str) -> Option<ListingConst> {
+    if comment.ends_with("\"~") {
+        return Some(ListingConst::TruncatedStr);
+    }
+
+    let str = comment.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = Vec::with_capacity(str.len());
+    let mut bytes = str.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            unescaped.push(byte);
+            continue;
+        }
+
+        match bytes.next()? {
+            b'n' => unescaped.push(b'\n'),
+            b'r' => unescaped.push(b'\r'),
+            b't' => unescaped.push(b'\t'),
+            digit => {
+                let code = [digit, bytes.next()?, bytes.next()?];
+                unescaped.push(std::str::from_utf8(&code).ok()?.parse::<u8>().ok()?);
+            }
+        }
+    }
+
+    Some(ListingConst::Str(unescaped))
+}
+
+// parse instruction line: `0006 => GGET     2   1      ; "print"`
+fn parse_ins_line(
+    line: &str,
+    line_no: usize,
+    proto: &mut ListingProto,
+) -> Result<(), DecompileError> {
+    let pc = proto.bc_raw.len() + 1;
+
+    let (ins, mut comment) = match line.split_once(';') {
+        Some((ins, comment)) => (ins, Some(comment.trim())),
+        None => (line, None),
+    };
+
+    let mut tokens = ins
+        .split_whitespace()
+        .filter(|&token| token != "=>")
+        .peekable();
+
+    if tokens.next().and_then(|token| token.parse::<usize>().ok()) != Some(pc) {
+        return Err(listing_error(line_no, "unexpected instruction pc"));
+    }
+
+    let name = tokens
+        .next()
+        .ok_or_else(|| listing_error(line_no, "missing mnemonic"))?;
+    let (opcode, info) = OP_INFO
+        .iter()
+        .enumerate()
+        .find(|(_, info)| info.name == name)
+        .ok_or_else(|| listing_error(line_no, "unknown mnemonic"))?;
+
+    let mut operand = |mode: OpMode| -> Result<i64, DecompileError> {
+        if mode == OpMode::None {
+            return Ok(0);
+        }
+
+        tokens
+            .next()
+            .and_then(|token| token.parse::<i64>().ok())
+            .ok_or_else(|| listing_error(line_no, "invalid operand"))
+    };
+
+    let a = operand(info.a)?;
+    let b = operand(info.b)?;
+    let mut cd = operand(info.cd)?;
+
+    if tokens.next().is_some() {
+        return Err(listing_error(line_no, "unexpected operand"));
+    }
+
+    // jump operand is a listing pc of destination
+    if info.cd == OpMode::Jump {
+        cd = cd - pc as i64 + 0x7fff;
+    }
+
+    let max_cd = if info.b == OpMode::None { 0xffff } else { 0xff };
+    let min_cd = if info.cd == OpMode::LitS { -0x8000 } else { 0 };
+
+    if !(0..=0xff).contains(&a) || !(0..=0xff).contains(&b) || cd < min_cd || cd > max_cd {
+        return Err(listing_error(line_no, "operand is out of range"));
+    }
+
+    let (a, b, cd) = (a as u32, b as u32, cd as u32 & 0xffff);
+
+    let ins_raw = if info.b == OpMode::None {
+        opcode as u32 | a << 8 | cd << 16
+    } else {
+        opcode as u32 | a << 8 | cd << 16 | b << 24
+    };
+    proto.bc_raw.push(ins_raw);
+
+    // name of up value in A operand precedes constant comment
+    if info.a == OpMode::UV && matches!(info.cd, OpMode::Str | OpMode::Num) {
+        comment = comment
+            .and_then(|comment| comment.split_once(';'))
+            .map(|(_, comment)| comment.trim());
+    }
+
+    // collect constants from comments
+    let d = cd as u16;
+    match info.cd {
+        OpMode::Str => {
+            let str = comment
+                .and_then(parse_str_comment)
+                .ok_or_else(|| listing_error(line_no, "missing string constant"))?;
+            proto.global_consts.insert(d, str);
+        }
+        OpMode::Num => {
+            let mut num = comment
+                .and_then(|comment| comment.parse::<f64>().ok())
+                .ok_or_else(|| listing_error(line_no, "missing number constant"))?;
+
+            // TSETM keeps start index in the low bits of a double
+            if info.name == "TSETM" {
+                num += 2f64.powi(52);
+            }
+            proto.num_consts.insert(d, num);
+        }
+        OpMode::Func => {
+            proto.global_consts.insert(d, ListingConst::Child);
+            proto.flags |= PROTO_CHILD;
+        }
+        OpMode::Tab => {
+            proto.global_consts.insert(d, ListingConst::Unknown);
+        }
+        OpMode::CData => {
+            proto.global_consts.insert(d, ListingConst::Unknown);
+            proto.flags |= PROTO_FFI;
+        }
+        _ => {}
+    }
+
+    if info.a == OpMode::UV || info.cd == OpMode::UV {
+        proto.uses_up_values = true;
+    }
+
+    if info.name == "VARG" {
+        proto.flags |= PROTO_VARARG;
+    }
+
+    // estimate frame size from used slots, call ranges are counted from base
+    for (mode, val) in [(info.a, a), (info.b, b), (info.cd, cd)] {
+        if matches!(
+            mode,
+            OpMode::Dst | OpMode::Base | OpMode::Var | OpMode::RBase
+        ) {
+            proto.frame_size = proto.frame_size.max(val + 1);
+        }
+    }
+
+    if info.a == OpMode::Base {
+        let lits = [(info.b, b), (info.cd, cd)];
+        let max_lit = lits
+            .iter()
+            .filter(|(mode, _)| *mode == OpMode::Lit)
+            .map(|(_, val)| *val)
+            .max();
+
+        if let Some(max_lit) = max_lit {
+            proto.frame_size = proto.frame_size.max(a + max_lit + 2);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_listing(text: &str) -> Result<Vec<ListingProto>, DecompileError> {
+    let mut prototypes: Vec<ListingProto> = vec![];
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+
+        if line.starts_with("-- BYTECODE --") {
+            prototypes.push(ListingProto::default());
+        } else if !line.trim().is_empty() {
+            let proto = prototypes
+                .last_mut()
+                .ok_or_else(|| listing_error(line_no, "instruction is out of prototype"))?;
+            parse_ins_line(line, line_no, proto)?;
+        }
+    }
+
+    Ok(prototypes)
+}
+
+// build prototype from listing only, every constant has to be recovered from comments
+fn build_prototype(proto: ListingProto) -> Result<ByteCodeProto, DecompileError> {
+    if proto.uses_up_values {
+        return Err(listing_error(
+            0,
+            "up values can't be assembled without base dump",
+        ));
+    }
+
+    let size_global_consts = proto
+        .global_consts
+        .keys()
+        .last()
+        .map_or(0, |&d| d as usize + 1);
+    let mut global_consts: Vec<GlobalConst> = (0..size_global_consts)
+        .map(|_| GlobalConst::Str(vec![]))
+        .collect();
+
+    // global constants are indexed from the end
+    for (d, global_const) in proto.global_consts {
+        global_consts[size_global_consts - d as usize - 1] = match global_const {
+            ListingConst::Str(str) => GlobalConst::Str(str),
+            ListingConst::Child => GlobalConst::ProtoChild(0),
+            ListingConst::TruncatedStr => {
+                return Err(listing_error(
+                    0,
+                    "long strings can't be assembled without base dump",
+                ))
+            }
+            ListingConst::Unknown => {
+                return Err(listing_error(
+                    0,
+                    "tables and cdata can't be assembled without base dump",
+                ))
+            }
+        };
+    }
+
+    let size_num_consts = proto
+        .num_consts
+        .keys()
+        .last()
+        .map_or(0, |&d| d as usize + 1);
+    let mut num_consts: Vec<NumConst> = (0..size_num_consts).map(|_| NumConst::Int(0)).collect();
+
+    // integral numbers are narrowed to integers like luajit does
+    for (d, num) in proto.num_consts {
+        let int = num as i32;
+
+        num_consts[d as usize] = if int as f64 == num && !(num == 0.0 && num.is_sign_negative()) {
+            NumConst::Int(int as u32)
+        } else {
+            let bits = num.to_bits();
+            NumConst::Num(bits as u32, (bits >> 32) as u32)
+        };
+    }
+
+    ByteCodeProto::from_parts(
+        proto.flags,
+        0,
+        proto.frame_size.min(MAX_SLOTS) as u8,
+        vec![],
+        global_consts,
+        num_consts,
+        proto.bc_raw,
+    )
+}
+
+/// Assembles `luajit -bl` listing into binary dump. With base dump only bytecode of its
+/// prototypes is replaced, otherwise constants are recovered from listing comments and
+/// stripped dump is produced.
+///
+/// Comments don't keep every constant exactly: luajit doesn't escape backslashes, so `\n`,
+/// `\r`, `\t` and `\ddd` in strings are always read as escapes, and numbers are printed with
+/// 14 significant digits only. Pass base dump to keep such constants unchanged.
+pub fn assemble_listing(
+    text: &str,
+    base: Option<&ByteCodeDump>,
+) -> Result<Vec<u8>, DecompileError> {
+    let listing_prototypes = parse_listing(text)?;
+
+    let dump = match base {
+        Some(base) => {
+            if base.prototypes().len() != listing_prototypes.len() {
+                return Err(listing_error(0, "prototype count differs from base dump"));
+            }
+
+            let mut prototypes = Vec::with_capacity(listing_prototypes.len());
+            for (listing_proto, base_proto) in listing_prototypes.into_iter().zip(base.prototypes())
+            {
+                let mut proto = base_proto.clone();
+                proto.set_bc_raw(listing_proto.bc_raw)?;
+                prototypes.push(proto);
+            }
+
+            ByteCodeDump::from_prototypes(base.flags(), base.name_bytes().to_vec(), prototypes)
+        }
+        None => {
+            let prototypes = listing_prototypes
+                .into_iter()
+                .map(build_prototype)
+                .collect::<Result<Vec<ByteCodeProto>, DecompileError>>()?;
+
+            ByteCodeDump::from_prototypes(BC_F_STRIP, vec![], prototypes)
+        }
+    };
+
+    assemble_dump(&dump)
+}
+
+/// Serialises structured dump into binary form
+pub fn assemble_dump(dump: &ByteCodeDump) -> Result<Vec<u8>, DecompileError> {
+    let mut data = vec![];
+    write_bytecode_dump(&mut data, dump)?;
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble_dump, assemble_listing};
+    use crate::bytecode_reader::{GlobalConst, NumConst, BC_F_STRIP};
+    use crate::listing::dump_listing;
+    use crate::{read_bytecode_dump, ByteCodeDump, ByteCodeProto};
+
+    const LISTING: &str = "-- BYTECODE -- ?:0-0\n\
+        0001    KSTR     0   0      ; \"license\\tok\"\n\
+        0002    KSHORT   1  -1\n\
+        0003    ISLT     0   1\n\
+        0004    JMP      2 => 0006\n\
+        0005    KNUM     1   0      ; 3.5\n\
+        0006 => GGET     2   1      ; \"print\"\n\
+        0007    CALL     2   1   2\n\
+        0008    RET0     0   1\n\
+        \n";
+
+    #[test]
+    fn assemble_without_base() {
+        let data = assemble_listing(LISTING, None).unwrap();
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+
+        assert_eq!(dump_listing(&dump), LISTING);
+    }
+
+    #[test]
+    fn assemble_patch_with_base() {
+        let proto = ByteCodeProto::from_parts(
+            0,
+            0,
+            3,
+            vec![],
+            vec![
+                GlobalConst::Str(b"print".to_vec()),
+                GlobalConst::Str(b"license\tok".to_vec()),
+            ],
+            vec![NumConst::Num(0, 0x400c_0000)],
+            vec![
+                0x0000_0027,
+                0xffff_0129,
+                0x0001_0000,
+                0x8001_0258,
+                0x0000_012a,
+                0x0001_0236,
+                0x0102_0242,
+                0x0001_004b,
+            ],
+        )
+        .unwrap();
+        let base = ByteCodeDump::from_prototypes(BC_F_STRIP, vec![], vec![proto]);
+        assert_eq!(dump_listing(&base), LISTING);
+
+        // make condition jump always
+        let patched_listing =
+            LISTING.replace("0003    ISLT     0   1\n", "0003    ISLT     1   1\n");
+        let data = assemble_listing(&patched_listing, Some(&base)).unwrap();
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+
+        assert_eq!(dump_listing(&dump), patched_listing);
+        assert_eq!(dump.prototypes()[0].bc_raw()[2], 0x0001_0100);
+
+        // unmodified listing gives the same dump
+        let data = assemble_listing(LISTING, Some(&base)).unwrap();
+        assert_eq!(data, assemble_dump(&base).unwrap());
+    }
+
+    #[test]
+    fn assemble_inexact_constants() {
+        // literal backslash followed by `n` and a number which needs 17 digits
+        let proto = ByteCodeProto::from_parts(
+            0,
+            0,
+            2,
+            vec![],
+            vec![GlobalConst::Str(b"a\\nb".to_vec())],
+            vec![NumConst::Num(0x5555_5555, 0x3fd5_5555)],
+            vec![0x0000_0027, 0x0000_012a, 0x0001_004b], // KSTR, KNUM, RET0
+        )
+        .unwrap();
+        let base = ByteCodeDump::from_prototypes(0, b"@\xffchunk.lua".to_vec(), vec![proto]);
+        let listing = dump_listing(&base);
+        assert!(listing.contains("; \"a\\nb\"\n"));
+        assert!(listing.contains("; 0.33333333333333\n"));
+
+        // without base string gets new line and number loses precision
+        let data = assemble_listing(&listing, None).unwrap();
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        let proto = &dump.prototypes()[0];
+        assert_eq!(proto.bytes_from_global_table(0), Some(&b"a\nb"[..]));
+        assert_ne!(proto.num_consts()[0].to_f64(), 1.0 / 3.0);
+
+        // base keeps constants and chunk name byte for byte
+        let data = assemble_listing(&listing, Some(&base)).unwrap();
+        assert_eq!(data, assemble_dump(&base).unwrap());
+
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        assert_eq!(dump.name_bytes(), b"@\xffchunk.lua");
+        assert_eq!(dump.prototypes()[0].num_consts()[0].to_f64(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn assemble_invalid_listing() {
+        assert!(assemble_listing("0001    RET0     0   1\n", None).is_err());
+        assert!(assemble_listing("-- BYTECODE -- ?:0-0\n0002    RET0     0   1\n", None).is_err());
+        assert!(assemble_listing("-- BYTECODE -- ?:0-0\n0001    NOP      0   1\n", None).is_err());
+        assert!(assemble_listing("-- BYTECODE -- ?:0-0\n0001    UGET     0   0\n", None).is_err());
+    }
+}
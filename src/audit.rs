@@ -0,0 +1,424 @@
+use crate::const_fold::Const;
+use crate::instruction::Instruction;
+use crate::ir::{Expr, Insn, Var};
+use crate::lifting::Lifter;
+use crate::listing::fmt_lua_num;
+use crate::op::Op;
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Sinks reported by default, patterns ending with `.*` match every field of the table
+pub const DEFAULT_SINKS: [&str; 9] = [
+    "os.execute",
+    "io.popen",
+    "loadstring",
+    "load",
+    "dofile",
+    "require",
+    "ffi.cdef",
+    "ffi.C.*",
+    "debug.*",
+];
+
+/// Call of a dangerous function
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// index of the prototype in the dump
+    pub proto: usize,
+    /// 1-based instruction number as in `luajit -bl` listing
+    pub pc: u32,
+    pub line: Option<u32>,
+    /// called function, e.g. `os.execute`
+    pub callee: String,
+    /// sink pattern matching the callee
+    pub sink: String,
+    /// constant arguments as lua literals, `None` for arguments which aren't resolved
+    pub args: Vec<Option<String>>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "proto {} pc {:04}", self.proto, self.pc)?;
+
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+        }
+
+        let args: Vec<&str> = self
+            .args
+            .iter()
+            .map(|arg| arg.as_deref().unwrap_or("?"))
+            .collect();
+
+        write!(f, ": {}({})", self.callee, args.join(", "))
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AuditReport {
+    findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+
+        Ok(())
+    }
+}
+
+// value of variable known from lifted instructions of the current block
+#[derive(Clone)]
+enum Value {
+    Name(String),
+    Const(Const),
+}
+
+fn lua_literal(value: &Const) -> String {
+    match value {
+        Const::Nil => "nil".to_string(),
+        Const::Bool(a) => a.to_string(),
+        Const::Num(a) => fmt_lua_num(*a),
+        Const::Str(a) => format!("\"{}\"", a.escape_debug()),
+    }
+}
+
+struct BlockValues<'a> {
+    proto: &'a ByteCodeProto,
+    values: BTreeMap<u16, Value>,
+}
+
+impl BlockValues<'_> {
+    fn eval(&self, expr: &Expr) -> Option<Value> {
+        match expr {
+            Expr::Var(var) => self.values.get(&var.0).cloned(),
+            Expr::Table([table, key]) => {
+                let Expr::Str(field) = key.as_ref() else {
+                    return None;
+                };
+
+                match table.as_ref() {
+                    Expr::GlobalTable => Some(Value::Name(field.clone())),
+                    Expr::Var(var) => match self.values.get(&var.0)? {
+                        Value::Name(object) => Some(Value::Name(format!("{}.{}", object, field))),
+                        Value::Const(_) => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => Const::from_expr(expr, self.proto.num_consts()).map(Value::Const),
+        }
+    }
+
+    fn name(&self, expr: &Expr) -> Option<String> {
+        match self.eval(expr)? {
+            Value::Name(name) => Some(name),
+            Value::Const(_) => None,
+        }
+    }
+
+    fn literal(&self, expr: &Expr) -> Option<String> {
+        match self.eval(expr)? {
+            Value::Const(value) => Some(lua_literal(&value)),
+            Value::Name(_) => None,
+        }
+    }
+
+    fn set(&mut self, vars: &[Var], value: Option<Value>) {
+        for var in vars {
+            self.values.remove(&var.0);
+        }
+
+        if let ([var], Some(value)) = (vars, value) {
+            self.values.insert(var.0, value);
+        }
+    }
+
+    // calls and varargs can write any number of slots above the base
+    fn clobber_from(&mut self, base: u16) {
+        self.values.split_off(&base);
+    }
+}
+
+/// Searches lifted IR for calls of dangerous functions like `os.execute` or `loadstring`.
+/// Callees are resolved by global and field names inside of basic blocks.
+pub struct Auditor {
+    sinks: Vec<String>,
+}
+
+impl Default for Auditor {
+    fn default() -> Self {
+        Self {
+            sinks: DEFAULT_SINKS.iter().map(|sink| sink.to_string()).collect(),
+        }
+    }
+}
+
+impl Auditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds sink, e.g. `lib.sys.exec` or `lib.sys.*`
+    pub fn sink(mut self, pattern: &str) -> Self {
+        self.sinks.push(pattern.to_string());
+        self
+    }
+
+    pub fn sinks(&self) -> &[String] {
+        &self.sinks
+    }
+
+    fn matching_sink(&self, callee: &str) -> Option<&str> {
+        self.sinks
+            .iter()
+            .find(|sink| match sink.strip_suffix('*') {
+                Some(prefix) => callee.starts_with(prefix) && callee.len() > prefix.len(),
+                None => callee == sink.as_str(),
+            })
+            .map(|sink| sink.as_str())
+    }
+
+    pub fn audit(&self, dump: &ByteCodeDump) -> Result<AuditReport, DecompileError> {
+        let mut report = AuditReport::default();
+
+        for (proto_idx, proto) in dump.prototypes().iter().enumerate() {
+            self.audit_proto(proto_idx, proto, &mut report.findings)?;
+        }
+
+        Ok(report)
+    }
+
+    fn audit_proto(
+        &self,
+        proto_idx: usize,
+        proto: &ByteCodeProto,
+        findings: &mut Vec<Finding>,
+    ) -> Result<(), DecompileError> {
+        let lifted = Lifter::new().analyze_bc_proto(proto)?;
+
+        for (block_idx, basic_block) in proto.basic_block_graph_ref().iter_node_weights() {
+            // lifted calls don't keep their pc, they are matched with call instructions in order
+            let mut call_pcs = Instruction::decode_all(block_idx, basic_block.data())?
+                .into_iter()
+                .filter(|ins| {
+                    matches!(
+                        ins.op,
+                        Op::CALL(..) | Op::CALLM(..) | Op::CALLT(..) | Op::CALLMT(..)
+                    )
+                })
+                .map(|ins| ins.pc);
+
+            let mut values = BlockValues {
+                proto,
+                values: BTreeMap::new(),
+            };
+
+            for insn in lifted.node_weight(block_idx).unwrap().iter_insn() {
+                let (callee, args) = match insn {
+                    Insn::Call(_, args) | Insn::TailCall(args) => match args.split_first() {
+                        Some((callee, args)) => (callee.clone(), args.to_vec()),
+                        None => continue,
+                    },
+                    Insn::Unlifted("CALLMT", _, uses) => match uses.split_first() {
+                        Some((callee, args)) => (
+                            Expr::Var(callee.clone()),
+                            args.iter().map(|var| Expr::Var(var.clone())).collect(),
+                        ),
+                        None => continue,
+                    },
+                    Insn::SetVars(vars, expr) => {
+                        let value = values.eval(expr);
+                        values.set(vars, value);
+                        continue;
+                    }
+                    Insn::Cat(var, _) | Insn::IfCopy(var, _) => {
+                        values.set(std::slice::from_ref(var), None);
+                        continue;
+                    }
+                    Insn::Unlifted(_, defs, _) => {
+                        if let Some(base) = defs.iter().map(|var| var.0).min() {
+                            values.clobber_from(base);
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                let Some(pc) = call_pcs.next() else {
+                    continue;
+                };
+
+                if let Some(name) = values.name(&callee) {
+                    if let Some(sink) = self.matching_sink(&name) {
+                        findings.push(Finding {
+                            proto: proto_idx,
+                            pc: pc + 1,
+                            line: proto.line(pc),
+                            callee: name.clone(),
+                            sink: sink.to_string(),
+                            args: args.iter().map(|arg| values.literal(arg)).collect(),
+                        });
+                    }
+                }
+
+                if let Expr::Var(var) = callee {
+                    values.clobber_from(var.0);
+                }
+            }
+        }
+
+        findings.sort_by_key(|finding| (finding.proto, finding.pc));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_listing;
+    use crate::audit::Auditor;
+    use crate::bytecode_reader::{read_bytecode_dump, write_uleb128};
+    use crate::disasm::{ins_abc, ins_ad};
+    use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+
+    // os.execute("cat /etc/version"); lib.sys.exec(x, 5)
+    // if x then debug.sethook() end; print("ok"); return loadstring(x)
+    const LISTING: &str = "-- BYTECODE -- ?:0-0\n\
+        0001    GGET     0   0      ; \"os\"\n\
+        0002    TGETS    0   0   1  ; \"execute\"\n\
+        0003    KSTR     1   2      ; \"cat /etc/version\"\n\
+        0004    CALL     0   1   2\n\
+        0005    GGET     0   3      ; \"lib\"\n\
+        0006    TGETS    0   0   4  ; \"sys\"\n\
+        0007    TGETS    0   0   5  ; \"exec\"\n\
+        0008    GGET     1   6      ; \"x\"\n\
+        0009    KSHORT   2   5\n\
+        0010    CALL     0   1   3\n\
+        0011    GGET     0   6      ; \"x\"\n\
+        0012    ISF          0\n\
+        0013    JMP      1 => 0017\n\
+        0014    GGET     0   7      ; \"debug\"\n\
+        0015    TGETS    0   0   8  ; \"sethook\"\n\
+        0016    CALL     0   1   1\n\
+        0017 => GGET     0   9      ; \"print\"\n\
+        0018    KSTR     1   10     ; \"ok\"\n\
+        0019    CALL     0   1   2\n\
+        0020    GGET     0   11     ; \"loadstring\"\n\
+        0021    GGET     1   6      ; \"x\"\n\
+        0022    CALLT    0   2\n\
+        \n";
+
+    #[test]
+    fn audit_dangerous_calls() {
+        let data = assemble_listing(LISTING, None).unwrap();
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+
+        let report = Auditor::new().audit(&dump).unwrap();
+        let found: Vec<String> = report.findings().iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            found,
+            vec![
+                "proto 0 pc 0004: os.execute(\"cat /etc/version\")",
+                "proto 0 pc 0016: debug.sethook()",
+                "proto 0 pc 0022: loadstring(?)",
+            ]
+        );
+        assert_eq!(report.findings()[1].sink, "debug.*");
+
+        let report = Auditor::new().sink("lib.sys.*").audit(&dump).unwrap();
+        let exec = &report.findings()[1];
+        assert_eq!(exec.callee, "lib.sys.exec");
+        assert_eq!(exec.args, vec![None, Some("5".to_string())]);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["findings"][0]["pc"], 4);
+        assert_eq!(json["findings"][0]["line"], serde_json::Value::Null);
+    }
+
+    // `@run.lua` with `os.execute("id")` at line 3
+    fn dump_with_lines() -> Vec<u8> {
+        let bc = [
+            ins_ad(0x36, 0, 0),     // GGET 0 "os"
+            ins_abc(0x39, 0, 0, 1), // TGETS 0 0 "execute"
+            ins_ad(0x27, 1, 2),     // KSTR 1 "id"
+            ins_abc(0x42, 0, 1, 2), // CALL 0 1 2
+            ins_ad(0x4b, 0, 1),     // RET0 0 1
+        ];
+        // line offsets of instructions and empty variable info
+        let debug = [0, 0, 1, 2, 2, 0];
+
+        let mut proto = vec![0, 0, 2, 0];
+        write_uleb128(&mut proto, 3).unwrap(); // global constants
+        write_uleb128(&mut proto, 0).unwrap(); // num constants
+        write_uleb128(&mut proto, bc.len() as u32).unwrap();
+        write_uleb128(&mut proto, debug.len() as u32).unwrap();
+        write_uleb128(&mut proto, 1).unwrap(); // first line
+        write_uleb128(&mut proto, 3).unwrap(); // number of lines
+
+        for ins in bc {
+            proto.extend_from_slice(&ins.to_le_bytes());
+        }
+
+        // global constants are indexed from the end
+        for str in ["id", "execute", "os"] {
+            write_uleb128(&mut proto, 5 + str.len() as u32).unwrap();
+            proto.extend_from_slice(str.as_bytes());
+        }
+        proto.extend_from_slice(&debug);
+
+        let mut dump = vec![0x1b, 0x4c, 0x4a, 2, 0];
+        write_uleb128(&mut dump, 8).unwrap();
+        dump.extend_from_slice(b"@run.lua");
+        write_uleb128(&mut dump, proto.len() as u32).unwrap();
+        dump.extend_from_slice(&proto);
+        dump.push(0);
+
+        dump
+    }
+
+    #[test]
+    fn audit_reports_lines() {
+        let dump = read_bytecode_dump(&mut dump_with_lines().as_slice()).unwrap();
+
+        let report = Auditor::new().audit(&dump).unwrap();
+        let found: Vec<String> = report.findings().iter().map(|f| f.to_string()).collect();
+        assert_eq!(found, vec!["proto 0 pc 0004 line 3: os.execute(\"id\")"]);
+        assert_eq!(report.findings()[0].line, Some(3));
+    }
+
+    #[test]
+    fn bad_const_index_is_error() {
+        // KSTR 0 5 without string constants, RET0 0 1
+        let proto = ByteCodeProto::from_parts(
+            0,
+            0,
+            1,
+            vec![],
+            vec![],
+            vec![],
+            vec![0x0005_0027, 0x0001_004b],
+        )
+        .unwrap();
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![proto]);
+
+        assert!(matches!(
+            Auditor::new().audit(&dump),
+            Err(DecompileError::InvalidConstIndex)
+        ));
+    }
+}
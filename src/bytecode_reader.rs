@@ -1,12 +1,21 @@
-use std::io::Read;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
 
 use crate::disasm::disasm;
 use crate::error::DecompileError;
+use crate::opcode_map::OpcodeMap;
 use crate::Graph;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use thiserror::Error;
 
-use crate::resolver::{resolve_basic_blocks, Block, BranchKind};
+use crate::resolver::{
+    add_unreachable_blocks, find_unreachable_ranges, resolve_basic_blocks, BasicBlockFormatter,
+    Block, BranchKind,
+};
 
 // byte code header constants
 pub const BC_HEAD1: u8 = 0x1b;
@@ -38,8 +47,17 @@ pub const TABLE_ENTRY_TYPE_INT: u32 = 3;
 pub const TABLE_ENTRY_TYPE_NUM: u32 = 4;
 pub const TABLE_ENTRY_TYPE_STR: u32 = 5;
 
+// prototype flags
+pub const PROTO_CHILD: u8 = 0x01;
+pub const PROTO_VARARG: u8 = 0x02;
+pub const PROTO_FFI: u8 = 0x04;
+
+// up value flags, local up values keep slot of parent prototype in low bits
+pub const PROTO_UV_LOCAL: u16 = 0x8000;
+pub const PROTO_UV_IMMUTABLE: u16 = 0x4000;
+
 // lua prototype aka function
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ByteCodeProto {
     flags: u8,
     num_params: u8,
@@ -48,13 +66,18 @@ pub struct ByteCodeProto {
     size_global_consts: u32,
     size_num_consts: u32,
     size_bc: u32,
+    size_debug: u32,
+    first_line: u32,
+    num_lines: u32,
 
     basic_block_graph: Graph<Block, BranchKind>,
+    unreachable_ranges: Vec<Range<u32>>,
 
     bc_raw: Vec<u32>,
     up_values: Vec<u16>,
     global_consts: Vec<GlobalConst>,
     num_consts: Vec<NumConst>,
+    debug_info: Vec<u8>,
 }
 
 impl ByteCodeProto {
@@ -67,58 +90,284 @@ impl ByteCodeProto {
             size_global_consts: 0,
             size_num_consts: 0,
             size_bc: 0,
+            size_debug: 0,
+            first_line: 0,
+            num_lines: 0,
             basic_block_graph: Graph::new(),
+            unreachable_ranges: vec![],
             bc_raw: vec![],
             up_values: vec![],
             global_consts: vec![],
             num_consts: vec![],
+            debug_info: vec![],
+        }
+    }
+
+    /// Creates prototype without debug info, basic block graph is resolved from bytecode
+    pub fn from_parts(
+        flags: u8,
+        num_params: u8,
+        frame_size: u8,
+        up_values: Vec<u16>,
+        global_consts: Vec<GlobalConst>,
+        num_consts: Vec<NumConst>,
+        bc_raw: Vec<u32>,
+    ) -> Result<Self, DecompileError> {
+        let mut bc_proto = ByteCodeProto::new();
+
+        bc_proto.flags = flags;
+        bc_proto.num_params = num_params;
+        bc_proto.frame_size = frame_size;
+        bc_proto.size_up_values = up_values.len() as u8;
+        bc_proto.size_global_consts = global_consts.len() as u32;
+        bc_proto.size_num_consts = num_consts.len() as u32;
+        bc_proto.up_values = up_values;
+        bc_proto.global_consts = global_consts;
+        bc_proto.num_consts = num_consts;
+
+        bc_proto.set_bc_raw(bc_raw)?;
+
+        Ok(bc_proto)
+    }
+
+    pub fn bc_raw(&self) -> &[u32] {
+        &self.bc_raw
+    }
+
+    /// Replaces prototype bytecode and resolves basic blocks again. Debug info is dropped
+    /// when instruction count is changed cause line info doesn't match anymore.
+    pub fn set_bc_raw(&mut self, bc_raw: Vec<u32>) -> Result<(), DecompileError> {
+        if bc_raw.len() != self.bc_raw.len() {
+            self.size_debug = 0;
+            self.first_line = 0;
+            self.num_lines = 0;
+            self.debug_info.clear();
+        }
+
+        self.size_bc = bc_raw.len() as u32;
+        self.bc_raw = bc_raw;
+
+        self.basic_block_graph = resolve_basic_blocks(&self.bc_raw[..])?;
+        self.unreachable_ranges = find_unreachable_ranges(&self.bc_raw[..], &self.basic_block_graph);
+
+        Ok(())
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    pub fn num_params(&self) -> u8 {
+        self.num_params
+    }
+
+    pub fn frame_size(&self) -> u8 {
+        self.frame_size
+    }
+
+    pub fn up_values(&self) -> &[u16] {
+        &self.up_values
+    }
+
+    pub fn global_consts(&self) -> &[GlobalConst] {
+        &self.global_consts
+    }
+
+    pub fn num_consts(&self) -> &[NumConst] {
+        &self.num_consts
+    }
+
+    /// First line of the prototype, 0 when debug info is stripped
+    pub fn first_line(&self) -> u32 {
+        self.first_line
+    }
+
+    pub fn num_lines(&self) -> u32 {
+        self.num_lines
+    }
+
+    // size of the line offset, line info starts debug info with an offset per instruction
+    fn line_info_width(&self) -> usize {
+        match self.num_lines {
+            0..=0xff => 1,
+            0x100..=0xffff => 2,
+            _ => 4,
         }
     }
 
+    /// Source line of the instruction, `None` when debug info is stripped
+    pub fn line(&self, pc: u32) -> Option<u32> {
+        let width = self.line_info_width();
+
+        if pc as usize >= self.bc_raw.len() {
+            return None;
+        }
+
+        let start = pc as usize * width;
+        let bytes = self.debug_info.get(start..start + width)?;
+        let offset = bytes
+            .iter()
+            .rev()
+            .fold(0u32, |offset, &byte| offset << 8 | byte as u32);
+
+        Some(self.first_line + offset)
+    }
+
+    /// Name of the up value, `None` when debug info is stripped. Zero terminated names follow
+    /// line info in order of up values.
+    pub fn up_value_name(&self, idx: u16) -> Option<String> {
+        if self.debug_info.is_empty() || idx as usize >= self.up_values.len() {
+            return None;
+        }
+
+        let start = self.bc_raw.len() * self.line_info_width();
+        let name = self.debug_info.get(start..)?.split(|&byte| byte == 0).nth(idx as usize)?;
+
+        Some(String::from_utf8_lossy(name).to_string())
+    }
+
     pub fn basic_block_graph_ref(&self) -> &Graph<Block, BranchKind> {
         &self.basic_block_graph
     }
 
-    pub fn str_from_global_table(&self, idx: u16) -> Option<&String> {
-        let size = self.size_global_consts;
+    /// Instruction ranges which can't be reached from the prototype entry
+    pub fn unreachable_ranges(&self) -> &[Range<u32>] {
+        &self.unreachable_ranges
+    }
 
-        let idx = (size - idx as u32 - 1) as usize;
+    /// Adds unreachable instruction ranges into basic block graph as disconnected nodes, ranges
+    /// are still reported as unreachable. Calling it again doesn't change the graph.
+    pub fn keep_unreachable_blocks(&mut self) {
+        add_unreachable_blocks(
+            &mut self.basic_block_graph,
+            &self.bc_raw,
+            &self.unreachable_ranges,
+        );
+    }
+
+    pub fn str_from_global_table(&self, idx: u16) -> Option<String> {
+        self.bytes_from_global_table(idx)
+            .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+    }
+
+    /// Returns dump index of child prototype referenced by `FNEW` constant
+    pub fn child_from_global_table(&self, idx: u16) -> Option<usize> {
+        let idx = (self.size_global_consts as usize).checked_sub(idx as usize + 1)?;
 
-        if let Some(global_const) = self.global_consts.get(idx) {
-            return match global_const {
-                GlobalConst::Str(str) => Some(str),
-                _ => None,
-            };
+        match self.global_consts.get(idx)? {
+            GlobalConst::ProtoChild(child) => Some(*child),
+            _ => None,
         }
+    }
+
+    /// Returns raw bytes of string constant, lua strings aren't required to be valid UTF-8
+    pub fn bytes_from_global_table(&self, idx: u16) -> Option<&[u8]> {
+        let idx = self.size_global_consts.checked_sub(idx as u32 + 1)? as usize;
 
-        None
+        match self.global_consts.get(idx)? {
+            GlobalConst::Str(str) => Some(str),
+            _ => None,
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct ByteCodeDump<'a> {
+pub struct ByteCodeDump {
     magic: [u8; 3],
     version: u8,
     flags: u32,
-    name: &'a str,
+    name: Vec<u8>,
 
     prototypes: Vec<ByteCodeProto>,
 }
 
-impl ByteCodeDump<'_> {
+impl ByteCodeDump {
     pub fn new() -> Self {
         ByteCodeDump {
             magic: [0, 0, 0],
             version: 0,
             flags: 0,
-            name: "",
+            name: vec![],
             prototypes: vec![],
         }
     }
 
+    /// Creates dump from prototypes, children have to be placed before their parents
+    pub fn from_prototypes(flags: u32, name: Vec<u8>, prototypes: Vec<ByteCodeProto>) -> Self {
+        ByteCodeDump {
+            magic: [BC_HEAD1, BC_HEAD2, BC_HEAD3],
+            version: BC_VERSION,
+            flags,
+            name,
+            prototypes,
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Chunk name, empty when debug info is stripped
+    pub fn name(&self) -> Cow<str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    /// Raw bytes of chunk name, it isn't required to be valid UTF-8
+    pub fn name_bytes(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Serialises dump back into binary form, unmodified dump is written byte for byte
+    pub fn write_to<T: Write>(&self, data: &mut T) -> Result<(), DecompileError> {
+        write_bytecode_dump(data, self)
+    }
+
     pub fn prototypes(&self) -> &Vec<ByteCodeProto> {
         &self.prototypes
     }
+
+    /// Slots of prototype which are captured as up values by its child closures
+    pub fn captured_slots(&self, proto_idx: usize) -> BTreeSet<u16> {
+        let mut slots = BTreeSet::new();
+
+        for global_const in &self.prototypes[proto_idx].global_consts {
+            if let GlobalConst::ProtoChild(child) = global_const {
+                slots.extend(
+                    self.prototypes[*child]
+                        .up_values
+                        .iter()
+                        .filter(|&&uv| uv & PROTO_UV_LOCAL != 0)
+                        .map(|&uv| uv & !(PROTO_UV_LOCAL | PROTO_UV_IMMUTABLE)),
+                );
+            }
+        }
+
+        slots
+    }
+
+    pub fn prototypes_mut(&mut self) -> &mut Vec<ByteCodeProto> {
+        &mut self.prototypes
+    }
+
+    /// Returns (prototype index, instruction range) for every dead code range in the dump
+    pub fn unreachable_ranges(&self) -> impl Iterator<Item = (usize, &Range<u32>)> {
+        self.prototypes
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, proto)| proto.unreachable_ranges.iter().map(move |range| (idx, range)))
+    }
+
+    /// Writes basic block graph of every prototype into `dir` as `proto_<index>.dot`
+    pub fn write_dot_files(&self, dir: &Path) -> Result<(), DecompileError> {
+        fs::create_dir_all(dir)?;
+
+        for (idx, proto) in self.prototypes.iter().enumerate() {
+            let dot = proto.basic_block_graph.to_dot(&BasicBlockFormatter);
+            fs::write(dir.join(format!("proto_{}.dot", idx)), dot)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -133,35 +382,56 @@ pub enum LuaValue {
     ProtoChild,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum GlobalConst {
-    ProtoChild,
+    /// index of the child prototype in the dump
+    ProtoChild(usize),
     Table(ConstTable),
-    Num(u32, u32),
+    I64(u32, u32),
+    U64(u32, u32),
     Complex(u32, u32, u32, u32),
-    Str(String),
+    Str(Vec<u8>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NumConst {
     Int(u32),
     Num(u32, u32),
 }
 
-#[derive(Debug)]
+impl NumConst {
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            NumConst::Int(val) => val as i32 as f64,
+            NumConst::Num(lo, hi) => f64::from_bits((hi as u64) << 32 | lo as u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ConstTable {
     array: Vec<ConstTableVal>,
     hash: Vec<(ConstTableVal, ConstTableVal)>,
 }
 
-#[derive(Debug)]
+impl ConstTable {
+    pub fn array(&self) -> &[ConstTableVal] {
+        &self.array
+    }
+
+    pub fn hash(&self) -> &[(ConstTableVal, ConstTableVal)] {
+        &self.hash
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum ConstTableVal {
     Nil,
     True,
     False,
     Int(u32),
     Num(u32, u32),
-    String(String),
+    String(Vec<u8>),
 }
 
 pub fn read_uleb128<T: Read>(data: &mut T) -> Result<u32, DecompileError> {
@@ -214,6 +484,18 @@ pub fn read_uleb128_33<T: Read>(data: &mut T) -> Result<(u32, u8), DecompileErro
     Ok((v, tmp))
 }
 
+// reads exactly `len` bytes, buffer grows with data read so bogus length can't exhaust memory
+fn read_bytes<T: Read>(data: &mut T, len: usize) -> Result<Vec<u8>, DecompileError> {
+    let mut buf = vec![];
+    data.take(len as u64).read_to_end(&mut buf)?;
+
+    if buf.len() != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Ok(buf)
+}
+
 pub fn read_header<T: ReadBytesExt>(
     file: &mut T,
     bc_dump: &mut ByteCodeDump,
@@ -238,15 +520,11 @@ pub fn read_header<T: ReadBytesExt>(
         return Err(DecompileError::InvalidHeaderBytes("Invalid header flags."));
     }
 
-    // todo: unknown logic with chunk string
-    // if flags & BC_F_STRIP == 1 {
-    //     // ...
-    //     Ok(0)
-    // } else {
-    //     let len = read_uleb128(file)?;
-    //     println!("len {:x}", len);
-    //     Ok(len)
-    // }
+    // chunk name is dumped only with debug info
+    if flags & BC_F_STRIP == 0 {
+        let len = read_uleb128(file)?;
+        bc_dump.name = read_bytes(file, len as usize)?;
+    }
 
     bc_dump.flags = flags;
     bc_dump.magic = [arr[0], arr[1], arr[2]];
@@ -258,6 +536,8 @@ pub fn read_header<T: ReadBytesExt>(
 pub fn read_prototype<T: Read>(
     data: &mut T,
     bc_proto: &mut ByteCodeProto,
+    strip: bool,
+    opcodes: Option<&OpcodeMap>,
 ) -> Result<(), DecompileError> {
     // read prototype header
     let mut arr = [0u8; 4];
@@ -275,16 +555,28 @@ pub fn read_prototype<T: Read>(
     // println!("flags 0x{:x} num_params 0x{:x} frame_size 0x{:x} size_uv 0x{:x} size_kgc 0x{:x} size_kn 0x{:x} size_bc 0x{:x}",
     //          bc_proto.flags, bc_proto.num_params, bc_proto.frame_size, bc_proto.size_up_values, bc_proto.size_global_consts, bc_proto.size_num_consts, bc_proto.size_bc);
 
-    // todo: check debug flags and collect debug data if it exists
+    if !strip {
+        bc_proto.size_debug = read_uleb128(data)?;
+
+        if bc_proto.size_debug > 0 {
+            bc_proto.first_line = read_uleb128(data)?;
+            bc_proto.num_lines = read_uleb128(data)?;
+        }
+    }
 
     // read bytecode instructions and up values
-    read_prototype_bytecode(data, bc_proto)?;
+    read_prototype_bytecode(data, bc_proto, opcodes)?;
     read_prototype_up_values(data, bc_proto)?;
 
     // read consts
     read_prototype_global_constants(data, bc_proto)?;
     read_prototype_num_constants(data, bc_proto)?;
 
+    // line info, up value and variable names are kept raw
+    if bc_proto.size_debug > 0 {
+        bc_proto.debug_info = read_bytes(data, bc_proto.size_debug as usize)?;
+    }
+
     Ok(())
 }
 
@@ -304,27 +596,40 @@ pub fn read_prototype_up_values<T: Read>(
     Ok(())
 }
 
+/// Reads instructions translating them to the standard opcode numbering. Without opcode map
+/// instructions are kept as they are and basic blocks aren't resolved.
 pub fn read_prototype_bytecode<T: Read>(
     data: &mut T,
     bc_proto: &mut ByteCodeProto,
+    opcodes: Option<&OpcodeMap>,
 ) -> Result<(), DecompileError> {
     if bc_proto.size_bc > 0 {
-        let mut ins_buff: Vec<u32> = Vec::with_capacity(bc_proto.size_bc as usize);
-
-        unsafe {
-            ins_buff.set_len(bc_proto.size_bc as usize);
+        let mut ins_buff: Vec<u32> = read_bytes(data, bc_proto.size_bc as usize * 4)?
+            .chunks_exact(4)
+            .map(LittleEndian::read_u32)
+            .collect();
+
+        if let Some(opcodes) = opcodes.filter(|opcodes| !opcodes.is_identity()) {
+            for ins in ins_buff.iter_mut() {
+                *ins = opcodes.decode(*ins)?;
+            }
         }
-        data.read_u32_into::<LittleEndian>(&mut ins_buff[..])?;
 
         bc_proto.bc_raw = ins_buff;
     }
 
+    if opcodes.is_none() {
+        return Ok(());
+    }
+
     // for &ins in &bc_proto.bc_raw {
     //     println!("{:?}", disasm(ins)?);
     // }
 
     // analyze control flow graph
     bc_proto.basic_block_graph = resolve_basic_blocks(&bc_proto.bc_raw[..])?;
+    bc_proto.unreachable_ranges =
+        find_unreachable_ranges(&bc_proto.bc_raw[..], &bc_proto.basic_block_graph);
 
     Ok(())
 }
@@ -337,9 +642,10 @@ pub fn read_prototype_const_table<T: Read>(
     let n_array = read_uleb128(data)?;
     let n_hash = read_uleb128(data)?;
 
+    // counts aren't trusted, vectors grow with items actually read
     let mut ktab = ConstTable {
-        array: Vec::with_capacity(n_array as usize),
-        hash: Vec::with_capacity(n_hash as usize),
+        array: Vec::new(),
+        hash: Vec::new(),
     };
 
     if n_array > 0 {
@@ -371,11 +677,15 @@ pub fn read_prototype_global_constants<T: Read>(
         let tp = read_uleb128(data)?;
 
         match tp {
-            GC_TYPE_PROTO_CHILD => bc_proto.global_consts.push(GlobalConst::ProtoChild),
+            // child index is linked after prototype is read
+            GC_TYPE_PROTO_CHILD => bc_proto.global_consts.push(GlobalConst::ProtoChild(0)),
             GC_TYPE_TABLE => read_prototype_const_table(data, bc_proto)?,
-            GC_TYPE_I64 | GC_TYPE_U64 => bc_proto
+            GC_TYPE_I64 => bc_proto
+                .global_consts
+                .push(GlobalConst::I64(read_uleb128(data)?, read_uleb128(data)?)),
+            GC_TYPE_U64 => bc_proto
                 .global_consts
-                .push(GlobalConst::Num(read_uleb128(data)?, read_uleb128(data)?)),
+                .push(GlobalConst::U64(read_uleb128(data)?, read_uleb128(data)?)),
             GC_TYPE_COMPLEX => bc_proto.global_consts.push(GlobalConst::Complex(
                 read_uleb128(data)?,
                 read_uleb128(data)?,
@@ -385,12 +695,9 @@ pub fn read_prototype_global_constants<T: Read>(
             _ => {
                 // const string type
                 let len = tp - GC_TYPE_STR;
-                let mut str = vec![0u8; len as usize];
-                data.read_exact(&mut str)?;
+                let str = read_bytes(data, len as usize)?;
 
-                bc_proto.global_consts.push(GlobalConst::Str(
-                    String::from_utf8_lossy(str.as_slice()).to_string(),
-                ));
+                bc_proto.global_consts.push(GlobalConst::Str(str));
             }
         }
     }
@@ -412,12 +719,9 @@ pub fn read_prototype_const_table_val<T: Read>(
         TABLE_ENTRY_TYPE_NUM => Ok(ConstTableVal::Num(read_uleb128(data)?, read_uleb128(data)?)),
         _ => {
             let len = tp - TABLE_ENTRY_TYPE_STR;
-            let mut str = vec![0u8; len as usize];
-            data.read_exact(&mut str)?;
+            let str = read_bytes(data, len as usize)?;
 
-            Ok(ConstTableVal::String(
-                String::from_utf8_lossy(str.as_slice()).to_string(),
-            ))
+            Ok(ConstTableVal::String(str))
         }
     }
 }
@@ -440,32 +744,63 @@ pub fn read_prototype_num_constants<T: Read>(
 }
 
 pub fn read_bytecode_dump<T: Read>(data: &mut T) -> Result<ByteCodeDump, DecompileError> {
+    read_bytecode_dump_with_opcodes(data, &OpcodeMap::default())
+}
+
+/// Reads dump of LuaJIT build with custom opcode numbering
+pub fn read_bytecode_dump_with_opcodes<T: Read>(
+    data: &mut T,
+    opcodes: &OpcodeMap,
+) -> Result<ByteCodeDump, DecompileError> {
+    read_dump(data, Some(opcodes))
+}
+
+/// Reads dump keeping instructions undecoded, basic block graphs are left empty. It is used
+/// when opcode numbering of the build isn't known yet.
+pub fn read_raw_bytecode_dump<T: Read>(data: &mut T) -> Result<ByteCodeDump, DecompileError> {
+    read_dump(data, None)
+}
+
+fn read_dump<T: Read>(
+    data: &mut T,
+    opcodes: Option<&OpcodeMap>,
+) -> Result<ByteCodeDump, DecompileError> {
     let mut bc_dump = ByteCodeDump::new();
 
     // read byte code header
     read_header(data, &mut bc_dump)?;
 
+    // children are dumped before their parent, parent takes them from the top of stack
+    let mut children: Vec<usize> = vec![];
+
     loop {
         // read next prototype len
         if let Ok(proto_len) = read_uleb128(data) {
+            // end of dump, data after it doesn't belong to the dump
             if proto_len == 0 {
-                continue;
+                break;
             }
 
             // println!("Prototype len 0x{:x}", proto_len);
 
             // read prototype data
-            let mut proto_data: Vec<u8> = Vec::with_capacity(proto_len as usize);
-
-            unsafe {
-                proto_data.set_len(proto_len as usize);
-            }
-            data.read_exact(&mut proto_data)?;
-
+            let proto_data = read_bytes(data, proto_len as usize)?;
             let mut proto_data = proto_data.as_slice();
             let mut proto = ByteCodeProto::new();
 
-            read_prototype(&mut proto_data, &mut proto)?;
+            read_prototype(
+                &mut proto_data,
+                &mut proto,
+                bc_dump.flags & BC_F_STRIP != 0,
+                opcodes,
+            )?;
+
+            for global_const in proto.global_consts.iter_mut() {
+                if let GlobalConst::ProtoChild(child) = global_const {
+                    *child = children.pop().ok_or(DecompileError::InvalidProtoChild)?;
+                }
+            }
+            children.push(bc_dump.prototypes.len());
 
             // println!("Prototype object: {:?}", proto);
             bc_dump.prototypes.push(proto);
@@ -476,3 +811,357 @@ pub fn read_bytecode_dump<T: Read>(data: &mut T) -> Result<ByteCodeDump, Decompi
 
     Ok(bc_dump)
 }
+
+pub fn write_uleb128<T: Write>(data: &mut T, mut val: u32) -> Result<(), DecompileError> {
+    while val >= 0x80 {
+        data.write_u8((val & 0x7f) as u8 | 0x80)?;
+        val >>= 7;
+    }
+    data.write_u8(val as u8)?;
+
+    Ok(())
+}
+
+// write 32 bit value as top bits of 33 bit ULEB128, lowest bit is number flag
+pub fn write_uleb128_33<T: Write>(data: &mut T, val: u32, is_num: bool) -> Result<(), DecompileError> {
+    let mut val = (val as u64) << 1 | is_num as u64;
+
+    while val >= 0x80 {
+        data.write_u8((val & 0x7f) as u8 | 0x80)?;
+        val >>= 7;
+    }
+    data.write_u8(val as u8)?;
+
+    Ok(())
+}
+
+// write length prefixed string with type code offset
+fn write_str<T: Write>(data: &mut T, tp: u32, str: &[u8]) -> Result<(), DecompileError> {
+    write_uleb128(data, tp + str.len() as u32)?;
+    data.write_all(str)?;
+
+    Ok(())
+}
+
+pub fn write_header<T: Write>(data: &mut T, bc_dump: &ByteCodeDump) -> Result<(), DecompileError> {
+    data.write_all(&[BC_HEAD1, BC_HEAD2, BC_HEAD3, BC_VERSION])?;
+    write_uleb128(data, bc_dump.flags)?;
+
+    if bc_dump.flags & BC_F_STRIP == 0 {
+        write_uleb128(data, bc_dump.name.len() as u32)?;
+        data.write_all(&bc_dump.name)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_prototype<T: Write>(
+    data: &mut T,
+    bc_proto: &ByteCodeProto,
+    strip: bool,
+    opcodes: &OpcodeMap,
+) -> Result<(), DecompileError> {
+    data.write_all(&[
+        bc_proto.flags,
+        bc_proto.num_params,
+        bc_proto.frame_size,
+        bc_proto.up_values.len() as u8,
+    ])?;
+
+    write_uleb128(data, bc_proto.global_consts.len() as u32)?;
+    write_uleb128(data, bc_proto.num_consts.len() as u32)?;
+    write_uleb128(data, bc_proto.bc_raw.len() as u32)?;
+
+    let size_debug = if strip { 0 } else { bc_proto.debug_info.len() as u32 };
+    if !strip {
+        write_uleb128(data, size_debug)?;
+
+        if size_debug > 0 {
+            write_uleb128(data, bc_proto.first_line)?;
+            write_uleb128(data, bc_proto.num_lines)?;
+        }
+    }
+
+    let encode = !opcodes.is_identity();
+    for &ins in &bc_proto.bc_raw {
+        let ins = if encode { opcodes.encode(ins)? } else { ins };
+        data.write_u32::<LittleEndian>(ins)?;
+    }
+
+    for &uv in &bc_proto.up_values {
+        data.write_u16::<LittleEndian>(uv)?;
+    }
+
+    write_prototype_global_constants(data, bc_proto)?;
+    write_prototype_num_constants(data, bc_proto)?;
+
+    if size_debug > 0 {
+        data.write_all(&bc_proto.debug_info)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_prototype_const_table<T: Write>(
+    data: &mut T,
+    ktab: &ConstTable,
+) -> Result<(), DecompileError> {
+    write_uleb128(data, ktab.array.len() as u32)?;
+    write_uleb128(data, ktab.hash.len() as u32)?;
+
+    for val in &ktab.array {
+        write_prototype_const_table_val(data, val)?;
+    }
+
+    for (key, val) in &ktab.hash {
+        write_prototype_const_table_val(data, key)?;
+        write_prototype_const_table_val(data, val)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_prototype_global_constants<T: Write>(
+    data: &mut T,
+    bc_proto: &ByteCodeProto,
+) -> Result<(), DecompileError> {
+    for global_const in &bc_proto.global_consts {
+        match global_const {
+            GlobalConst::ProtoChild(_) => write_uleb128(data, GC_TYPE_PROTO_CHILD)?,
+            GlobalConst::Table(ktab) => {
+                write_uleb128(data, GC_TYPE_TABLE)?;
+                write_prototype_const_table(data, ktab)?;
+            }
+            GlobalConst::I64(lo, hi) | GlobalConst::U64(lo, hi) => {
+                let tp = match global_const {
+                    GlobalConst::I64(..) => GC_TYPE_I64,
+                    _ => GC_TYPE_U64,
+                };
+
+                write_uleb128(data, tp)?;
+                write_uleb128(data, *lo)?;
+                write_uleb128(data, *hi)?;
+            }
+            GlobalConst::Complex(a, b, c, d) => {
+                write_uleb128(data, GC_TYPE_COMPLEX)?;
+                for &val in &[*a, *b, *c, *d] {
+                    write_uleb128(data, val)?;
+                }
+            }
+            GlobalConst::Str(str) => write_str(data, GC_TYPE_STR, str)?,
+        }
+    }
+
+    Ok(())
+}
+
+// write single key/value of a template table
+pub fn write_prototype_const_table_val<T: Write>(
+    data: &mut T,
+    val: &ConstTableVal,
+) -> Result<(), DecompileError> {
+    match val {
+        ConstTableVal::Nil => write_uleb128(data, TABLE_ENTRY_TYPE_NIL),
+        ConstTableVal::False => write_uleb128(data, TABLE_ENTRY_TYPE_FALSE),
+        ConstTableVal::True => write_uleb128(data, TABLE_ENTRY_TYPE_TRUE),
+        ConstTableVal::Int(val) => {
+            write_uleb128(data, TABLE_ENTRY_TYPE_INT)?;
+            write_uleb128(data, *val)
+        }
+        ConstTableVal::Num(lo, hi) => {
+            write_uleb128(data, TABLE_ENTRY_TYPE_NUM)?;
+            write_uleb128(data, *lo)?;
+            write_uleb128(data, *hi)
+        }
+        ConstTableVal::String(str) => write_str(data, TABLE_ENTRY_TYPE_STR, str),
+    }
+}
+
+pub fn write_prototype_num_constants<T: Write>(
+    data: &mut T,
+    bc_proto: &ByteCodeProto,
+) -> Result<(), DecompileError> {
+    for num_const in &bc_proto.num_consts {
+        match *num_const {
+            NumConst::Int(val) => write_uleb128_33(data, val, false)?,
+            NumConst::Num(lo, hi) => {
+                write_uleb128_33(data, lo, true)?;
+                write_uleb128(data, hi)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_bytecode_dump<T: Write>(data: &mut T, bc_dump: &ByteCodeDump) -> Result<(), DecompileError> {
+    write_bytecode_dump_with_opcodes(data, bc_dump, &OpcodeMap::default())
+}
+
+/// Writes dump for LuaJIT build with custom opcode numbering
+pub fn write_bytecode_dump_with_opcodes<T: Write>(
+    data: &mut T,
+    bc_dump: &ByteCodeDump,
+    opcodes: &OpcodeMap,
+) -> Result<(), DecompileError> {
+    write_header(data, bc_dump)?;
+
+    for proto in &bc_dump.prototypes {
+        // prototype is prefixed with its length
+        let mut proto_data: Vec<u8> = vec![];
+        write_prototype(
+            &mut proto_data,
+            proto,
+            bc_dump.flags & BC_F_STRIP != 0,
+            opcodes,
+        )?;
+
+        write_uleb128(data, proto_data.len() as u32)?;
+        data.write_all(&proto_data)?;
+    }
+
+    // end of dump
+    data.write_u8(0)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_reader::{
+        read_bytecode_dump, read_uleb128, read_uleb128_33, write_bytecode_dump, write_uleb128,
+        write_uleb128_33, ByteCodeDump, ByteCodeProto, ConstTable, ConstTableVal, GlobalConst,
+        NumConst, BC_F_STRIP,
+    };
+
+    #[test]
+    fn uleb128_round_trip() {
+        for val in [0, 1, 0x3f, 0x40, 0x7f, 0x80, 0x3fff, 0x4000, 0x7fff_ffff, 0xffff_ffff] {
+            let mut data = vec![];
+            write_uleb128(&mut data, val).unwrap();
+            assert_eq!(read_uleb128(&mut data.as_slice()).unwrap(), val);
+
+            for is_num in [false, true] {
+                let mut data = vec![];
+                write_uleb128_33(&mut data, val, is_num).unwrap();
+
+                let (read_val, first) = read_uleb128_33(&mut data.as_slice()).unwrap();
+                assert_eq!(read_val, val);
+                assert_eq!(first & 1 == 1, is_num);
+            }
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let ktab = ConstTable {
+            array: vec![ConstTableVal::Nil, ConstTableVal::Int(1), ConstTableVal::Num(0, 0x4004_0000)],
+            hash: vec![(ConstTableVal::String(b"key".to_vec()), ConstTableVal::True)],
+        };
+
+        let proto = ByteCodeProto::from_parts(
+            0,
+            1,
+            2,
+            vec![],
+            vec![GlobalConst::Table(ktab), GlobalConst::Str(b"str".to_vec())],
+            vec![NumConst::Int(-1i32 as u32), NumConst::Num(0, 0x4004_0000)],
+            vec![0x0001_0035, 0x0001_004b], // TDUP 0 1, RET0 0 1
+        )
+        .unwrap();
+
+        let dump = ByteCodeDump::from_prototypes(BC_F_STRIP, vec![], vec![proto]);
+
+        let mut data = vec![];
+        write_bytecode_dump(&mut data, &dump).unwrap();
+
+        let read_dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        let read_proto = &read_dump.prototypes()[0];
+
+        assert_eq!(read_proto.bc_raw(), dump.prototypes()[0].bc_raw());
+        assert_eq!(read_proto.str_from_global_table(0).unwrap(), "str");
+        assert_eq!(read_proto.num_consts()[0].to_f64(), -1.0);
+        assert_eq!(read_proto.num_consts()[1].to_f64(), 2.5);
+
+        let mut rewritten = vec![];
+        write_bytecode_dump(&mut rewritten, &read_dump).unwrap();
+        assert_eq!(rewritten, data);
+    }
+
+    fn proto_bytes(bc: &[u32], kgc: &[u8], kn: &[u8], counts: (u8, u8), debug: &[u8]) -> Vec<u8> {
+        let mut proto = vec![0, 0, 2, 0, counts.0, counts.1, bc.len() as u8];
+        // debug info size, first line and line count
+        proto.extend_from_slice(&[debug.len() as u8, 1, 2]);
+
+        for ins in bc {
+            proto.extend_from_slice(&ins.to_le_bytes());
+        }
+
+        proto.extend_from_slice(kgc);
+        proto.extend_from_slice(kn);
+        proto.extend_from_slice(debug);
+        proto
+    }
+
+    #[test]
+    fn huge_debug_info_size_is_error() {
+        // debug info size of 4 GB, first line and line count, then `RET0 0 1`
+        let mut proto = vec![0, 0, 2, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff, 0x0f, 1, 2];
+        proto.extend_from_slice(&0x0001_004bu32.to_le_bytes());
+
+        let mut data = vec![0x1b, 0x4c, 0x4a, 2, 0, 0, proto.len() as u8];
+        data.extend_from_slice(&proto);
+        data.push(0);
+
+        assert!(read_bytecode_dump(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn write_to_is_byte_exact() {
+        // child prototype with `RET0 0 1` only
+        let child = proto_bytes(&[0x0001_004b], &[], &[], (0, 0), &[1, 0]);
+
+        let kgc = [
+            vec![6, 0xff],                                 // non UTF-8 string
+            vec![1, 1, 1, 2, 8, b'k', b'e', b'y', 3, 7], // { true, key = 7 }
+            vec![2, 1, 0x80, 0x01],                        // int64
+            vec![3, 0xff, 0xff, 0xff, 0xff, 0x0f, 0],      // uint64
+            vec![4, 0, 0, 0, 0x80, 0x80, 0xc0, 0x3f],      // complex
+            vec![0],                                       // child
+        ]
+        .concat();
+        // int 5 and double 2.5
+        let kn = [10, 1, 0x80, 0x80, 0x80, 0x80, 0x04];
+        let parent = proto_bytes(
+            &[0x0000_0033, 0x0004_0135, 0x0005_0227, 0x0001_004b], // FNEW, TDUP, KSTR, RET0
+            &kgc,
+            &kn,
+            (6, 2),
+            &[1, 1, 1, 2, 0],
+        );
+
+        let mut data = vec![0x1b, 0x4c, 0x4a, 2, 0, 9];
+        data.extend_from_slice(b"@test.lua");
+        for proto in [child, parent] {
+            data.push(proto.len() as u8);
+            data.extend_from_slice(&proto);
+        }
+        data.push(0);
+
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        assert_eq!(dump.name(), "@test.lua");
+        assert_eq!(dump.prototypes()[1].bytes_from_global_table(5), Some(&[0xff][..]));
+        assert_eq!(dump.prototypes()[1].bytes_from_global_table(6), None);
+        assert_eq!(dump.prototypes()[1].str_from_global_table(0xffff), None);
+
+        // line offsets follow first line, names of up values and variables follow them
+        let parent = &dump.prototypes()[1];
+        assert_eq!(parent.line(0), Some(2));
+        assert_eq!(parent.line(3), Some(3));
+        assert_eq!(parent.line(4), None);
+
+        let mut written = vec![];
+        dump.write_to(&mut written).unwrap();
+        assert_eq!(written, data);
+    }
+}
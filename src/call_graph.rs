@@ -0,0 +1,484 @@
+use crate::analysis::{Def, ReachingDefinitions};
+use crate::bytecode_reader::{GlobalConst, PROTO_UV_IMMUTABLE, PROTO_UV_LOCAL};
+use crate::graph::{Graph, GraphFormatter};
+use crate::instruction::Instruction;
+use crate::op::Op;
+use crate::resolver::resolve_basic_blocks;
+use crate::xref::{visit_slot_values, SlotValue, SlotValues};
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+use std::collections::BTreeMap;
+
+/// Node of call graph
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CallTarget {
+    /// index of the prototype in the dump
+    Proto(usize),
+    /// function which isn't defined by the dump, e.g. `string.format`
+    External(String),
+}
+
+/// Call sites of one caller calling one target
+#[derive(Debug, Clone)]
+pub struct CallSites {
+    /// 1-based instruction numbers of calls as in `luajit -bl` listing
+    pub pcs: Vec<u32>,
+}
+
+// closure created by `FNEW` at pc, `None` for other definitions
+fn def_closure(proto: &ByteCodeProto, insns: &[Instruction], pc: u32) -> Option<usize> {
+    match insns.get(pc as usize)?.op {
+        Op::FNEW(_, d) => proto.child_from_global_table(d.0),
+        _ => None,
+    }
+}
+
+// slots which all passed definitions assign closure of one prototype
+fn closure_slots(
+    proto: &ByteCodeProto,
+    insns: &[Instruction],
+    defs: impl Iterator<Item = Def>,
+) -> SlotValues {
+    // parameters can reach any point without definition
+    let num_params = proto.num_params() as u16;
+    // calls with multiple results write any slot above their base
+    let multres_base = insns
+        .iter()
+        .filter_map(|ins| match ins.op {
+            Op::CALL(a, b, _)
+            | Op::CALLM(a, b, _)
+            | Op::ITERC(a, b, _)
+            | Op::ITERN(a, b, _)
+            | Op::VARG(a, b, _)
+                if b.0 == 0 =>
+            {
+                Some(a.0)
+            }
+            _ => None,
+        })
+        .min()
+        .unwrap_or(u16::MAX);
+
+    let mut children: BTreeMap<u16, Option<usize>> = BTreeMap::new();
+    for (pc, slot) in defs {
+        let child = def_closure(proto, insns, pc);
+        let def = children.entry(slot).or_insert(child);
+        if *def != child {
+            *def = None;
+        }
+    }
+
+    let mut closures = SlotValues::default();
+    for (slot, child) in children {
+        if let Some(child) = child.filter(|_| (num_params..multres_base).contains(&slot)) {
+            closures.insert(slot, SlotValue::Closure(child));
+        }
+    }
+
+    closures
+}
+
+/// Closures held by local slots of one prototype
+struct LocalClosures {
+    /// slots assigned only by `FNEW` of one closure, up values capturing them are known
+    captured: SlotValues,
+    /// slots known at the start of basic blocks from reaching definitions
+    blocks: BTreeMap<u32, SlotValues>,
+}
+
+impl LocalClosures {
+    fn new(proto: &ByteCodeProto) -> Result<Self, DecompileError> {
+        let insns = Instruction::decode_all(0, proto.bc_raw())?;
+        let all_defs = insns
+            .iter()
+            .flat_map(|ins| ins.defs().into_iter().map(|slot| (ins.pc, slot)));
+        let captured = closure_slots(proto, &insns, all_defs);
+
+        let graph = resolve_basic_blocks(proto.bc_raw())?;
+        let reaching = ReachingDefinitions::analyze(&graph)?;
+        let blocks = reaching
+            .entry
+            .iter()
+            .map(|(&start, defs)| (start, closure_slots(proto, &insns, defs.iter().cloned())))
+            .collect();
+
+        Ok(LocalClosures { captured, blocks })
+    }
+}
+
+/// Closures reachable through up values and local slots
+struct Closures<'a> {
+    dump: &'a ByteCodeDump,
+    /// prototype creating closure of the child
+    parents: BTreeMap<usize, usize>,
+    locals: Vec<LocalClosures>,
+}
+
+impl<'a> Closures<'a> {
+    fn new(dump: &'a ByteCodeDump) -> Result<Self, DecompileError> {
+        let mut parents = BTreeMap::new();
+        let mut locals = vec![];
+
+        for (idx, proto) in dump.prototypes().iter().enumerate() {
+            for global_const in proto.global_consts() {
+                if let GlobalConst::ProtoChild(child) = global_const {
+                    parents.insert(*child, idx);
+                }
+            }
+
+            locals.push(LocalClosures::new(proto)?);
+        }
+
+        Ok(Closures {
+            dump,
+            parents,
+            locals,
+        })
+    }
+
+    // children are dumped before their parents, so the chain of parents ends. Up values
+    // assigned anywhere, e.g. by `USETV` of another closure, aren't resolved
+    fn up_value(&self, proto: usize, uv: u16) -> Option<SlotValue> {
+        let desc = *self.dump.prototypes()[proto].up_values().get(uv as usize)?;
+        if desc & PROTO_UV_IMMUTABLE == 0 {
+            return None;
+        }
+
+        let parent = *self.parents.get(&proto)?;
+        let idx = desc & !(PROTO_UV_LOCAL | PROTO_UV_IMMUTABLE);
+
+        match desc & PROTO_UV_LOCAL != 0 {
+            true => self.locals[parent].captured.get(idx).cloned(),
+            false => self.up_value(parent, idx),
+        }
+    }
+
+    fn visit<F>(&self, proto: usize, visit: F) -> Result<(), DecompileError>
+    where
+        F: FnMut(&Instruction, &SlotValues),
+    {
+        let blocks = &self.locals[proto].blocks;
+
+        visit_slot_values(
+            &self.dump.prototypes()[proto],
+            |pc| blocks.get(&pc).cloned().unwrap_or_default(),
+            |uv| self.up_value(proto, uv),
+            visit,
+        )
+    }
+}
+
+/// Calls between prototypes of a dump. Callee is resolved when it is a closure created in
+/// the same block, a local reached only by `FNEW` of one closure, an up value assigned by one
+/// `FNEW` only, or a global or field
+/// assigned one closure in the whole dump. Other named callees are kept as external targets,
+/// calls of unknown values are skipped.
+pub struct CallGraph {
+    graph: Graph<CallTarget, CallSites>,
+    /// globals and fields holding closure of the prototype
+    names: BTreeMap<usize, Vec<String>>,
+    main: usize,
+}
+
+impl CallGraph {
+    pub fn build(dump: &ByteCodeDump) -> Result<Self, DecompileError> {
+        let closures = Closures::new(dump)?;
+        let proto_count = dump.prototypes().len();
+
+        // closures assigned to globals and fields, `None` when it isn't the only value
+        let mut named: BTreeMap<String, Option<usize>> = BTreeMap::new();
+        for proto_idx in 0..proto_count {
+            let proto = &dump.prototypes()[proto_idx];
+
+            closures.visit(proto_idx, |ins, values| {
+                let (slot, name) = match ins.op {
+                    Op::GSET(a, d) => (a.0, proto.str_from_global_table(d.0)),
+                    Op::TSETS(a, b, c) => (
+                        a.0,
+                        values
+                            .name(b.0)
+                            .zip(proto.str_from_global_table(c.0))
+                            .map(|(object, field)| format!("{}.{}", object, field)),
+                    ),
+                    _ => return,
+                };
+
+                if let Some(name) = name {
+                    let child = match values.get(slot) {
+                        Some(SlotValue::Closure(child)) => Some(*child),
+                        _ => None,
+                    };
+
+                    let def = named.entry(name).or_insert(child);
+                    if *def != child {
+                        *def = None;
+                    }
+                }
+            })?;
+        }
+
+        let mut calls: BTreeMap<(usize, CallTarget), Vec<u32>> = BTreeMap::new();
+        for proto_idx in 0..proto_count {
+            closures.visit(proto_idx, |ins, values| {
+                let slot = match ins.op {
+                    Op::CALL(a, ..) | Op::CALLM(a, ..) | Op::CALLT(a, _) | Op::CALLMT(a, _) => a.0,
+                    _ => return,
+                };
+
+                let target = match values.get(slot) {
+                    Some(SlotValue::Closure(child)) => CallTarget::Proto(*child),
+                    Some(SlotValue::Name(name)) => match named.get(name) {
+                        Some(Some(child)) => CallTarget::Proto(*child),
+                        _ => CallTarget::External(name.clone()),
+                    },
+                    None => return,
+                };
+
+                calls
+                    .entry((proto_idx, target))
+                    .or_default()
+                    .push(ins.pc + 1);
+            })?;
+        }
+
+        let mut graph = Graph::new();
+        for proto_idx in 0..proto_count {
+            graph.add_node(proto_idx as u32, CallTarget::Proto(proto_idx));
+        }
+
+        let mut externals: BTreeMap<String, u32> = BTreeMap::new();
+        for ((caller, target), pcs) in calls {
+            let to = match &target {
+                CallTarget::Proto(callee) => *callee as u32,
+                CallTarget::External(name) => match externals.get(name) {
+                    Some(&idx) => idx,
+                    None => {
+                        let idx = (proto_count + externals.len()) as u32;
+                        externals.insert(name.clone(), idx);
+                        graph.add_node(idx, target);
+                        idx
+                    }
+                },
+            };
+
+            graph.add_edge(CallSites { pcs }, caller as u32, to);
+        }
+
+        let mut names: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (name, def) in named {
+            if let Some(child) = def {
+                names.entry(child).or_default().push(name);
+            }
+        }
+
+        Ok(CallGraph {
+            graph,
+            names,
+            main: proto_count.saturating_sub(1),
+        })
+    }
+
+    /// Graph of prototypes and external targets, prototype nodes have indexes of prototypes
+    pub fn graph(&self) -> &Graph<CallTarget, CallSites> {
+        &self.graph
+    }
+
+    /// Globals and fields holding closure of the prototype
+    pub fn names(&self, proto: usize) -> &[String] {
+        self.names.get(&proto).map_or(&[], |names| names)
+    }
+
+    /// Targets called by the prototype with their call sites
+    pub fn callees(&self, proto: usize) -> Vec<(&CallTarget, &CallSites)> {
+        let mut callees: Vec<(&CallTarget, &CallSites)> = self
+            .graph
+            .outputs(proto as u32)
+            .map(|edge_idx| {
+                let edge = self.graph.edge(edge_idx).unwrap();
+                let target = self.graph.node_weight(edge.to()).unwrap();
+                (target, edge.weight())
+            })
+            .collect();
+
+        // outputs are iterated from the last added edge
+        callees.reverse();
+        callees
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.graph.to_dot(&CallGraphFormatter { call_graph: self })
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.graph
+            .to_mermaid(&CallGraphFormatter { call_graph: self })
+    }
+}
+
+struct CallGraphFormatter<'a> {
+    call_graph: &'a CallGraph,
+}
+
+impl GraphFormatter<CallTarget, CallSites> for CallGraphFormatter<'_> {
+    fn node_label(&self, _index: u32, node: &CallTarget) -> String {
+        match node {
+            CallTarget::Proto(idx) => {
+                let mut lines = vec![match *idx == self.call_graph.main {
+                    true => "main".to_string(),
+                    false => format!("proto {}", idx),
+                }];
+                lines.extend(self.call_graph.names(*idx).iter().cloned());
+                lines.join("\n")
+            }
+            CallTarget::External(name) => name.clone(),
+        }
+    }
+
+    fn edge_label(&self, edge: &CallSites) -> String {
+        let pcs: Vec<String> = edge.pcs.iter().map(|pc| format!("{:04}", pc)).collect();
+        pcs.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_reader::{GlobalConst, PROTO_UV_IMMUTABLE, PROTO_UV_LOCAL};
+    use crate::call_graph::{CallGraph, CallTarget};
+    use crate::disasm::asm;
+    use crate::op::Op;
+    use crate::types::*;
+    use crate::{ByteCodeDump, ByteCodeProto};
+
+    fn proto(up_values: Vec<u16>, global_consts: Vec<GlobalConst>, bc: &[Op]) -> ByteCodeProto {
+        let bc_raw = bc.iter().map(asm).collect();
+        ByteCodeProto::from_parts(0, 0, 4, up_values, global_consts, vec![], bc_raw).unwrap()
+    }
+
+    fn str(name: &str) -> GlobalConst {
+        GlobalConst::Str(name.as_bytes().to_vec())
+    }
+
+    // local function helper() print("x") end
+    // function run() helper() end
+    // run(); string.format("%d", 1)
+    fn dump(helper_uv: u16) -> ByteCodeDump {
+        let helper = proto(
+            vec![],
+            vec![str("x"), str("print")],
+            &[
+                Op::GGET(Dst(0), Str(0)),
+                Op::KSTR(Dst(1), Str(1)),
+                Op::CALL(Base(0), Lit(1), Lit(2)),
+                Op::RET0(RBase(0), Lit(1)),
+            ],
+        );
+        let run = proto(
+            vec![helper_uv],
+            vec![],
+            &[
+                Op::UGET(Dst(0), UV(0)),
+                Op::CALL(Base(0), Lit(1), Lit(1)),
+                Op::RET0(RBase(0), Lit(1)),
+            ],
+        );
+        let main = proto(
+            vec![],
+            vec![
+                str("%d"),
+                str("format"),
+                str("string"),
+                str("run"),
+                GlobalConst::ProtoChild(1),
+                GlobalConst::ProtoChild(0),
+            ],
+            &[
+                Op::FNEW(Dst(0), Func(0)),
+                Op::FNEW(Dst(1), Func(1)),
+                Op::GSET(Var(1), Str(2)),
+                Op::GGET(Dst(1), Str(2)),
+                Op::CALL(Base(1), Lit(1), Lit(1)),
+                Op::GGET(Dst(1), Str(3)),
+                Op::TGETS(Dst(1), Var(1), Str(4)),
+                Op::KSTR(Dst(2), Str(5)),
+                Op::KSHORT(Dst(3), LitS(1)),
+                Op::CALL(Base(1), Lit(1), Lit(3)),
+                Op::RET0(RBase(0), Lit(1)),
+            ],
+        );
+
+        ByteCodeDump::from_prototypes(0, vec![], vec![helper, run, main])
+    }
+
+    #[test]
+    fn resolve_calls() {
+        let call_graph = CallGraph::build(&dump(PROTO_UV_LOCAL | PROTO_UV_IMMUTABLE)).unwrap();
+        let callees = |proto: usize| -> Vec<(CallTarget, Vec<u32>)> {
+            call_graph
+                .callees(proto)
+                .into_iter()
+                .map(|(target, sites)| (target.clone(), sites.pcs.clone()))
+                .collect()
+        };
+
+        assert_eq!(
+            callees(2),
+            vec![
+                (CallTarget::Proto(1), vec![5]),
+                (CallTarget::External("string.format".to_string()), vec![10]),
+            ]
+        );
+        assert_eq!(callees(1), vec![(CallTarget::Proto(0), vec![2])]);
+        assert_eq!(
+            callees(0),
+            vec![(CallTarget::External("print".to_string()), vec![3])]
+        );
+        assert_eq!(call_graph.names(1), &["run".to_string()]);
+
+        let dot = call_graph.to_dot();
+        assert!(dot.contains("n1 [label=\"proto 1\\lrun\\l\"];"));
+        assert!(dot.contains("n2 -> n1 [label=\"0005\"];"));
+    }
+    #[test]
+    fn mutable_up_value_unresolved() {
+        // `helper` could be reassigned by another closure
+        let call_graph = CallGraph::build(&dump(PROTO_UV_LOCAL)).unwrap();
+
+        assert!(call_graph.callees(1).is_empty());
+        assert_eq!(call_graph.callees(2).len(), 2);
+    }
+
+    // function(f) f(); f = function() end; f(); local g = f; goto next; ::next:: g() end
+    #[test]
+    fn closures_known_after_fnew() {
+        let child = proto(vec![], vec![], &[Op::RET0(RBase(0), Lit(1))]);
+        let bc = [
+            Op::CALL(Base(0), Lit(1), Lit(1)),
+            Op::FNEW(Dst(0), Func(0)),
+            Op::CALL(Base(0), Lit(1), Lit(1)),
+            Op::FNEW(Dst(1), Func(0)),
+            Op::JMP(RBase(2), Jump(0)),
+            Op::CALL(Base(1), Lit(1), Lit(1)),
+            Op::RET0(RBase(0), Lit(1)),
+        ];
+        let main = ByteCodeProto::from_parts(
+            0,
+            1,
+            3,
+            vec![],
+            vec![GlobalConst::ProtoChild(0)],
+            vec![],
+            bc.iter().map(asm).collect(),
+        )
+        .unwrap();
+
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![child, main]);
+        let call_graph = CallGraph::build(&dump).unwrap();
+        let callees: Vec<(CallTarget, Vec<u32>)> = call_graph
+            .callees(1)
+            .into_iter()
+            .map(|(target, sites)| (target.clone(), sites.pcs.clone()))
+            .collect();
+
+        // parameter called before `FNEW` is unknown
+        assert_eq!(callees, vec![(CallTarget::Proto(0), vec![3, 6])]);
+    }
+}
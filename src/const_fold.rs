@@ -0,0 +1,538 @@
+use crate::bytecode_reader::NumConst;
+use crate::graph::dataflow::{solve, DataflowAnalysis, Direction};
+use crate::ir::{Block, Expr, Insn, ENTRY_BLOCK};
+use crate::listing::fmt_lua_num;
+use crate::resolver::BranchKind;
+use crate::Graph;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Value of constant expression
+#[derive(Debug, Clone)]
+pub enum Const {
+    Nil,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+}
+
+// numbers are compared by bits, it is identity of the fact and not lua equality
+impl PartialEq for Const {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Const::Nil, Const::Nil) => true,
+            (Const::Bool(a), Const::Bool(b)) => a == b,
+            (Const::Num(a), Const::Num(b)) => a.to_bits() == b.to_bits(),
+            (Const::Str(a), Const::Str(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Const {
+    pub fn from_expr(expr: &Expr, num_consts: &[NumConst]) -> Option<Const> {
+        match expr {
+            Expr::Nil => Some(Const::Nil),
+            Expr::Bool(a) => Some(Const::Bool(*a)),
+            Expr::Lit(a) => Some(Const::Num(*a as f64)),
+            Expr::Short(a) => Some(Const::Num(*a as f64)),
+            Expr::Number(a) => Some(Const::Num(*a)),
+            Expr::Num(idx) => num_consts
+                .get(*idx as usize)
+                .map(|num| Const::Num(num.to_f64())),
+            Expr::Str(a) => Some(Const::Str(a.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_expr(&self) -> Box<Expr> {
+        Box::new(match self {
+            Const::Nil => Expr::Nil,
+            Const::Bool(a) => Expr::Bool(*a),
+            Const::Num(a) => Expr::Number(*a),
+            Const::Str(a) => Expr::Str(a.clone()),
+        })
+    }
+
+    fn is_truthy(&self) -> bool {
+        !matches!(self, Const::Nil | Const::Bool(false))
+    }
+
+    // arithmetic operand, strings are coerced to numbers
+    fn to_number(&self) -> Option<f64> {
+        match self {
+            Const::Num(a) => Some(*a),
+            Const::Str(a) => str_to_number(a),
+            _ => None,
+        }
+    }
+
+    // concatenation operand, numbers are converted like lua `tostring` (`%.14g`)
+    fn to_piece(&self) -> Option<String> {
+        match self {
+            Const::Num(a) => Some(fmt_lua_num(*a)),
+            Const::Str(a) => Some(a.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Lua string to number coercion. Only decimal and hexadecimal integer forms are accepted,
+/// everything else isn't folded.
+pub fn str_to_number(str: &str) -> Option<f64> {
+    let str = str.trim_matches(|c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0b' | '\x0c'));
+
+    let (negative, body) = match str.as_bytes().first() {
+        Some(b'-') => (true, &str[1..]),
+        Some(b'+') => (false, &str[1..]),
+        _ => (false, str),
+    };
+
+    let value = match body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+        Some(hex) => {
+            if hex.is_empty() || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            u64::from_str_radix(hex, 16).ok()? as f64
+        }
+        None => {
+            // rust parser also accepts `inf` and `nan` words
+            let is_decimal = matches!(body.bytes().next(), Some(c) if c.is_ascii_digit() || c == b'.')
+                && body
+                    .bytes()
+                    .all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'));
+
+            if !is_decimal {
+                return None;
+            }
+
+            body.parse::<f64>().ok()?
+        }
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn arith(expr: &Expr, a: &Const, b: &Const) -> Option<f64> {
+    let (a, b) = (a.to_number()?, b.to_number()?);
+
+    Some(match expr {
+        Expr::Add(_) => a + b,
+        Expr::Sub(_) => a - b,
+        Expr::Mul(_) => a * b,
+        Expr::Div(_) => a / b,
+        // result takes sign of the divisor
+        Expr::Mod(_) => a - (a / b).floor() * b,
+        Expr::Pow(_) => a.powf(b),
+        _ => return None,
+    })
+}
+
+// values of different types are never equal, metamethods aren't called for constants
+fn lua_eq(a: &Const, b: &Const) -> bool {
+    match (a, b) {
+        (Const::Num(a), Const::Num(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// only numbers with numbers and strings with strings can be ordered
+fn lua_lt(a: &Const, b: &Const, or_equal: bool) -> Option<bool> {
+    match (a, b) {
+        (Const::Num(a), Const::Num(b)) => Some(if or_equal { a <= b } else { a < b }),
+        (Const::Str(a), Const::Str(b)) => Some(if or_equal { a <= b } else { a < b }),
+        _ => None,
+    }
+}
+
+// evaluates operator with constant operands, operands aren't folded recursively
+fn eval(expr: &Expr, num_consts: &[NumConst]) -> Option<Const> {
+    let operand = |expr: &Expr| Const::from_expr(expr, num_consts);
+
+    let value = match expr {
+        Expr::Not(a) => Const::Bool(!operand(a)?.is_truthy()),
+        Expr::Minus(a) => Const::Num(-operand(a)?.to_number()?),
+        Expr::Eq([a, b]) => Const::Bool(lua_eq(&operand(a)?, &operand(b)?)),
+        Expr::Ne([a, b]) => Const::Bool(!lua_eq(&operand(a)?, &operand(b)?)),
+        Expr::Lt([a, b]) => Const::Bool(lua_lt(&operand(a)?, &operand(b)?, false)?),
+        Expr::Le([a, b]) => Const::Bool(lua_lt(&operand(a)?, &operand(b)?, true)?),
+        Expr::Gt([a, b]) => Const::Bool(lua_lt(&operand(b)?, &operand(a)?, false)?),
+        Expr::Ge([a, b]) => Const::Bool(lua_lt(&operand(b)?, &operand(a)?, true)?),
+        Expr::Add([a, b])
+        | Expr::Sub([a, b])
+        | Expr::Mul([a, b])
+        | Expr::Div([a, b])
+        | Expr::Mod([a, b])
+        | Expr::Pow([a, b]) => Const::Num(arith(expr, &operand(a)?, &operand(b)?)?),
+        _ => return None,
+    };
+
+    // there is no literal for infinity and nan, so such results are left as is
+    match value {
+        Const::Num(a) if !a.is_finite() => None,
+        _ => Some(value),
+    }
+}
+
+/// Folds operators with constant operands bottom up. Returns true if expression was changed.
+pub fn fold_expr(expr: &mut Expr, num_consts: &[NumConst]) -> bool {
+    let mut changed = match expr {
+        Expr::Not(a) | Expr::Len(a) | Expr::Minus(a) => fold_expr(a, num_consts),
+        Expr::Lt(args)
+        | Expr::Ge(args)
+        | Expr::Le(args)
+        | Expr::Gt(args)
+        | Expr::Eq(args)
+        | Expr::Ne(args)
+        | Expr::Add(args)
+        | Expr::Sub(args)
+        | Expr::Mul(args)
+        | Expr::Div(args)
+        | Expr::Mod(args)
+        | Expr::Pow(args)
+        | Expr::Table(args) => {
+            let changed = fold_expr(&mut args[0], num_consts);
+            fold_expr(&mut args[1], num_consts) || changed
+        }
+        _ => false,
+    };
+
+    if let Some(value) = eval(expr, num_consts) {
+        *expr = *value.to_expr();
+        changed = true;
+    }
+
+    changed
+}
+
+// replaces variables having known constant value
+fn substitute(expr: &mut Expr, consts: &BTreeMap<u16, Const>) -> bool {
+    match expr {
+        Expr::Var(var) => match consts.get(&var.0) {
+            Some(value) => {
+                *expr = *value.to_expr();
+                true
+            }
+            None => false,
+        },
+        Expr::Not(a) | Expr::Len(a) | Expr::Minus(a) => substitute(a, consts),
+        Expr::Lt(args)
+        | Expr::Ge(args)
+        | Expr::Le(args)
+        | Expr::Gt(args)
+        | Expr::Eq(args)
+        | Expr::Ne(args)
+        | Expr::Add(args)
+        | Expr::Sub(args)
+        | Expr::Mul(args)
+        | Expr::Div(args)
+        | Expr::Mod(args)
+        | Expr::Pow(args)
+        | Expr::Table(args) => {
+            let changed = substitute(&mut args[0], consts);
+            substitute(&mut args[1], consts) || changed
+        }
+        _ => false,
+    }
+}
+
+// expressions read by instruction, loop and unlifted operands have to stay slots
+fn operands_mut(insn: &mut Insn) -> Vec<&mut Expr> {
+    match insn {
+        Insn::SetVars(_, expr)
+        | Insn::If(expr)
+        | Insn::IfCopy(_, expr)
+        | Insn::While(expr)
+        | Insn::Repeat(expr)
+        | Insn::SetUpValue(_, expr) => vec![expr.as_mut()],
+        Insn::SetGlobalTableVar(args) | Insn::SetTableVar(_, args) => {
+            args.iter_mut().map(|arg| arg.as_mut()).collect()
+        }
+        Insn::Call(_, exprs)
+        | Insn::TailCall(exprs)
+        | Insn::Cat(_, exprs)
+        | Insn::Return(exprs) => exprs.iter_mut().collect(),
+        Insn::For(_) | Insn::TypeGuard(..) | Insn::Unlifted(..) => vec![],
+    }
+}
+
+// joins constant tail of concatenation, vm joins it the same way before calling `__concat`
+// of earlier operands, so the rest is kept untouched
+fn fold_cat(insn: &mut Insn, num_consts: &[NumConst]) -> bool {
+    let Insn::Cat(var, exprs) = insn else {
+        return false;
+    };
+
+    let pieces: Vec<String> = exprs
+        .iter()
+        .rev()
+        .map_while(|expr| Const::from_expr(expr, num_consts)?.to_piece())
+        .collect();
+
+    if pieces.len() == exprs.len() {
+        let str = pieces.into_iter().rev().collect();
+        *insn = Insn::set_var(var.clone(), Expr::str(str));
+        true
+    } else if pieces.len() >= 2 {
+        let mut folded = exprs[..exprs.len() - pieces.len()].to_vec();
+        folded.push(Expr::Str(pieces.into_iter().rev().collect()));
+        *exprs = folded.into_boxed_slice();
+        true
+    } else {
+        false
+    }
+}
+
+/// Substitutes known constants into operands of instruction and folds them.
+/// Returns true if instruction was changed.
+pub fn fold_insn(insn: &mut Insn, consts: &BTreeMap<u16, Const>, num_consts: &[NumConst]) -> bool {
+    let mut changed = false;
+
+    for expr in operands_mut(insn) {
+        changed |= substitute(expr, consts);
+        changed |= fold_expr(expr, num_consts);
+    }
+
+    fold_cat(insn, num_consts) || changed
+}
+
+// constants after instruction from constants before it, `captured` slots can be changed by
+// child closures and are never tracked
+fn step_consts(
+    insn: &Insn,
+    consts: &mut BTreeMap<u16, Const>,
+    captured: &BTreeSet<u16>,
+    num_consts: &[NumConst],
+) {
+    for slot in insn.defs() {
+        consts.remove(&slot);
+    }
+
+    if let Insn::SetVars(vars, expr) = insn {
+        if let Some(value) = Const::from_expr(expr, num_consts) {
+            for var in vars.iter().filter(|var| !captured.contains(&var.0)) {
+                consts.insert(var.0, value.clone());
+            }
+        }
+    }
+}
+
+/// Forward analysis of slots holding the same constant on all paths,
+/// `None` is a fact of block which isn't reached yet
+struct ConstPropagation<'a> {
+    num_consts: &'a [NumConst],
+    captured: &'a BTreeSet<u16>,
+}
+
+impl DataflowAnalysis<Block, BranchKind> for ConstPropagation<'_> {
+    type Fact = Option<BTreeMap<u16, Const>>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self) -> Self::Fact {
+        Some(BTreeMap::new())
+    }
+
+    fn initial(&self) -> Self::Fact {
+        None
+    }
+
+    fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        if let Some(other) = other {
+            match fact {
+                None => *fact = Some(other.clone()),
+                Some(consts) => consts.retain(|slot, value| other.get(slot) == Some(value)),
+            }
+        }
+    }
+
+    fn transfer(&self, _: u32, node: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut consts = fact.clone()?;
+
+        for insn in node.iter_insn() {
+            let mut insn = insn.clone();
+            fold_insn(&mut insn, &consts, self.num_consts);
+            step_consts(&insn, &mut consts, self.captured, self.num_consts);
+        }
+
+        Some(consts)
+    }
+}
+
+/// Propagates constants through assignments and folds operators with constant operands
+/// following lua semantics. Slots `captured` by child closures are never substituted.
+/// Returns number of changed instructions.
+pub fn propagate_constants(
+    graph: &mut Graph<Block, BranchKind>,
+    num_consts: &[NumConst],
+    captured: &BTreeSet<u16>,
+) -> usize {
+    let analysis = ConstPropagation {
+        num_consts,
+        captured,
+    };
+    let result = solve(graph, ENTRY_BLOCK, &analysis);
+    let block_indexes: Vec<u32> = graph.nodes().keys().cloned().collect();
+    let mut changed = 0;
+
+    for block_idx in block_indexes {
+        // unreachable blocks are only folded
+        let mut consts = result.entry[&block_idx].clone().unwrap_or_default();
+
+        for insn in graph.node_weight_mut(block_idx).unwrap().insns_mut() {
+            if fold_insn(insn, &consts, num_consts) {
+                changed += 1;
+            }
+
+            step_consts(insn, &mut consts, captured, num_consts);
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_reader::NumConst;
+    use crate::const_fold::{fold_insn, propagate_constants, str_to_number};
+    use crate::graph::Graph;
+    use crate::ir::test_utils::{block, lines};
+    use crate::ir::{Block, Expr, Insn, Var};
+    use crate::resolver::BranchKind;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn fold(expr: Box<Expr>) -> String {
+        let mut insn = Insn::set_var(Var(0), expr);
+        fold_insn(&mut insn, &BTreeMap::new(), &[NumConst::Num(0, 0x40240000)]);
+        insn.to_string()
+    }
+
+    fn str(val: &str) -> Box<Expr> {
+        Expr::str(val.to_string())
+    }
+
+    #[test]
+    fn fold_lua_semantics() {
+        let expr = Expr::sub(Expr::mul(Expr::short(3), Expr::short(7)), Expr::short(20));
+        assert_eq!(fold(expr), "v0 = 1");
+
+        assert_eq!(fold(Expr::div(Expr::short(7), Expr::short(2))), "v0 = 3.5");
+        assert_eq!(fold(Expr::mod_(Expr::short(7), Expr::short(-3))), "v0 = -2");
+        assert_eq!(fold(Expr::mod_(Expr::short(-7), Expr::short(3))), "v0 = 2");
+        assert_eq!(fold(Expr::mod_(Expr::num(0), Expr::short(3))), "v0 = 1");
+        assert_eq!(
+            fold(Expr::pow(Expr::short(2), Expr::short(10))),
+            "v0 = 1024"
+        );
+        assert_eq!(fold(Expr::add(str(" 10 "), Expr::short(1))), "v0 = 11");
+
+        // folded numbers keep all digits, `%.14g` would print 0.3 and 9.007199254741e+15
+        assert_eq!(
+            fold(Expr::add(str("0.1"), str("0.2"))),
+            "v0 = 0.30000000000000004"
+        );
+        assert_eq!(
+            fold(Expr::pow(Expr::short(2), Expr::short(53))),
+            "v0 = 9007199254740992"
+        );
+        assert_eq!(fold(Expr::mul(str("0x10"), Expr::short(2))), "v0 = 32");
+        assert_eq!(fold(Expr::minus(str("1e2"))), "v0 = -100");
+
+        // runtime errors and values without literal stay unfolded
+        assert_eq!(
+            fold(Expr::div(Expr::short(1), Expr::short(0))),
+            "v0 = Short(1) / Short(0)"
+        );
+        assert_eq!(
+            fold(Expr::add(str("abc"), Expr::short(1))),
+            "v0 = \"abc\" + Short(1)"
+        );
+        assert_eq!(
+            fold(Expr::add(str("inf"), Expr::short(1))),
+            "v0 = \"inf\" + Short(1)"
+        );
+        assert_eq!(
+            fold(Expr::add(Expr::nil(), Expr::short(1))),
+            "v0 = Nil + Short(1)"
+        );
+        assert_eq!(
+            fold(Expr::lt(Expr::short(1), str("2"))),
+            "v0 = Short(1) < \"2\""
+        );
+    }
+
+    #[test]
+    fn fold_comparison_and_not() {
+        assert_eq!(fold(Expr::lt(str("a"), str("b"))), "v0 = Bool(true)");
+        assert_eq!(
+            fold(Expr::ge(Expr::short(1), Expr::short(2))),
+            "v0 = Bool(false)"
+        );
+        assert_eq!(fold(Expr::eq(Expr::short(1), str("1"))), "v0 = Bool(false)");
+        assert_eq!(
+            fold(Expr::ne(Expr::num(0), Expr::short(10))),
+            "v0 = Bool(false)"
+        );
+        assert_eq!(fold(Expr::not(Expr::nil())), "v0 = Bool(true)");
+        assert_eq!(fold(Expr::not(Expr::short(0))), "v0 = Bool(false)");
+    }
+
+    #[test]
+    fn fold_concatenation() {
+        let mut insn = Insn::Cat(Var(0), vec![*str("a"), *str("b"), Expr::Short(1)].into());
+        assert!(fold_insn(&mut insn, &BTreeMap::new(), &[]));
+        assert_eq!(insn.to_string(), "v0 = \"ab1\"");
+
+        let mut insn = Insn::Cat(
+            Var(0),
+            vec![*str("a"), Expr::Var(Var(1)), *str("b"), *str("c")].into(),
+        );
+        assert!(fold_insn(&mut insn, &BTreeMap::new(), &[]));
+        assert_eq!(insn.to_string(), "v0 = \"a\" ~ v1 ~ \"bc\"");
+    }
+
+    #[test]
+    fn string_coercion() {
+        assert_eq!(str_to_number("\t0XfF\n"), Some(255.0));
+        assert_eq!(str_to_number("-.5"), Some(-0.5));
+        assert_eq!(str_to_number("nan"), None);
+        assert_eq!(str_to_number("+-1"), None);
+        assert_eq!(str_to_number("0x"), None);
+    }
+
+    // v1 and v0 are the same on both paths, v3 differs
+    #[test]
+    fn propagate_through_merge() {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(
+            0,
+            block(vec![
+                Insn::set_var(Var(0), Expr::short(3)),
+                Insn::set_var(Var(1), Expr::mul(Expr::var(0), Expr::short(7))),
+                Insn::set_var(Var(5), Expr::str("x".to_string())),
+                Insn::If(Expr::var(2)),
+            ]),
+        );
+        graph.add_node(1, block(vec![Insn::set_var(Var(3), Expr::short(1))]));
+        graph.add_node(2, block(vec![Insn::set_var(Var(3), Expr::short(2))]));
+        graph.add_node(
+            3,
+            block(vec![
+                Insn::set_var(Var(4), Expr::sub(Expr::var(1), Expr::var(0))),
+                Insn::Return(vec![Expr::Var(Var(4)), Expr::Var(Var(3)), Expr::Var(Var(5))].into()),
+            ]),
+        );
+        graph.add_edge(BranchKind::True, 0, 1);
+        graph.add_edge(BranchKind::False, 0, 2);
+        graph.add_edge(BranchKind::Unconditional, 1, 3);
+        graph.add_edge(BranchKind::Unconditional, 2, 3);
+
+        let captured: BTreeSet<u16> = [5].into_iter().collect();
+
+        assert_eq!(propagate_constants(&mut graph, &[], &captured), 3);
+        assert_eq!(lines(&graph, 0)[1], "v1 = 21");
+        assert_eq!(lines(&graph, 3), vec!["v4 = 18", "return 18, v3, v5"]);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::graph::dataflow::{solve, DataflowAnalysis, Direction};
+use crate::ir::{Block, Insn, Var, ENTRY_BLOCK};
+use crate::resolver::BranchKind;
+use crate::Graph;
+use std::collections::BTreeSet;
+
+// live slots before instruction from live slots after it
+fn step_liveness(insn: &Insn, live: &mut BTreeSet<u16>) {
+    // copy is done on one edge only, old value is still alive on the other one
+    if !matches!(insn, Insn::IfCopy(..)) {
+        for slot in insn.defs() {
+            live.remove(&slot);
+        }
+    }
+
+    live.extend(insn.uses());
+}
+
+/// Liveness of variable slots in lifted blocks
+struct IrLiveness;
+
+impl DataflowAnalysis<Block, BranchKind> for IrLiveness {
+    type Fact = BTreeSet<u16>;
+
+    fn direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn boundary(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn initial(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.extend(other.iter().cloned());
+    }
+
+    fn transfer(&self, _: u32, node: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut live = fact.clone();
+
+        for insn in node.iter_insn().rev() {
+            step_liveness(insn, &mut live);
+        }
+
+        live
+    }
+}
+
+enum Store {
+    Alive,
+    Trimmed,
+    Dead,
+}
+
+// checks pure assignment against slots live after it, dead targets of multiple
+// assignment are dropped
+fn check_store(insn: &mut Insn, live: &BTreeSet<u16>, captured: &BTreeSet<u16>) -> Store {
+    let is_dead = |var: &Var| !live.contains(&var.0) && !captured.contains(&var.0);
+
+    match insn {
+        Insn::SetVars(vars, expr) if expr.is_pure() => {
+            let alive: Vec<Var> = vars.iter().filter(|var| !is_dead(var)).cloned().collect();
+
+            if alive.is_empty() {
+                Store::Dead
+            } else if alive.len() < vars.len() {
+                *vars = alive.into_boxed_slice();
+                Store::Trimmed
+            } else {
+                Store::Alive
+            }
+        }
+        Insn::Cat(var, _) if is_dead(var) => Store::Dead,
+        _ => Store::Alive,
+    }
+}
+
+/// Removes assignments of pure expressions to slots which are never read. Calls, table and
+/// up value stores are kept, slots `captured` by child closures are treated as always read.
+/// Returns number of removed or trimmed instructions.
+pub fn eliminate_dead_stores(
+    graph: &mut Graph<Block, BranchKind>,
+    captured: &BTreeSet<u16>,
+) -> usize {
+    let mut total = 0;
+
+    // removed store can make stores of its operands dead, repeat until nothing changes
+    loop {
+        let liveness = solve(graph, ENTRY_BLOCK, &IrLiveness);
+        let block_indexes: Vec<u32> = graph.nodes().keys().cloned().collect();
+        let mut changed = 0;
+
+        for block_idx in block_indexes {
+            let mut live = liveness.exit[&block_idx].clone();
+            let insns = graph.node_weight_mut(block_idx).unwrap().insns_mut();
+            let mut kept = Vec::with_capacity(insns.len());
+
+            for mut insn in insns.drain(..).rev() {
+                match check_store(&mut insn, &live, captured) {
+                    Store::Alive => {}
+                    Store::Trimmed => changed += 1,
+                    Store::Dead => {
+                        changed += 1;
+                        continue;
+                    }
+                }
+
+                step_liveness(&insn, &mut live);
+                kept.push(insn);
+            }
+
+            kept.reverse();
+            *insns = kept;
+        }
+
+        if changed == 0 {
+            return total;
+        }
+
+        total += changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dce::eliminate_dead_stores;
+    use crate::graph::Graph;
+    use crate::ir::test_utils::{block, lines};
+    use crate::ir::{Block, Expr, Insn, Var};
+    use crate::resolver::BranchKind;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn remove_dead_stores() {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(
+            0,
+            block(vec![
+                Insn::set_var(Var(0), Expr::short(1)),
+                Insn::set_var(
+                    Var(0),
+                    Expr::table(Box::new(Expr::GlobalTable), Expr::str("print".to_string())),
+                ),
+                Insn::set_var(Var(1), Expr::var(2)),
+                Insn::Call(
+                    vec![Var(1)].into_boxed_slice(),
+                    vec![Expr::Var(Var(0))].into_boxed_slice(),
+                ),
+                Insn::Cat(
+                    Var(3),
+                    vec![Expr::Var(Var(1)), Expr::Var(Var(1))].into_boxed_slice(),
+                ),
+                Insn::set_var(Var(6), Expr::var(2)),
+                Insn::set_var(Var(7), Expr::add(Expr::var(6), Expr::num(0))),
+                Insn::set_var(Var(8), Expr::short(3)),
+                Insn::SetVars(vec![Var(4), Var(5)].into_boxed_slice(), Expr::nil()),
+                Insn::Return(vec![Expr::Var(Var(5))].into_boxed_slice()),
+            ]),
+        );
+
+        let captured: BTreeSet<u16> = [8].into_iter().collect();
+
+        assert_eq!(eliminate_dead_stores(&mut graph, &captured), 6);
+        assert_eq!(
+            lines(&graph, 0),
+            vec![
+                "v0 = print",
+                "v1 = v0()",
+                "v8 = Short(3)",
+                "v5 = Nil",
+                "return v5",
+            ]
+        );
+    }
+
+    // value of v2 is returned unchanged when copy edge isn't taken
+    #[test]
+    fn keep_stores_alive_on_branches() {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(
+            0,
+            block(vec![
+                Insn::set_var(Var(0), Expr::short(1)),
+                Insn::set_var(Var(2), Expr::short(2)),
+                Insn::IfCopy(Var(2), Expr::var(1)),
+            ]),
+        );
+        graph.add_node(
+            1,
+            block(vec![Insn::Return(
+                vec![Expr::Var(Var(0)), Expr::Var(Var(2))].into_boxed_slice(),
+            )]),
+        );
+        graph.add_node(
+            2,
+            block(vec![Insn::Return(
+                vec![Expr::Var(Var(2))].into_boxed_slice(),
+            )]),
+        );
+        graph.add_edge(BranchKind::TrueCopy, 0, 2);
+        graph.add_edge(BranchKind::False, 0, 1);
+
+        assert_eq!(eliminate_dead_stores(&mut graph, &BTreeSet::new()), 0);
+        assert_eq!(lines(&graph, 0).len(), 3);
+    }
+}
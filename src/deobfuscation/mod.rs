@@ -0,0 +1,74 @@
+pub mod opaque;
+pub mod strings;
+pub mod unflatten;
+
+pub use opaque::OpaquePredicates;
+pub use strings::{EmulatorEvaluator, StringDecryption, StringEvaluator};
+pub use unflatten::{unflatten, Unflattening};
+
+use crate::ir::Block;
+use crate::resolver::BranchKind;
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError, Graph};
+use std::collections::BTreeSet;
+
+// passes can enable each other, but a broken pass mustn't loop forever
+const MAX_ROUNDS: usize = 16;
+
+/// Prototype which lifted graph belongs to
+pub struct PassContext<'a> {
+    pub dump: &'a ByteCodeDump,
+    pub proto_idx: usize,
+    /// slots captured by child closures, see `ByteCodeDump::captured_slots`
+    pub captured: BTreeSet<u16>,
+}
+
+impl<'a> PassContext<'a> {
+    pub fn new(dump: &'a ByteCodeDump, proto_idx: usize) -> Self {
+        PassContext {
+            dump,
+            proto_idx,
+            captured: dump.captured_slots(proto_idx),
+        }
+    }
+
+    pub fn proto(&self) -> &'a ByteCodeProto {
+        &self.dump.prototypes()[self.proto_idx]
+    }
+}
+
+/// Transformation of lifted IR graph which undoes obfuscation
+pub trait DeobfuscationPass {
+    fn name(&self) -> &'static str;
+
+    /// Returns number of changes made to the graph
+    fn run(
+        &mut self,
+        graph: &mut Graph<Block, BranchKind>,
+        ctx: &PassContext,
+    ) -> Result<usize, DecompileError>;
+}
+
+/// Runs passes in order until none of them changes the graph, returns total number of changes
+pub fn run_passes(
+    passes: &mut [Box<dyn DeobfuscationPass>],
+    graph: &mut Graph<Block, BranchKind>,
+    ctx: &PassContext,
+) -> Result<usize, DecompileError> {
+    let mut total = 0;
+
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = 0;
+
+        for pass in passes.iter_mut() {
+            changed += pass.run(graph, ctx)?;
+        }
+
+        if changed == 0 {
+            break;
+        }
+
+        total += changed;
+    }
+
+    Ok(total)
+}
@@ -0,0 +1,333 @@
+use crate::bytecode_reader::NumConst;
+use crate::const_fold::{propagate_constants, Const};
+use crate::deobfuscation::{DeobfuscationPass, PassContext};
+use crate::ir::{Block, Expr, Insn, Var, ENTRY_BLOCK};
+use crate::resolver::BranchKind;
+use crate::type_inference::{expr_type, Type, TypeEnv, TypeInference};
+use crate::{DecompileError, Graph};
+use std::collections::BTreeSet;
+
+// truthiness of constant branch condition
+fn constant_condition(block: &Block, num_consts: &[NumConst]) -> Option<bool> {
+    let cond = match block.iter_insn().last()? {
+        Insn::If(expr) | Insn::IfCopy(_, expr) => Const::from_expr(expr, num_consts)?,
+        _ => return None,
+    };
+
+    Some(!matches!(cond, Const::Nil | Const::Bool(false)))
+}
+
+/// Turns branches with constant condition into unconditional edges, copy of `IfCopy` is kept
+/// as assignment when its edge is taken. Returns number of folded branches.
+pub fn fold_constant_branches(
+    graph: &mut Graph<Block, BranchKind>,
+    num_consts: &[NumConst],
+) -> usize {
+    let block_indexes: Vec<u32> = graph.nodes().keys().cloned().collect();
+    let mut folded = 0;
+
+    for block_idx in block_indexes {
+        let cond = match constant_condition(graph.node_weight(block_idx).unwrap(), num_consts) {
+            Some(cond) => cond,
+            None => continue,
+        };
+
+        let outputs: Vec<u32> = graph.outputs(block_idx).collect();
+        let taken = outputs
+            .iter()
+            .cloned()
+            .find(|&edge_idx| graph.edge_weight(edge_idx).unwrap().polarity() == Some(cond));
+
+        let Some(taken) = taken else {
+            continue;
+        };
+
+        let kind = *graph.edge_weight(taken).unwrap();
+        let to = graph.edge_to(taken);
+
+        for edge_idx in outputs {
+            graph.remove_edge(edge_idx);
+        }
+        graph.add_edge(BranchKind::Unconditional, block_idx, to);
+
+        let insns = graph.node_weight_mut(block_idx).unwrap().insns_mut();
+        match insns.pop() {
+            Some(Insn::IfCopy(var, expr)) if kind.is_copy() => insns.push(Insn::set_var(var, expr)),
+            _ => {}
+        }
+
+        folded += 1;
+    }
+
+    folded
+}
+
+/// Removes blocks which can't be reached from the entry block. Returns number of removed blocks.
+pub fn prune_unreachable(graph: &mut Graph<Block, BranchKind>) -> usize {
+    if !graph.exists(ENTRY_BLOCK) {
+        return 0;
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut dfs = graph.dfs_post_order_visitor(ENTRY_BLOCK);
+    while let Some(idx) = dfs.next(graph) {
+        reachable.insert(idx);
+    }
+
+    let unreachable: Vec<u32> = graph
+        .nodes()
+        .keys()
+        .filter(|idx| !reachable.contains(idx))
+        .cloned()
+        .collect();
+
+    for &idx in &unreachable {
+        graph.remove_node(idx);
+    }
+
+    if !unreachable.is_empty() {
+        graph.compact();
+    }
+
+    unreachable.len()
+}
+
+// constant store which is overwritten before it is read, e.g. `KPRI` in front of real store
+fn is_overwritten(insns: &[Insn], idx: usize, captured: &BTreeSet<u16>) -> bool {
+    let slot = match &insns[idx] {
+        Insn::SetVars(vars, expr) if vars.len() == 1 => match expr.as_ref() {
+            Expr::Nil | Expr::Bool(_) => vars[0].0,
+            _ => return false,
+        },
+        _ => return false,
+    };
+
+    for insn in &insns[idx + 1..] {
+        if insn.uses().contains(&slot) {
+            return false;
+        }
+
+        // captured slot can be read by called closure
+        if captured.contains(&slot)
+            && matches!(
+                insn,
+                Insn::Call(..) | Insn::TailCall(..) | Insn::Unlifted(..)
+            )
+        {
+            return false;
+        }
+
+        if insn.defs().contains(&slot) {
+            return !matches!(insn, Insn::IfCopy(..));
+        }
+    }
+
+    false
+}
+
+// `v = !x; v = !v` where `x` is boolean, returns `v` and `x`
+fn double_not(insns: &[Insn], idx: usize, types: &[TypeEnv]) -> Option<(Var, Box<Expr>)> {
+    let (Insn::SetVars(first, a), Some(Insn::SetVars(second, b))) =
+        (&insns[idx], insns.get(idx + 1))
+    else {
+        return None;
+    };
+
+    match (first.as_ref(), second.as_ref(), a.as_ref(), b.as_ref()) {
+        ([var], [second], Expr::Not(x), Expr::Not(y))
+            if second.0 == var.0
+                && matches!(y.as_ref(), Expr::Var(y) if y.0 == var.0)
+                && expr_type(x, &types[idx]) == Type::Boolean =>
+        {
+            Some((var.clone(), x.clone()))
+        }
+        _ => None,
+    }
+}
+
+// `MOV` of slot to itself
+fn is_self_move(insn: &Insn) -> bool {
+    match insn {
+        Insn::SetVars(vars, expr) => match (vars.as_ref(), expr.as_ref()) {
+            ([var], Expr::Var(src)) => var.0 == src.0,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Removes no-op sequences inserted between real instructions: self moves, double negation
+/// of boolean slots and constant stores overwritten before read. Returns number of removed
+/// instructions.
+pub fn remove_junk(graph: &mut Graph<Block, BranchKind>, captured: &BTreeSet<u16>) -> usize {
+    let inference = TypeInference::new(captured);
+    let types = TypeInference::analyze(graph, captured);
+    let block_indexes: Vec<u32> = graph.nodes().keys().cloned().collect();
+    let mut removed = 0;
+
+    for block_idx in block_indexes {
+        let block = graph.node_weight_mut(block_idx).unwrap();
+        let types = inference.types_before(block, &types.entry[&block_idx]);
+        let insns = std::mem::take(block.insns_mut());
+        let mut kept = Vec::with_capacity(insns.len());
+        let mut idx = 0;
+
+        while idx < insns.len() {
+            if let Some((var, value)) = double_not(&insns, idx, &types) {
+                // `v = !!v` of boolean is dropped completely
+                match value.as_ref() {
+                    Expr::Var(src) if src.0 == var.0 => removed += 2,
+                    _ => {
+                        kept.push(Insn::set_var(var, value));
+                        removed += 1;
+                    }
+                }
+
+                idx += 2;
+                continue;
+            }
+
+            if is_self_move(&insns[idx]) || is_overwritten(&insns, idx, captured) {
+                removed += 1;
+            } else {
+                kept.push(insns[idx].clone());
+            }
+
+            idx += 1;
+        }
+
+        *block.insns_mut() = kept;
+    }
+
+    removed
+}
+
+/// Folds opaque predicates, i.e. branches which are constant after constant propagation,
+/// prunes blocks unreachable after that and removes junk instructions
+pub struct OpaquePredicates;
+
+impl DeobfuscationPass for OpaquePredicates {
+    fn name(&self) -> &'static str {
+        "opaque-predicates"
+    }
+
+    fn run(
+        &mut self,
+        graph: &mut Graph<Block, BranchKind>,
+        ctx: &PassContext,
+    ) -> Result<usize, DecompileError> {
+        let num_consts = ctx.proto().num_consts();
+
+        let mut changed = propagate_constants(graph, num_consts, &ctx.captured);
+        changed += fold_constant_branches(graph, num_consts);
+        changed += prune_unreachable(graph);
+        changed += remove_junk(graph, &ctx.captured);
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deobfuscation::opaque::{fold_constant_branches, prune_unreachable, remove_junk};
+    use crate::graph::Graph;
+    use crate::ir::test_utils::{block, lines};
+    use crate::ir::{Block, Expr, Insn, Var};
+    use crate::resolver::BranchKind;
+    use std::collections::BTreeSet;
+
+    fn ret(var: u16) -> Block {
+        block(vec![Insn::Return(
+            vec![Expr::Var(Var(var))].into_boxed_slice(),
+        )])
+    }
+
+    #[test]
+    fn fold_opaque_predicates() {
+        // if 1 == 1 then return v0 else return v1 end, the false branch is dead
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(
+            0,
+            block(vec![Insn::If(Expr::eq(Expr::short(1), Expr::short(1)))]),
+        );
+        graph.add_node(1, ret(0));
+        graph.add_node(2, ret(1));
+        graph.add_node(3, ret(2));
+        graph.add_edge(BranchKind::True, 0, 1);
+        graph.add_edge(BranchKind::False, 0, 2);
+        graph.add_edge(BranchKind::Unconditional, 2, 3);
+
+        // condition is folded before
+        crate::const_fold::propagate_constants(&mut graph, &[], &BTreeSet::new());
+
+        assert_eq!(fold_constant_branches(&mut graph, &[]), 1);
+        assert_eq!(prune_unreachable(&mut graph), 2);
+        assert_eq!(
+            graph.nodes().keys().cloned().collect::<Vec<u32>>(),
+            vec![0, 1]
+        );
+        assert!(lines(&graph, 0).is_empty());
+
+        let edges = graph.edges();
+        assert_eq!(edges.len(), 1);
+        assert!(matches!(edges[0].weight(), BranchKind::Unconditional));
+        assert_eq!(edges[0].to(), 1);
+    }
+
+    #[test]
+    fn fold_constant_copy() {
+        // v0 = v1 or true, copy edge is taken
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(0, block(vec![Insn::IfCopy(Var(0), Expr::short(1))]));
+        graph.add_node(1, ret(0));
+        graph.add_node(2, ret(1));
+        graph.add_edge(BranchKind::TrueCopy, 0, 1);
+        graph.add_edge(BranchKind::False, 0, 2);
+
+        assert_eq!(fold_constant_branches(&mut graph, &[]), 1);
+        assert_eq!(lines(&graph, 0), vec!["v0 = Short(1)"]);
+    }
+
+    #[test]
+    fn remove_junk_insns() {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(
+            0,
+            block(vec![
+                Insn::set_var(Var(0), Expr::nil()),
+                Insn::set_var(Var(0), Expr::var(3)),
+                Insn::set_var(Var(1), Expr::var(1)),
+                Insn::set_var(Var(2), Expr::lt(Expr::var(3), Expr::var(4))),
+                Insn::set_var(Var(2), Expr::not(Expr::var(2))),
+                Insn::set_var(Var(2), Expr::not(Expr::var(2))),
+                Insn::set_var(Var(5), Expr::not(Expr::var(2))),
+                Insn::set_var(Var(5), Expr::not(Expr::var(5))),
+                // not a boolean, `not not v3` converts it
+                Insn::set_var(Var(6), Expr::not(Expr::var(3))),
+                Insn::set_var(Var(6), Expr::not(Expr::var(6))),
+                Insn::Return(
+                    vec![
+                        Expr::Var(Var(0)),
+                        Expr::Var(Var(2)),
+                        Expr::Var(Var(5)),
+                        Expr::Var(Var(6)),
+                    ]
+                    .into_boxed_slice(),
+                ),
+            ]),
+        );
+
+        assert_eq!(remove_junk(&mut graph, &BTreeSet::new()), 5);
+        assert_eq!(
+            lines(&graph, 0),
+            vec![
+                "v0 = v3",
+                "v2 = v3 < v4",
+                "v5 = v2",
+                "v6 = !v3",
+                "v6 = !v6",
+                "return v0, v2, v5, v6",
+            ]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use crate::bytecode_reader::GlobalConst;
+use crate::const_fold::Const;
+use crate::deobfuscation::{DeobfuscationPass, PassContext};
+use crate::emulator::{Emulator, Limits, Value};
+use crate::ir::{Block, Expr, Insn};
+use crate::resolver::BranchKind;
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError, Graph};
+use std::collections::BTreeMap;
+
+/// Computes result of decoder prototype for constant arguments
+pub trait StringEvaluator {
+    /// Returns decrypted bytes, `None` leaves the call as is
+    fn evaluate(
+        &mut self,
+        dump: &ByteCodeDump,
+        decoder: usize,
+        args: &[Value],
+    ) -> Result<Option<Vec<u8>>, DecompileError>;
+}
+
+/// Decoder reimplemented in rust
+impl<F> StringEvaluator for F
+where
+    F: FnMut(&[Value]) -> Option<Vec<u8>>,
+{
+    fn evaluate(
+        &mut self,
+        _dump: &ByteCodeDump,
+        _decoder: usize,
+        args: &[Value],
+    ) -> Result<Option<Vec<u8>>, DecompileError> {
+        Ok(self(args))
+    }
+}
+
+/// Runs decoder prototype in the emulator with standard library and configured globals.
+/// Every call gets fresh emulator, so limits are applied per call.
+#[derive(Default)]
+pub struct EmulatorEvaluator {
+    limits: Limits,
+    globals: Vec<(String, Value)>,
+}
+
+impl EmulatorEvaluator {
+    pub fn new(limits: Limits) -> Self {
+        EmulatorEvaluator {
+            limits,
+            globals: vec![],
+        }
+    }
+
+    /// Adds global visible to decoder, e.g. key table or native stub
+    pub fn global(mut self, name: &str, value: Value) -> Self {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+}
+
+impl StringEvaluator for EmulatorEvaluator {
+    fn evaluate(
+        &mut self,
+        dump: &ByteCodeDump,
+        decoder: usize,
+        args: &[Value],
+    ) -> Result<Option<Vec<u8>>, DecompileError> {
+        let mut emulator = Emulator::new(dump, self.limits);
+        emulator.open_stdlib()?;
+
+        for (name, value) in &self.globals {
+            emulator.set_global(name, value.clone())?;
+        }
+
+        // decoder up values are nil, it has to be self-contained
+        let closure = emulator.closure(decoder, vec![])?;
+
+        // failure of decoder on these arguments or exceeded limit isn't a decompilation
+        // error, the call is just kept
+        Ok(match emulator.call(&closure, args.to_vec()) {
+            Ok(results) => match results.first() {
+                Some(Value::Str(bytes)) => Some(bytes.to_vec()),
+                _ => None,
+            },
+            Err(_) => None,
+        })
+    }
+}
+
+/// Replaces calls of decoder prototype with constant arguments by the decrypted string.
+/// Decoder is recognized when it is a closure created in the same function or a global
+/// registered with `global`. Values of callee and arguments are tracked inside basic block.
+pub struct StringDecryption {
+    decoder: usize,
+    globals: Vec<String>,
+    evaluator: Box<dyn StringEvaluator>,
+}
+
+impl StringDecryption {
+    /// `decoder` is the dump index of decoder prototype
+    pub fn new<E: StringEvaluator + 'static>(decoder: usize, evaluator: E) -> Self {
+        StringDecryption {
+            decoder,
+            globals: vec![],
+            evaluator: Box::new(evaluator),
+        }
+    }
+
+    /// Calls of global `name` are calls of decoder too
+    pub fn global(mut self, name: &str) -> Self {
+        self.globals.push(name.to_string());
+        self
+    }
+
+    fn is_decoder(&self, proto: &ByteCodeProto, expr: &Expr) -> bool {
+        match expr {
+            Expr::Closure(idx) => proto.child_from_global_table(*idx) == Some(self.decoder),
+            Expr::Table([table, key]) => match (table.as_ref(), key.as_ref()) {
+                (Expr::GlobalTable, Expr::Str(name)) => self.globals.contains(name),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    // replacement of decoder call, `None` when it can't be evaluated
+    fn decrypt(
+        &mut self,
+        insn: &Insn,
+        known: &BTreeMap<u16, Expr>,
+        ctx: &PassContext,
+    ) -> Result<Option<Insn>, DecompileError> {
+        let proto = ctx.proto();
+
+        let (returns, callee, args) = match insn {
+            Insn::Call(returns, args) => match args.split_first() {
+                Some((Expr::Var(callee), args)) => (returns, callee, args),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        match known.get(&callee.0) {
+            Some(expr) if self.is_decoder(proto, expr) => {}
+            _ => return Ok(None),
+        }
+
+        // exactly one result written to the callee slot
+        let result = match returns.as_ref() {
+            [result] if result.0 == callee.0 => result.clone(),
+            _ => return Ok(None),
+        };
+
+        let mut values = Vec::with_capacity(args.len());
+
+        for arg in args {
+            let expr = match arg {
+                Expr::Var(var) => match known.get(&var.0) {
+                    Some(expr) => expr,
+                    None => return Ok(None),
+                },
+                expr => expr,
+            };
+
+            match arg_value(proto, expr) {
+                Some(value) => values.push(value),
+                None => return Ok(None),
+            }
+        }
+
+        let bytes = match self.evaluator.evaluate(ctx.dump, self.decoder, &values)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+
+        // string expressions of IR can't keep arbitrary bytes
+        Ok(String::from_utf8(bytes)
+            .ok()
+            .map(|str| Insn::set_var(result, Expr::str(str))))
+    }
+}
+
+// lifted strings are lossy, raw bytes are taken from matching string constant
+fn raw_string(proto: &ByteCodeProto, str: &str) -> Vec<u8> {
+    proto
+        .global_consts()
+        .iter()
+        .find_map(|global_const| match global_const {
+            GlobalConst::Str(bytes) if String::from_utf8_lossy(bytes) == str => Some(bytes.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| str.as_bytes().to_vec())
+}
+
+fn arg_value(proto: &ByteCodeProto, expr: &Expr) -> Option<Value> {
+    Some(match Const::from_expr(expr, proto.num_consts())? {
+        Const::Nil => Value::Nil,
+        Const::Bool(a) => Value::Bool(a),
+        Const::Num(a) => Value::Num(a),
+        Const::Str(a) => Value::str(&raw_string(proto, &a)),
+    })
+}
+
+// values which can be callee or argument of decoder call
+fn is_tracked(expr: &Expr) -> bool {
+    match expr {
+        Expr::Closure(_) | Expr::Num(_) => true,
+        Expr::Table([table, _]) => matches!(table.as_ref(), Expr::GlobalTable),
+        expr => Const::from_expr(expr, &[]).is_some(),
+    }
+}
+
+impl DeobfuscationPass for StringDecryption {
+    fn name(&self) -> &'static str {
+        "string-decryption"
+    }
+
+    fn run(
+        &mut self,
+        graph: &mut Graph<Block, BranchKind>,
+        ctx: &PassContext,
+    ) -> Result<usize, DecompileError> {
+        let block_indexes: Vec<u32> = graph.nodes().keys().cloned().collect();
+        let mut changed = 0;
+
+        for block_idx in block_indexes {
+            let insns = graph.node_weight_mut(block_idx).unwrap().insns_mut();
+            let mut known: BTreeMap<u16, Expr> = BTreeMap::new();
+
+            for insn in insns.iter_mut() {
+                if let Some(decrypted) = self.decrypt(insn, &known, ctx)? {
+                    *insn = decrypted;
+                    changed += 1;
+                }
+
+                for slot in insn.defs() {
+                    known.remove(&slot);
+                }
+
+                match insn {
+                    Insn::SetVars(vars, expr) if vars.len() == 1 && is_tracked(expr) => {
+                        known.insert(vars[0].0, expr.as_ref().clone());
+                    }
+                    // called function can change captured slots and globals
+                    Insn::Call(_, _) => known.retain(|slot, expr| {
+                        !ctx.captured.contains(slot) && !matches!(expr, Expr::Table(_))
+                    }),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_reader::GlobalConst;
+    use crate::deobfuscation::{
+        run_passes, DeobfuscationPass, EmulatorEvaluator, PassContext, StringDecryption,
+    };
+    use crate::disasm::{ins_abc, ins_ad};
+    use crate::emulator::test_utils::decoder;
+    use crate::emulator::Value;
+    use crate::lifting::Lifter;
+    use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+
+    // v0 = callee; v1 = "ifmmp"; v0 = v0(v1); return v0
+    fn main(callee: u32, global_consts: Vec<GlobalConst>) -> ByteCodeProto {
+        ByteCodeProto::from_parts(
+            0,
+            0,
+            2,
+            vec![],
+            global_consts,
+            vec![],
+            vec![
+                callee,
+                ins_ad(0x27, 1, 1),     // KSTR 1 "ifmmp"
+                ins_abc(0x42, 0, 2, 2), // CALL 0 2 2
+                ins_ad(0x4c, 0, 2),     // RET1 0 2
+            ],
+        )
+        .unwrap()
+    }
+
+    fn lines(dump: &ByteCodeDump, pass: StringDecryption) -> Result<Vec<String>, DecompileError> {
+        let ctx = PassContext::new(dump, 1);
+        let mut graph = Lifter::new().analyze_bc_proto(ctx.proto())?;
+        let mut passes: Vec<Box<dyn DeobfuscationPass>> = vec![Box::new(pass)];
+
+        assert_eq!(run_passes(&mut passes, &mut graph, &ctx)?, 1);
+
+        Ok(graph
+            .node_weight(0)
+            .unwrap()
+            .iter_insn()
+            .map(|insn| insn.to_string())
+            .collect())
+    }
+
+    #[test]
+    fn decrypt_with_emulator() -> Result<(), DecompileError> {
+        let main = main(
+            ins_ad(0x33, 0, 0), // FNEW 0 decoder
+            vec![
+                GlobalConst::Str(b"ifmmp".to_vec()),
+                GlobalConst::ProtoChild(0),
+            ],
+        );
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![decoder(), main]);
+        let pass = StringDecryption::new(0, EmulatorEvaluator::default());
+
+        assert_eq!(
+            lines(&dump, pass)?,
+            vec![
+                "v0 = closure(proto(0))",
+                "v1 = \"ifmmp\"",
+                "v0 = \"hello\"",
+                "return v0",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_global_with_custom_evaluator() -> Result<(), DecompileError> {
+        let main = main(
+            ins_ad(0x36, 0, 0), // GGET 0 "decode"
+            vec![
+                GlobalConst::Str(b"ifmmp".to_vec()),
+                GlobalConst::Str(b"decode".to_vec()),
+            ],
+        );
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![decoder(), main]);
+        let evaluator = |args: &[Value]| match args {
+            [Value::Str(str)] => Some(str.iter().map(|byte| byte - 1).collect()),
+            _ => None,
+        };
+        let pass = StringDecryption::new(0, evaluator).global("decode");
+
+        assert_eq!(lines(&dump, pass)?[2], "v0 = \"hello\"");
+
+        Ok(())
+    }
+}
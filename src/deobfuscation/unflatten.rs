@@ -0,0 +1,360 @@
+use crate::bytecode_reader::NumConst;
+use crate::const_fold::Const;
+use crate::deobfuscation::{DeobfuscationPass, PassContext};
+use crate::ir::{Block, Expr, Insn, ENTRY_BLOCK};
+use crate::resolver::BranchKind;
+use crate::{DecompileError, Graph};
+use std::collections::{BTreeMap, BTreeSet};
+
+// comparisons of one slot with constants needed to treat them as dispatcher
+const MIN_CASES: usize = 2;
+
+/// Node of dispatcher, it only inspects the state slot
+#[derive(Debug)]
+enum DispatchNode {
+    /// `if state == value`, true and false successors
+    Case(Const, u32, u32),
+    /// empty block jumping back to dispatcher
+    Hub(u32),
+}
+
+// `LOOP` of `while true do` is kept in the dispatcher head
+fn is_loop_marker(insn: &Insn) -> bool {
+    matches!(insn, Insn::While(_))
+}
+
+// successors on the true and false edges of conditional block
+fn branch_targets(graph: &Graph<Block, BranchKind>, idx: u32) -> Option<(u32, u32)> {
+    let mut targets = (None, None);
+
+    for edge_idx in graph.outputs(idx) {
+        let edge = graph.edge(edge_idx).unwrap();
+
+        match edge.weight() {
+            BranchKind::True => targets.0 = Some(edge.to()),
+            BranchKind::False => targets.1 = Some(edge.to()),
+            _ => return None,
+        }
+    }
+
+    Some((targets.0?, targets.1?))
+}
+
+// `state == value` comparison ending the block, returns the state slot
+fn case_of(
+    graph: &Graph<Block, BranchKind>,
+    idx: u32,
+    num_consts: &[NumConst],
+) -> Option<(u16, DispatchNode)> {
+    let block = graph.node_weight(idx)?;
+    let insns: Vec<&Insn> = block.iter_insn().collect();
+    let (last, rest) = insns.split_last()?;
+
+    if !rest.iter().all(|insn| is_loop_marker(insn)) {
+        return None;
+    }
+
+    let (slot, value) = match *last {
+        Insn::If(expr) => match expr.as_ref() {
+            Expr::Eq([a, b]) => match (a.as_ref(), b.as_ref()) {
+                (Expr::Var(var), value) | (value, Expr::Var(var)) => {
+                    (var.0, Const::from_expr(value, num_consts)?)
+                }
+                _ => return None,
+            },
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (on_true, on_false) = branch_targets(graph, idx)?;
+    Some((slot, DispatchNode::Case(value, on_true, on_false)))
+}
+
+// single unconditional successor of block without instructions
+fn hub_target(graph: &Graph<Block, BranchKind>, idx: u32) -> Option<u32> {
+    if !graph.node_weight(idx)?.iter_insn().all(is_loop_marker) {
+        return None;
+    }
+
+    let outputs: Vec<u32> = graph.outputs(idx).collect();
+
+    match outputs.as_slice() {
+        [edge_idx] => {
+            let edge = graph.edge(*edge_idx).unwrap();
+            match edge.weight() {
+                BranchKind::Unconditional => Some(edge.to()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Dispatcher of flattened control flow: state slot and blocks which only inspect it
+struct Dispatcher {
+    state: u16,
+    nodes: BTreeMap<u32, DispatchNode>,
+}
+
+impl Dispatcher {
+    fn find(
+        graph: &Graph<Block, BranchKind>,
+        num_consts: &[NumConst],
+        captured: &BTreeSet<u16>,
+    ) -> Option<Dispatcher> {
+        let mut cases: BTreeMap<u16, BTreeMap<u32, DispatchNode>> = BTreeMap::new();
+
+        for &idx in graph.nodes().keys() {
+            if let Some((slot, node)) = case_of(graph, idx, num_consts) {
+                cases.entry(slot).or_default().insert(idx, node);
+            }
+        }
+
+        // captured slot can be changed by any call, its value is unknown
+        cases.retain(|slot, _| !captured.contains(slot));
+
+        // slot compared with constants most often is the state
+        let (state, mut nodes) = cases
+            .into_iter()
+            .max_by_key(|(_, nodes)| nodes.len())
+            .filter(|(_, nodes)| nodes.len() >= MIN_CASES)?;
+
+        // empty blocks jumping into dispatcher join it
+        loop {
+            let hubs: Vec<(u32, u32)> = graph
+                .nodes()
+                .keys()
+                .filter(|idx| !nodes.contains_key(idx))
+                .filter_map(|&idx| Some((idx, hub_target(graph, idx)?)))
+                .filter(|(_, target)| nodes.contains_key(target))
+                .collect();
+
+            if hubs.is_empty() {
+                break;
+            }
+
+            for (idx, target) in hubs {
+                nodes.insert(idx, DispatchNode::Hub(target));
+            }
+        }
+
+        Some(Dispatcher { state, nodes })
+    }
+
+    // block which is reached from dispatcher node with known state, `None` for cycles
+    fn resolve(&self, start: u32, value: &Const) -> Option<u32> {
+        let mut visited = BTreeSet::new();
+        let mut idx = start;
+
+        while let Some(node) = self.nodes.get(&idx) {
+            if !visited.insert(idx) {
+                return None;
+            }
+
+            idx = match node {
+                DispatchNode::Case(case, on_true, on_false) => match case == value {
+                    true => *on_true,
+                    false => *on_false,
+                },
+                DispatchNode::Hub(target) => *target,
+            };
+        }
+
+        Some(idx)
+    }
+
+    // constant assigned to the state by the last write in block
+    fn state_at_exit(&self, block: &Block, num_consts: &[NumConst]) -> Option<Const> {
+        let insn = block
+            .iter_insn()
+            .rev()
+            .find(|insn| insn.defs().contains(&self.state))?;
+
+        match insn {
+            Insn::SetVars(vars, expr) if vars.len() == 1 => Const::from_expr(expr, num_consts),
+            _ => None,
+        }
+    }
+}
+
+/// Removes control flow flattening dispatcher `while true do if state == N then ... end end`.
+/// Every edge entering the dispatcher has to come from block assigning constant to the state,
+/// it is redirected to the case block selected by that constant. Returns graph without
+/// dispatcher blocks or `None` when dispatcher isn't found or some edge can't be resolved.
+/// Stores to the state slot are left for dead store elimination. Slots captured by child
+/// closures aren't treated as state.
+pub fn unflatten(
+    graph: &Graph<Block, BranchKind>,
+    num_consts: &[NumConst],
+    captured: &BTreeSet<u16>,
+) -> Option<Graph<Block, BranchKind>> {
+    let dispatcher = Dispatcher::find(graph, num_consts, captured)?;
+
+    if dispatcher.nodes.contains_key(&ENTRY_BLOCK) {
+        return None;
+    }
+
+    let mut unflattened: Graph<Block, BranchKind> = Graph::new();
+    let mut edges = vec![];
+
+    for (&idx, node) in graph.nodes() {
+        if dispatcher.nodes.contains_key(&idx) {
+            continue;
+        }
+
+        let block = node.weight();
+        let state = dispatcher.state_at_exit(&block, num_consts);
+
+        // outputs are iterated from the last added edge
+        let mut outputs: Vec<u32> = graph.outputs(idx).collect();
+        outputs.reverse();
+
+        for edge_idx in outputs {
+            let edge = graph.edge(edge_idx).unwrap();
+            let mut to = edge.to();
+
+            if dispatcher.nodes.contains_key(&to) {
+                to = dispatcher.resolve(to, state.as_ref()?)?;
+            }
+
+            edges.push((*edge.weight(), idx, to));
+        }
+
+        unflattened.add_node(idx, block);
+    }
+
+    for (kind, from, to) in edges {
+        unflattened.add_edge(kind, from, to);
+    }
+
+    Some(unflattened)
+}
+
+/// Pass removing control flow flattening, see `unflatten`
+pub struct Unflattening;
+
+impl DeobfuscationPass for Unflattening {
+    fn name(&self) -> &'static str {
+        "unflattening"
+    }
+
+    fn run(
+        &mut self,
+        graph: &mut Graph<Block, BranchKind>,
+        ctx: &PassContext,
+    ) -> Result<usize, DecompileError> {
+        let num_consts = ctx.proto().num_consts();
+
+        Ok(match unflatten(graph, num_consts, &ctx.captured) {
+            Some(unflattened) => {
+                let removed = graph.node_count() - unflattened.node_count();
+                *graph = unflattened;
+                removed
+            }
+            None => 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_reader::NumConst;
+    use crate::deobfuscation::unflatten::unflatten;
+    use crate::graph::Graph;
+    use crate::ir::test_utils::block;
+    use crate::ir::{Block, Expr, Insn, Var};
+    use crate::resolver::BranchKind;
+    use std::collections::BTreeSet;
+
+    fn case(value: u16) -> Block {
+        block(vec![Insn::If(Expr::eq(Expr::var(0), Expr::num(value)))])
+    }
+
+    fn edges(graph: &Graph<Block, BranchKind>) -> Vec<(u32, u32)> {
+        let mut edges: Vec<(u32, u32)> = graph
+            .edges()
+            .iter()
+            .map(|edge| (edge.from(), edge.to()))
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    // local s = 1
+    // while true do
+    //   if s == 1 then f(); s = 2
+    //   elseif s == 2 then s = 3
+    //   elseif s == 3 then return end
+    // end
+    fn flattened(state: Box<Expr>) -> Graph<Block, BranchKind> {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+        graph.add_node(0, block(vec![Insn::set_var(Var(0), Expr::short(1))]));
+        graph.add_node(
+            1,
+            block(vec![
+                Insn::While(Expr::var(1)),
+                Insn::If(Expr::eq(Expr::var(0), Expr::num(0))),
+            ]),
+        );
+        graph.add_node(
+            2,
+            block(vec![
+                Insn::Call(
+                    vec![].into_boxed_slice(),
+                    vec![Expr::Var(Var(2))].into_boxed_slice(),
+                ),
+                Insn::set_var(Var(0), state),
+            ]),
+        );
+        graph.add_node(3, case(1));
+        graph.add_node(4, block(vec![Insn::set_var(Var(0), Expr::num(2))]));
+        graph.add_node(5, case(2));
+        graph.add_node(6, block(vec![Insn::Return(vec![].into_boxed_slice())]));
+        graph.add_node(7, block(vec![]));
+
+        graph.add_edge(BranchKind::Unconditional, 0, 1);
+        graph.add_edge(BranchKind::True, 1, 2);
+        graph.add_edge(BranchKind::False, 1, 3);
+        graph.add_edge(BranchKind::Unconditional, 2, 1);
+        graph.add_edge(BranchKind::True, 3, 4);
+        graph.add_edge(BranchKind::False, 3, 5);
+        graph.add_edge(BranchKind::Unconditional, 4, 7);
+        graph.add_edge(BranchKind::True, 5, 6);
+        graph.add_edge(BranchKind::False, 5, 7);
+        graph.add_edge(BranchKind::Unconditional, 7, 1);
+
+        graph
+    }
+
+    fn num_consts() -> Vec<NumConst> {
+        vec![NumConst::Int(1), NumConst::Int(2), NumConst::Int(3)]
+    }
+
+    #[test]
+    fn remove_dispatcher() {
+        let graph = flattened(Expr::short(2));
+        let unflattened = unflatten(&graph, &num_consts(), &BTreeSet::new()).unwrap();
+
+        assert_eq!(
+            unflattened.nodes().keys().cloned().collect::<Vec<u32>>(),
+            vec![0, 2, 4, 6]
+        );
+        assert_eq!(edges(&unflattened), vec![(0, 2), (2, 4), (4, 6)]);
+    }
+
+    #[test]
+    fn keep_unknown_state() {
+        let graph = flattened(Expr::var(5));
+        assert!(unflatten(&graph, &num_consts(), &BTreeSet::new()).is_none());
+
+        // state without matching case spins in dispatcher forever
+        let graph = flattened(Expr::short(7));
+        assert!(unflatten(&graph, &num_consts(), &BTreeSet::new()).is_none());
+
+        // closure capturing the state can change it in `f()`
+        let graph = flattened(Expr::short(2));
+        assert!(unflatten(&graph, &num_consts(), &BTreeSet::from([0])).is_none());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::DecompileError;
 
-use crate::op::Op;
+use crate::op::{op_info, Op, OpMode};
+use crate::types::*;
 
 #[inline(always)]
 fn get_op(ins: u32) -> u8 {
@@ -28,6 +29,12 @@ fn get_d<R: From<u16>>(ins: u32) -> R {
 }
 
 pub fn disasm(ins_raw: u32) -> Result<Op, DecompileError> {
+    // primitive operand is nil, false or true
+    let is_pri = op_info(ins_raw).is_some_and(|info| info.cd == OpMode::Pri);
+    if is_pri && get_d::<u16>(ins_raw) > 2 {
+        return Err(DecompileError::InvalidPriValue);
+    }
+
     Ok(match get_op(ins_raw) {
         0x00 => Op::ISLT(get_a(ins_raw), get_d(ins_raw)),
         0x01 => Op::ISGE(get_a(ins_raw), get_d(ins_raw)),
@@ -126,6 +133,186 @@ pub fn disasm(ins_raw: u32) -> Result<Op, DecompileError> {
         0x5e => Op::JFUNCV(get_a(ins_raw), get_d(ins_raw)),
         0x5f => Op::FUNCC(get_a(ins_raw)),
         0x60 => Op::FUNCCW(get_a(ins_raw)),
-        _ => panic!("Unknown bytecode instruction opcode."),
+        _ => return Err(DecompileError::UnknownInsOpcode),
     })
 }
+
+// raw value of instruction operand field
+trait Operand {
+    fn raw(&self) -> u32;
+}
+
+macro_rules! impl_operand {
+    ($($ty:ty),*) => {
+        $(impl Operand for $ty {
+            #[inline(always)]
+            fn raw(&self) -> u32 {
+                self.0 as u32
+            }
+        })*
+    };
+}
+
+impl_operand!(Var, Dst, Base, RBase, UV, Lit, Str, Num, Tab, Func, CData);
+
+impl Operand for LitS {
+    #[inline(always)]
+    fn raw(&self) -> u32 {
+        self.0 as u16 as u32
+    }
+}
+
+impl Operand for Pri {
+    #[inline(always)]
+    fn raw(&self) -> u32 {
+        match self {
+            Pri::Nil => 0,
+            Pri::False => 1,
+            Pri::True => 2,
+        }
+    }
+}
+
+impl Operand for Jump {
+    #[inline(always)]
+    fn raw(&self) -> u32 {
+        (self.0 as i32 + 0x8000) as u32
+    }
+}
+
+#[inline(always)]
+pub(crate) fn ins_ad(op: u32, a: u32, d: u32) -> u32 {
+    op | (a & 0xff) << 8 | (d & 0xffff) << 16
+}
+
+#[inline(always)]
+pub(crate) fn ins_abc(op: u32, a: u32, b: u32, c: u32) -> u32 {
+    op | (a & 0xff) << 8 | (c & 0xff) << 16 | (b & 0xff) << 24
+}
+
+/// Encodes instruction into raw form, inverse of `disasm`. `Lit` operands keep only 8 bits.
+pub fn asm(op: &Op) -> u32 {
+    match op {
+        Op::ISLT(a, d) => ins_ad(0x00, a.raw(), d.raw()),
+        Op::ISGE(a, d) => ins_ad(0x01, a.raw(), d.raw()),
+        Op::ISLE(a, d) => ins_ad(0x02, a.raw(), d.raw()),
+        Op::ISGT(a, d) => ins_ad(0x03, a.raw(), d.raw()),
+        Op::ISEQV(a, d) => ins_ad(0x04, a.raw(), d.raw()),
+        Op::ISNEV(a, d) => ins_ad(0x05, a.raw(), d.raw()),
+        Op::ISEQS(a, d) => ins_ad(0x06, a.raw(), d.raw()),
+        Op::ISNES(a, d) => ins_ad(0x07, a.raw(), d.raw()),
+        Op::ISEQN(a, d) => ins_ad(0x08, a.raw(), d.raw()),
+        Op::ISNEN(a, d) => ins_ad(0x09, a.raw(), d.raw()),
+        Op::ISEQP(a, d) => ins_ad(0x0a, a.raw(), d.raw()),
+        Op::ISNEP(a, d) => ins_ad(0x0b, a.raw(), d.raw()),
+        Op::ISTC(a, d) => ins_ad(0x0c, a.raw(), d.raw()),
+        Op::ISFC(a, d) => ins_ad(0x0d, a.raw(), d.raw()),
+        Op::IST(d) => ins_ad(0x0e, 0, d.raw()),
+        Op::ISF(d) => ins_ad(0x0f, 0, d.raw()),
+        Op::ISTYPE(a, d) => ins_ad(0x10, a.raw(), d.raw()),
+        Op::ISNUM(a, d) => ins_ad(0x11, a.raw(), d.raw()),
+        Op::MOV(a, d) => ins_ad(0x12, a.raw(), d.raw()),
+        Op::NOT(a, d) => ins_ad(0x13, a.raw(), d.raw()),
+        Op::UNM(a, d) => ins_ad(0x14, a.raw(), d.raw()),
+        Op::LEN(a, d) => ins_ad(0x15, a.raw(), d.raw()),
+        Op::ADDVN(a, b, c) => ins_abc(0x16, a.raw(), b.raw(), c.raw()),
+        Op::SUBVN(a, b, c) => ins_abc(0x17, a.raw(), b.raw(), c.raw()),
+        Op::MULVN(a, b, c) => ins_abc(0x18, a.raw(), b.raw(), c.raw()),
+        Op::DIVVN(a, b, c) => ins_abc(0x19, a.raw(), b.raw(), c.raw()),
+        Op::MODVN(a, b, c) => ins_abc(0x1a, a.raw(), b.raw(), c.raw()),
+        Op::ADDNV(a, b, c) => ins_abc(0x1b, a.raw(), b.raw(), c.raw()),
+        Op::SUBNV(a, b, c) => ins_abc(0x1c, a.raw(), b.raw(), c.raw()),
+        Op::MULNV(a, b, c) => ins_abc(0x1d, a.raw(), b.raw(), c.raw()),
+        Op::DIVNV(a, b, c) => ins_abc(0x1e, a.raw(), b.raw(), c.raw()),
+        Op::MODNV(a, b, c) => ins_abc(0x1f, a.raw(), b.raw(), c.raw()),
+        Op::ADDVV(a, b, c) => ins_abc(0x20, a.raw(), b.raw(), c.raw()),
+        Op::SUBVV(a, b, c) => ins_abc(0x21, a.raw(), b.raw(), c.raw()),
+        Op::MULVV(a, b, c) => ins_abc(0x22, a.raw(), b.raw(), c.raw()),
+        Op::DIVVV(a, b, c) => ins_abc(0x23, a.raw(), b.raw(), c.raw()),
+        Op::MODVV(a, b, c) => ins_abc(0x24, a.raw(), b.raw(), c.raw()),
+        Op::POW(a, b, c) => ins_abc(0x25, a.raw(), b.raw(), c.raw()),
+        Op::CAT(a, b, c) => ins_abc(0x26, a.raw(), b.raw(), c.raw()),
+        Op::KSTR(a, d) => ins_ad(0x27, a.raw(), d.raw()),
+        Op::KCDATA(a, d) => ins_ad(0x28, a.raw(), d.raw()),
+        Op::KSHORT(a, d) => ins_ad(0x29, a.raw(), d.raw()),
+        Op::KNUM(a, d) => ins_ad(0x2a, a.raw(), d.raw()),
+        Op::KPRI(a, d) => ins_ad(0x2b, a.raw(), d.raw()),
+        Op::KNIL(a, d) => ins_ad(0x2c, a.raw(), d.raw()),
+        Op::UGET(a, d) => ins_ad(0x2d, a.raw(), d.raw()),
+        Op::USETV(a, d) => ins_ad(0x2e, a.raw(), d.raw()),
+        Op::USETS(a, d) => ins_ad(0x2f, a.raw(), d.raw()),
+        Op::USETN(a, d) => ins_ad(0x30, a.raw(), d.raw()),
+        Op::USETP(a, d) => ins_ad(0x31, a.raw(), d.raw()),
+        Op::UCLO(a, d) => ins_ad(0x32, a.raw(), d.raw()),
+        Op::FNEW(a, d) => ins_ad(0x33, a.raw(), d.raw()),
+        Op::TNEW(a, d) => ins_ad(0x34, a.raw(), d.raw()),
+        Op::TDUP(a, d) => ins_ad(0x35, a.raw(), d.raw()),
+        Op::GGET(a, d) => ins_ad(0x36, a.raw(), d.raw()),
+        Op::GSET(a, d) => ins_ad(0x37, a.raw(), d.raw()),
+        Op::TGETV(a, b, c) => ins_abc(0x38, a.raw(), b.raw(), c.raw()),
+        Op::TGETS(a, b, c) => ins_abc(0x39, a.raw(), b.raw(), c.raw()),
+        Op::TGETB(a, b, c) => ins_abc(0x3a, a.raw(), b.raw(), c.raw()),
+        Op::TGETR(a, b, c) => ins_abc(0x3b, a.raw(), b.raw(), c.raw()),
+        Op::TSETV(a, b, c) => ins_abc(0x3c, a.raw(), b.raw(), c.raw()),
+        Op::TSETS(a, b, c) => ins_abc(0x3d, a.raw(), b.raw(), c.raw()),
+        Op::TSETB(a, b, c) => ins_abc(0x3e, a.raw(), b.raw(), c.raw()),
+        Op::TSETM(a, d) => ins_ad(0x3f, a.raw(), d.raw()),
+        Op::TSETR(a, b, c) => ins_abc(0x40, a.raw(), b.raw(), c.raw()),
+        Op::CALLM(a, b, c) => ins_abc(0x41, a.raw(), b.raw(), c.raw()),
+        Op::CALL(a, b, c) => ins_abc(0x42, a.raw(), b.raw(), c.raw()),
+        Op::CALLMT(a, d) => ins_ad(0x43, a.raw(), d.raw()),
+        Op::CALLT(a, d) => ins_ad(0x44, a.raw(), d.raw()),
+        Op::ITERC(a, b, c) => ins_abc(0x45, a.raw(), b.raw(), c.raw()),
+        Op::ITERN(a, b, c) => ins_abc(0x46, a.raw(), b.raw(), c.raw()),
+        Op::VARG(a, b, c) => ins_abc(0x47, a.raw(), b.raw(), c.raw()),
+        Op::ISNEXT(a, d) => ins_ad(0x48, a.raw(), d.raw()),
+        Op::RETM(a, d) => ins_ad(0x49, a.raw(), d.raw()),
+        Op::RET(a, d) => ins_ad(0x4a, a.raw(), d.raw()),
+        Op::RET0(a, d) => ins_ad(0x4b, a.raw(), d.raw()),
+        Op::RET1(a, d) => ins_ad(0x4c, a.raw(), d.raw()),
+        Op::FORI(a, d) => ins_ad(0x4d, a.raw(), d.raw()),
+        Op::JFORI(a, d) => ins_ad(0x4e, a.raw(), d.raw()),
+        Op::FORL(a, d) => ins_ad(0x4f, a.raw(), d.raw()),
+        Op::IFORL(a, d) => ins_ad(0x50, a.raw(), d.raw()),
+        Op::JFORL(a, d) => ins_ad(0x51, a.raw(), d.raw()),
+        Op::ITERL(a, d) => ins_ad(0x52, a.raw(), d.raw()),
+        Op::IITERL(a, d) => ins_ad(0x53, a.raw(), d.raw()),
+        Op::JITERL(a, d) => ins_ad(0x54, a.raw(), d.raw()),
+        Op::LOOP(a, d) => ins_ad(0x55, a.raw(), d.raw()),
+        Op::ILOOP(a, d) => ins_ad(0x56, a.raw(), d.raw()),
+        Op::JLOOP(a, d) => ins_ad(0x57, a.raw(), d.raw()),
+        Op::JMP(a, d) => ins_ad(0x58, a.raw(), d.raw()),
+        Op::FUNCF(a) => ins_ad(0x59, a.raw(), 0),
+        Op::IFUNCF(a) => ins_ad(0x5a, a.raw(), 0),
+        Op::JFUNCF(a, d) => ins_ad(0x5b, a.raw(), d.raw()),
+        Op::FUNCV(a) => ins_ad(0x5c, a.raw(), 0),
+        Op::IFUNCV(a) => ins_ad(0x5d, a.raw(), 0),
+        Op::JFUNCV(a, d) => ins_ad(0x5e, a.raw(), d.raw()),
+        Op::FUNCC(a) => ins_ad(0x5f, a.raw(), 0),
+        Op::FUNCCW(a) => ins_ad(0x60, a.raw(), 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::{asm, disasm};
+
+    #[test]
+    fn asm_disasm_round_trip() {
+        for opcode in 0..=0x60u32 {
+            // A = 3, B = 0, C = 2, operands fit every field mode
+            let ins_raw = match opcode {
+                // D operand only
+                0x0e | 0x0f => opcode | 0x0002_0000,
+                // A operand only
+                0x59 | 0x5a | 0x5c | 0x5d | 0x5f | 0x60 => opcode | 0x0300,
+                _ => opcode | 0x0002_0300,
+            };
+
+            assert_eq!(asm(&disasm(ins_raw).unwrap()), ins_raw);
+        }
+
+        // backward jump
+        assert_eq!(asm(&disasm(0x7ffe_0058).unwrap()), 0x7ffe_0058);
+    }
+}
@@ -0,0 +1,1419 @@
+pub mod stdlib;
+pub mod value;
+
+pub use value::{Closure, NativeFn, Table, TableRef, UpValue, Value};
+
+use crate::bytecode_reader::{
+    ConstTableVal, GlobalConst, NumConst, BC_F_FR2, PROTO_UV_IMMUTABLE, PROTO_UV_LOCAL,
+    PROTO_VARARG,
+};
+use crate::instruction::Instruction;
+use crate::op::Op;
+use crate::type_inference::Type;
+use crate::types::Pri;
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// approximate sizes of allocated objects
+const STR_HEADER_SIZE: usize = 24;
+const TABLE_SIZE: usize = 64;
+const TABLE_ENTRY_SIZE: usize = 40;
+const CLOSURE_SIZE: usize = 32;
+const SLOT_SIZE: usize = 24;
+// LUAI_MAXCSTACK, variable number of results above it is a stack overflow
+const MAX_RESULTS: usize = 8000;
+
+/// Limits of emulation, they are shared by all calls made with one emulator
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// executed instructions
+    pub max_steps: u64,
+    /// bytes allocated for strings, tables, closures and frames, only frames are released
+    pub max_memory: usize,
+    /// nested lua and native calls, lua calls don't recurse on the host stack
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: 1_000_000,
+            max_memory: 64 << 20,
+            max_depth: 200,
+        }
+    }
+}
+
+fn error(msg: String) -> DecompileError {
+    DecompileError::Emulation(msg)
+}
+
+fn pri_value(pri: Pri) -> Value {
+    match pri {
+        Pri::Nil => Value::Nil,
+        Pri::False => Value::Bool(false),
+        Pri::True => Value::Bool(true),
+    }
+}
+
+fn const_table_value(val: &ConstTableVal) -> Value {
+    match val {
+        ConstTableVal::Nil => Value::Nil,
+        ConstTableVal::False => Value::Bool(false),
+        ConstTableVal::True => Value::Bool(true),
+        ConstTableVal::Int(a) => Value::Num(*a as i32 as f64),
+        ConstTableVal::Num(lo, hi) => Value::Num(f64::from_bits((*hi as u64) << 32 | *lo as u64)),
+        ConstTableVal::String(a) => Value::str(a),
+    }
+}
+
+// global constants are indexed from the end
+fn global_const(proto: &ByteCodeProto, idx: u16) -> Option<&GlobalConst> {
+    let consts = proto.global_consts();
+    consts.get(consts.len().checked_sub(idx as usize + 1)?)
+}
+
+fn jump(ins: &Instruction) -> Result<usize, DecompileError> {
+    ins.jump_target()
+        .map(|target| target as usize)
+        .ok_or(DecompileError::InvalidJumpTarget)
+}
+
+fn arith(a: &Value, b: &Value, op: fn(f64, f64) -> f64) -> Result<Value, DecompileError> {
+    match (a.to_number(), b.to_number()) {
+        (Some(a), Some(b)) => Ok(Value::Num(op(a, b))),
+        _ => {
+            let bad = if a.to_number().is_none() { a } else { b };
+            Err(error(format!(
+                "attempt to perform arithmetic on a {} value",
+                bad.type_name()
+            )))
+        }
+    }
+}
+
+fn add(a: f64, b: f64) -> f64 {
+    a + b
+}
+
+fn sub(a: f64, b: f64) -> f64 {
+    a - b
+}
+
+fn mul(a: f64, b: f64) -> f64 {
+    a * b
+}
+
+fn div(a: f64, b: f64) -> f64 {
+    a / b
+}
+
+fn modulo(a: f64, b: f64) -> f64 {
+    a - (a / b).floor() * b
+}
+
+fn pow(a: f64, b: f64) -> f64 {
+    a.powf(b)
+}
+
+// `or_equal` selects `<=` instead of `<`
+fn less_than(a: &Value, b: &Value, or_equal: bool) -> Result<bool, DecompileError> {
+    match (a, b) {
+        (Value::Num(a), Value::Num(b)) => Ok(if or_equal { a <= b } else { a < b }),
+        (Value::Str(a), Value::Str(b)) => Ok(if or_equal { a <= b } else { a < b }),
+        _ => Err(error(format!(
+            "attempt to compare {} with {}",
+            a.type_name(),
+            b.type_name()
+        ))),
+    }
+}
+
+fn for_number(value: Value, what: &str) -> Result<f64, DecompileError> {
+    match value {
+        Value::Num(a) => Ok(a),
+        _ => Err(error(format!("'for' {} must be a number", what))),
+    }
+}
+
+fn for_continues(idx: f64, stop: f64, step: f64) -> bool {
+    match step > 0.0 {
+        true => idx <= stop,
+        false => stop <= idx,
+    }
+}
+
+fn value_type(value: &Value) -> Type {
+    match value {
+        Value::Nil => Type::Nil,
+        Value::Bool(_) => Type::Boolean,
+        Value::Num(_) => Type::Number,
+        Value::Str(_) => Type::String,
+        Value::Table(_) => Type::Table,
+        Value::Closure(_) | Value::Native(_) => Type::Function,
+    }
+}
+
+// slot `idx` values after `start`, slots are addressed by 16 bit numbers
+fn slot(start: u16, idx: usize) -> Result<u16, DecompileError> {
+    u16::try_from(idx)
+        .ok()
+        .and_then(|idx| start.checked_add(idx))
+        .ok_or_else(|| error("stack overflow".to_string()))
+}
+
+/// Registers of executed function, every slot is a cell which can be captured as up value
+struct Frame {
+    regs: Vec<UpValue>,
+    varargs: Vec<Value>,
+    // number of values returned by the last `CALL`/`VARG` with variable results
+    multres: usize,
+}
+
+impl Frame {
+    fn get(&self, slot: u16) -> Value {
+        self.regs
+            .get(slot as usize)
+            .map_or(Value::Nil, |cell| cell.borrow().clone())
+    }
+
+    fn cell(&mut self, slot: u16) -> UpValue {
+        let slot = slot as usize;
+
+        if slot >= self.regs.len() {
+            self.regs
+                .resize_with(slot + 1, || Rc::new(RefCell::new(Value::Nil)));
+        }
+
+        self.regs[slot].clone()
+    }
+
+    fn set(&mut self, slot: u16, value: Value) {
+        *self.cell(slot).borrow_mut() = value;
+    }
+
+    fn range(&self, start: u16, count: usize) -> Result<Vec<Value>, DecompileError> {
+        (0..count)
+            .map(|idx| Ok(self.get(slot(start, idx)?)))
+            .collect()
+    }
+
+    // `fixed` is the number of stored values, variable number of values sets `multres`
+    fn store(
+        &mut self,
+        start: u16,
+        values: Vec<Value>,
+        fixed: Option<usize>,
+    ) -> Result<(), DecompileError> {
+        let count = match fixed {
+            Some(count) => count,
+            None if values.len() > MAX_RESULTS => return Err(error("stack overflow".to_string())),
+            None => {
+                self.multres = values.len();
+                values.len()
+            }
+        };
+
+        let mut values = values.into_iter();
+        for idx in 0..count {
+            self.set(slot(start, idx)?, values.next().unwrap_or(Value::Nil));
+        }
+
+        Ok(())
+    }
+
+    // closures created before keep old cells, slots get the fresh ones
+    fn close(&mut self, from: u16) {
+        for cell in self.regs.iter_mut().skip(from as usize) {
+            let value = cell.borrow().clone();
+            *cell = Rc::new(RefCell::new(value));
+        }
+    }
+}
+
+/// Lua function on the emulator call stack
+struct Activation {
+    closure: Rc<Closure>,
+    instructions: Rc<[Instruction]>,
+    frame: Frame,
+    pc: usize,
+    // charged frame memory, it is released on return
+    size: usize,
+    // caller slot and fixed number of results
+    ret_base: u16,
+    ret_fixed: Option<usize>,
+}
+
+/// Control transfer which leaves the executed function
+enum Transfer {
+    Call(Value, Vec<Value>, u16, Option<usize>),
+    TailCall(Value, Vec<Value>),
+    Return(Vec<Value>),
+}
+
+/// Sandboxed interpreter of dump prototypes. Globals are stubs configured by user, tables
+/// have no metatables and execution is stopped when any of `Limits` is exceeded.
+pub struct Emulator<'a> {
+    dump: &'a ByteCodeDump,
+    globals: TableRef,
+    limits: Limits,
+    steps: u64,
+    memory: usize,
+    depth: usize,
+    // extra frame link slot of two-slot frame mode shifts call arguments
+    fr2: u16,
+    decoded: HashMap<usize, Rc<[Instruction]>>,
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(dump: &'a ByteCodeDump, limits: Limits) -> Self {
+        Emulator {
+            dump,
+            globals: Rc::new(RefCell::new(Table::default())),
+            limits,
+            steps: 0,
+            memory: 0,
+            depth: 0,
+            fr2: (dump.flags() & BC_F_FR2 != 0) as u16,
+            decoded: HashMap::new(),
+        }
+    }
+
+    pub fn globals(&self) -> &TableRef {
+        &self.globals
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) -> Result<(), DecompileError> {
+        self.globals
+            .borrow_mut()
+            .set(Value::str(name.as_bytes()), value)?;
+        Ok(())
+    }
+
+    /// Adds deterministic subset of standard library, see `stdlib::open`
+    pub fn open_stdlib(&mut self) -> Result<(), DecompileError> {
+        stdlib::open(&self.globals)
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory
+    }
+
+    /// Accounts allocation of `bytes`, native stubs should call it before allocating
+    pub fn charge(&mut self, bytes: usize) -> Result<(), DecompileError> {
+        self.memory = self.memory.saturating_add(bytes);
+
+        match self.memory > self.limits.max_memory {
+            true => Err(DecompileError::MemoryLimitExceeded),
+            false => Ok(()),
+        }
+    }
+
+    /// Allocates string value
+    pub fn new_str(&mut self, bytes: Vec<u8>) -> Result<Value, DecompileError> {
+        self.charge(STR_HEADER_SIZE + bytes.len())?;
+        Ok(Value::Str(bytes.into()))
+    }
+
+    /// Allocates empty table
+    pub fn new_table(&mut self) -> Result<TableRef, DecompileError> {
+        self.charge(TABLE_SIZE)?;
+        Ok(Rc::new(RefCell::new(Table::default())))
+    }
+
+    /// Stores value into table, new entries are accounted
+    pub fn table_set(
+        &mut self,
+        table: &TableRef,
+        key: Value,
+        value: Value,
+    ) -> Result<(), DecompileError> {
+        if table.borrow_mut().set(key, value)? {
+            self.charge(TABLE_ENTRY_SIZE)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates closure of prototype with passed up values, missing up values are nil
+    pub fn closure(
+        &mut self,
+        proto_idx: usize,
+        up_values: Vec<Value>,
+    ) -> Result<Value, DecompileError> {
+        let proto = self
+            .dump
+            .prototypes()
+            .get(proto_idx)
+            .ok_or(DecompileError::InvalidProtoChild)?;
+
+        let mut up_values = up_values.into_iter();
+        let up_values = (0..proto.up_values().len())
+            .map(|_| Rc::new(RefCell::new(up_values.next().unwrap_or(Value::Nil))))
+            .collect();
+
+        self.new_closure(proto_idx, up_values)
+    }
+
+    /// Runs main chunk of the dump, it is the last prototype
+    pub fn run(&mut self, args: Vec<Value>) -> Result<Vec<Value>, DecompileError> {
+        let main = self
+            .dump
+            .prototypes()
+            .len()
+            .checked_sub(1)
+            .ok_or(DecompileError::InvalidProtoChild)?;
+
+        let closure = self.closure(main, vec![])?;
+        self.call(&closure, args)
+    }
+
+    /// Calls lua closure or native stub
+    pub fn call(&mut self, func: &Value, args: Vec<Value>) -> Result<Vec<Value>, DecompileError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(DecompileError::CallDepthExceeded);
+        }
+
+        self.depth += 1;
+
+        let result = match func {
+            Value::Closure(closure) => self.execute(closure.clone(), args),
+            Value::Native(native) => native.clone()(self, args),
+            _ => Err(error(format!(
+                "attempt to call a {} value",
+                func.type_name()
+            ))),
+        };
+
+        self.depth -= 1;
+        result
+    }
+
+    fn new_closure(
+        &mut self,
+        proto: usize,
+        up_values: Vec<UpValue>,
+    ) -> Result<Value, DecompileError> {
+        self.charge(CLOSURE_SIZE + up_values.len() * SLOT_SIZE)?;
+        Ok(Value::Closure(Rc::new(Closure { proto, up_values })))
+    }
+
+    fn instructions(&mut self, proto_idx: usize) -> Result<Rc<[Instruction]>, DecompileError> {
+        if let Some(instructions) = self.decoded.get(&proto_idx) {
+            return Ok(instructions.clone());
+        }
+
+        let proto = &self.dump.prototypes()[proto_idx];
+        let instructions: Rc<[Instruction]> = Instruction::decode_all(0, proto.bc_raw())?.into();
+        self.decoded.insert(proto_idx, instructions.clone());
+
+        Ok(instructions)
+    }
+
+    fn step(&mut self) -> Result<(), DecompileError> {
+        self.steps += 1;
+
+        match self.steps > self.limits.max_steps {
+            true => Err(DecompileError::StepLimitExceeded),
+            false => Ok(()),
+        }
+    }
+
+    fn index(&mut self, object: &Value, key: &Value) -> Result<Value, DecompileError> {
+        match object {
+            Value::Table(table) => Ok(table.borrow().get(key)),
+            // strings share `string` library as their metatable index
+            Value::Str(_) => match self.globals.borrow().get_str("string") {
+                Value::Table(string) => Ok(string.borrow().get(key)),
+                _ => Ok(Value::Nil),
+            },
+            _ => Err(error(format!(
+                "attempt to index a {} value",
+                object.type_name()
+            ))),
+        }
+    }
+
+    fn set_index(
+        &mut self,
+        object: &Value,
+        key: Value,
+        value: Value,
+    ) -> Result<(), DecompileError> {
+        match object {
+            Value::Table(table) => self.table_set(table, key, value),
+            _ => Err(error(format!(
+                "attempt to index a {} value",
+                object.type_name()
+            ))),
+        }
+    }
+
+    fn concat(&mut self, values: Vec<Value>) -> Result<Value, DecompileError> {
+        let mut bytes = vec![];
+
+        for value in values {
+            match value.to_bytes() {
+                Some(piece) => bytes.extend_from_slice(&piece),
+                None => {
+                    return Err(error(format!(
+                        "attempt to concatenate a {} value",
+                        value.type_name()
+                    )))
+                }
+            }
+        }
+
+        self.new_str(bytes)
+    }
+
+    fn activate(
+        &mut self,
+        closure: Rc<Closure>,
+        args: Vec<Value>,
+        ret_base: u16,
+        ret_fixed: Option<usize>,
+    ) -> Result<Activation, DecompileError> {
+        let proto = &self.dump.prototypes()[closure.proto];
+        let instructions = self.instructions(closure.proto)?;
+
+        let frame_size = (proto.frame_size() as usize).max(1);
+        let size = frame_size * SLOT_SIZE;
+        let num_params = proto.num_params() as usize;
+        let is_vararg = proto.flags() & PROTO_VARARG != 0;
+        self.charge(size)?;
+
+        let mut args = args.into_iter();
+
+        let mut frame = Frame {
+            regs: vec![],
+            varargs: vec![],
+            multres: 0,
+        };
+
+        for slot in 0..frame_size {
+            let value = match slot < num_params {
+                true => args.next().unwrap_or(Value::Nil),
+                false => Value::Nil,
+            };
+            frame.regs.push(Rc::new(RefCell::new(value)));
+        }
+
+        if is_vararg {
+            frame.varargs = args.collect();
+        }
+
+        Ok(Activation {
+            closure,
+            instructions,
+            frame,
+            pc: 0,
+            size,
+            ret_base,
+            ret_fixed,
+        })
+    }
+
+    fn release(&mut self, bytes: usize) {
+        self.memory = self.memory.saturating_sub(bytes);
+    }
+
+    fn execute(
+        &mut self,
+        closure: Rc<Closure>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, DecompileError> {
+        let depth = self.depth;
+        let mut stack = vec![self.activate(closure, args, 0, None)?];
+
+        let result = self.execute_stack(&mut stack);
+
+        // frames left by error are released too
+        for activation in stack {
+            self.release(activation.size);
+        }
+        self.depth = depth;
+
+        result
+    }
+
+    // lua to lua calls are pushed on the stack, native stubs are called directly
+    fn execute_stack(&mut self, stack: &mut Vec<Activation>) -> Result<Vec<Value>, DecompileError> {
+        loop {
+            let transfer = self.interpret(stack.last_mut().unwrap())?;
+
+            let (values, ret_base, ret_fixed) = match transfer {
+                Transfer::Call(Value::Closure(closure), args, base, fixed) => {
+                    if self.depth >= self.limits.max_depth {
+                        return Err(DecompileError::CallDepthExceeded);
+                    }
+
+                    self.depth += 1;
+                    stack.push(self.activate(closure, args, base, fixed)?);
+                    continue;
+                }
+                Transfer::Call(func, args, base, fixed) => {
+                    let results = self.call(&func, args)?;
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .frame
+                        .store(base, results, fixed)?;
+                    continue;
+                }
+                Transfer::TailCall(Value::Closure(closure), args) => {
+                    let caller = stack.pop().unwrap();
+                    self.release(caller.size);
+                    stack.push(self.activate(closure, args, caller.ret_base, caller.ret_fixed)?);
+                    continue;
+                }
+                Transfer::TailCall(func, args) => {
+                    let results = self.call(&func, args)?;
+                    let caller = stack.pop().unwrap();
+                    self.release(caller.size);
+                    (results, caller.ret_base, caller.ret_fixed)
+                }
+                Transfer::Return(values) => {
+                    let caller = stack.pop().unwrap();
+                    self.release(caller.size);
+                    (values, caller.ret_base, caller.ret_fixed)
+                }
+            };
+
+            match stack.last_mut() {
+                Some(activation) => {
+                    self.depth -= 1;
+                    activation.frame.store(ret_base, values, ret_fixed)?;
+                }
+                None => return Ok(values),
+            }
+        }
+    }
+
+    // runs instructions of the function until it calls or returns
+    fn interpret(&mut self, activation: &mut Activation) -> Result<Transfer, DecompileError> {
+        let dump = self.dump;
+        let Activation {
+            closure,
+            instructions,
+            frame,
+            pc: saved_pc,
+            size,
+            ..
+        } = activation;
+        let proto = &dump.prototypes()[closure.proto];
+        let num_consts = proto.num_consts();
+        let fr2 = self.fr2;
+
+        let num = |idx: u16| -> Result<f64, DecompileError> {
+            num_consts
+                .get(idx as usize)
+                .map(|num| num.to_f64())
+                .ok_or_else(|| error(format!("number constant {} is out of range", idx)))
+        };
+        let str = |idx: u16| -> Result<Value, DecompileError> {
+            proto
+                .bytes_from_global_table(idx)
+                .map(Value::str)
+                .ok_or_else(|| error(format!("string constant {} is out of range", idx)))
+        };
+        let up_value = |idx: u16| -> Result<&UpValue, DecompileError> {
+            closure
+                .up_values
+                .get(idx as usize)
+                .ok_or_else(|| error(format!("up value {} is out of range", idx)))
+        };
+
+        let mut pc = *saved_pc;
+
+        let transfer = loop {
+            self.step()?;
+
+            // slots above frame size are charged when frame grows
+            let frame_bytes = frame.regs.len() * SLOT_SIZE;
+            if frame_bytes > *size {
+                self.charge(frame_bytes - *size)?;
+                *size = frame_bytes;
+            }
+
+            let ins = *instructions
+                .get(pc)
+                .ok_or_else(|| error(format!("pc {} is out of bytecode", pc)))?;
+            pc += 1;
+
+            match ins.op {
+                // comparisons are followed by JMP which is taken when condition holds
+                Op::ISLT(a, d) | Op::ISGE(a, d) | Op::ISLE(a, d) | Op::ISGT(a, d) => {
+                    let or_equal = matches!(ins.op, Op::ISLE(..) | Op::ISGT(..));
+                    let negated = matches!(ins.op, Op::ISGE(..) | Op::ISGT(..));
+
+                    if less_than(&frame.get(a.0), &frame.get(d.0), or_equal)? == negated {
+                        pc += 1;
+                    }
+                }
+                Op::ISEQV(a, d) | Op::ISNEV(a, d) => {
+                    let equal = frame.get(a.0).raw_eq(&frame.get(d.0));
+                    if equal != matches!(ins.op, Op::ISEQV(..)) {
+                        pc += 1;
+                    }
+                }
+                Op::ISEQS(a, d) | Op::ISNES(a, d) => {
+                    let equal = frame.get(a.0).raw_eq(&str(d.0)?);
+                    if equal != matches!(ins.op, Op::ISEQS(..)) {
+                        pc += 1;
+                    }
+                }
+                Op::ISEQN(a, d) | Op::ISNEN(a, d) => {
+                    let equal = frame.get(a.0).raw_eq(&Value::Num(num(d.0)?));
+                    if equal != matches!(ins.op, Op::ISEQN(..)) {
+                        pc += 1;
+                    }
+                }
+                Op::ISEQP(a, d) | Op::ISNEP(a, d) => {
+                    let equal = frame.get(a.0).raw_eq(&pri_value(d));
+                    if equal != matches!(ins.op, Op::ISEQP(..)) {
+                        pc += 1;
+                    }
+                }
+                Op::ISTC(a, d) | Op::ISFC(a, d) => {
+                    let value = frame.get(d.0);
+
+                    if value.is_truthy() == matches!(ins.op, Op::ISTC(..)) {
+                        frame.set(a.0, value);
+                    } else {
+                        pc += 1;
+                    }
+                }
+                Op::IST(d) | Op::ISF(d) => {
+                    if frame.get(d.0).is_truthy() != matches!(ins.op, Op::IST(..)) {
+                        pc += 1;
+                    }
+                }
+                Op::ISTYPE(a, d) => {
+                    let expected = Type::from_type_tag(d.0);
+                    let value = frame.get(a.0);
+
+                    if expected != Type::Unknown && value_type(&value) != expected {
+                        return Err(error(format!(
+                            "{} expected, got {}",
+                            expected,
+                            value.type_name()
+                        )));
+                    }
+                }
+                Op::ISNUM(a, _) => {
+                    let value = frame.get(a.0);
+
+                    if !matches!(value, Value::Num(_)) {
+                        return Err(error(format!("number expected, got {}", value.type_name())));
+                    }
+                }
+                // unary
+                Op::MOV(a, d) => frame.set(a.0, frame.get(d.0)),
+                Op::NOT(a, d) => frame.set(a.0, Value::Bool(!frame.get(d.0).is_truthy())),
+                Op::UNM(a, d) => {
+                    let value = arith(&frame.get(d.0), &Value::Num(-1.0), mul)?;
+                    frame.set(a.0, value);
+                }
+                Op::LEN(a, d) => {
+                    let len = match frame.get(d.0) {
+                        Value::Str(str) => str.len(),
+                        Value::Table(table) => table.borrow().len(),
+                        value => {
+                            return Err(error(format!(
+                                "attempt to get length of a {} value",
+                                value.type_name()
+                            )))
+                        }
+                    };
+                    frame.set(a.0, Value::Num(len as f64));
+                }
+                // binary
+                Op::ADDVN(a, b, c)
+                | Op::SUBVN(a, b, c)
+                | Op::MULVN(a, b, c)
+                | Op::DIVVN(a, b, c)
+                | Op::MODVN(a, b, c) => {
+                    let op = match ins.op {
+                        Op::ADDVN(..) => add,
+                        Op::SUBVN(..) => sub,
+                        Op::MULVN(..) => mul,
+                        Op::DIVVN(..) => div,
+                        _ => modulo,
+                    };
+                    let value = arith(&frame.get(b.0), &Value::Num(num(c.0)?), op)?;
+                    frame.set(a.0, value);
+                }
+                Op::ADDNV(a, b, c)
+                | Op::SUBNV(a, b, c)
+                | Op::MULNV(a, b, c)
+                | Op::DIVNV(a, b, c)
+                | Op::MODNV(a, b, c) => {
+                    let op = match ins.op {
+                        Op::ADDNV(..) => add,
+                        Op::SUBNV(..) => sub,
+                        Op::MULNV(..) => mul,
+                        Op::DIVNV(..) => div,
+                        _ => modulo,
+                    };
+                    let value = arith(&Value::Num(num(c.0)?), &frame.get(b.0), op)?;
+                    frame.set(a.0, value);
+                }
+                Op::ADDVV(a, b, c)
+                | Op::SUBVV(a, b, c)
+                | Op::MULVV(a, b, c)
+                | Op::DIVVV(a, b, c)
+                | Op::MODVV(a, b, c)
+                | Op::POW(a, b, c) => {
+                    let op = match ins.op {
+                        Op::ADDVV(..) => add,
+                        Op::SUBVV(..) => sub,
+                        Op::MULVV(..) => mul,
+                        Op::DIVVV(..) => div,
+                        Op::MODVV(..) => modulo,
+                        _ => pow,
+                    };
+                    let value = arith(&frame.get(b.0), &frame.get(c.0), op)?;
+                    frame.set(a.0, value);
+                }
+                Op::CAT(a, b, c) => {
+                    let values = frame.range(b.0, (c.0 + 1).saturating_sub(b.0) as usize)?;
+                    let value = self.concat(values)?;
+                    frame.set(a.0, value);
+                }
+                // constants
+                Op::KSTR(a, d) => frame.set(a.0, str(d.0)?),
+                Op::KCDATA(_, _) => return Err(error("cdata isn't supported".to_string())),
+                Op::KSHORT(a, d) => frame.set(a.0, Value::Num(d.0 as f64)),
+                Op::KNUM(a, d) => frame.set(a.0, Value::Num(num(d.0)?)),
+                Op::KPRI(a, d) => frame.set(a.0, pri_value(d)),
+                Op::KNIL(a, d) => {
+                    for slot in a.0..=d.0 {
+                        frame.set(slot, Value::Nil);
+                    }
+                }
+                // up values and closures
+                Op::UGET(a, d) => frame.set(a.0, up_value(d.0)?.borrow().clone()),
+                Op::USETV(a, d) => *up_value(a.0)?.borrow_mut() = frame.get(d.0),
+                Op::USETS(a, d) => *up_value(a.0)?.borrow_mut() = str(d.0)?,
+                Op::USETN(a, d) => *up_value(a.0)?.borrow_mut() = Value::Num(num(d.0)?),
+                Op::USETP(a, d) => *up_value(a.0)?.borrow_mut() = pri_value(d),
+                Op::UCLO(a, _) => {
+                    frame.close(a.0);
+                    pc = jump(&ins)?;
+                }
+                Op::FNEW(a, d) => {
+                    let child = match global_const(proto, d.0) {
+                        Some(GlobalConst::ProtoChild(child)) => *child,
+                        _ => return Err(DecompileError::InvalidProtoChild),
+                    };
+
+                    let child_proto = dump
+                        .prototypes()
+                        .get(child)
+                        .ok_or(DecompileError::InvalidProtoChild)?;
+
+                    let mut up_values = Vec::with_capacity(child_proto.up_values().len());
+                    for &uv in child_proto.up_values() {
+                        let idx = uv & !(PROTO_UV_LOCAL | PROTO_UV_IMMUTABLE);
+
+                        up_values.push(match uv & PROTO_UV_LOCAL != 0 {
+                            true => frame.cell(idx),
+                            false => up_value(idx)?.clone(),
+                        });
+                    }
+
+                    let value = self.new_closure(child, up_values)?;
+                    frame.set(a.0, value);
+                }
+                // tables
+                Op::TNEW(a, _) => {
+                    let table = self.new_table()?;
+                    frame.set(a.0, Value::Table(table));
+                }
+                Op::TDUP(a, d) => {
+                    let template = match global_const(proto, d.0) {
+                        Some(GlobalConst::Table(template)) => template,
+                        _ => return Err(error(format!("table constant {} is out of range", d.0))),
+                    };
+
+                    let table = self.new_table()?;
+
+                    // array part starts from index 0
+                    for (idx, val) in template.array().iter().enumerate() {
+                        self.table_set(&table, Value::Num(idx as f64), const_table_value(val))?;
+                    }
+
+                    for (key, val) in template.hash() {
+                        self.table_set(&table, const_table_value(key), const_table_value(val))?;
+                    }
+
+                    frame.set(a.0, Value::Table(table));
+                }
+                Op::GGET(a, d) => {
+                    let value = self.globals.borrow().get(&str(d.0)?);
+                    frame.set(a.0, value);
+                }
+                Op::GSET(a, d) => {
+                    let globals = self.globals.clone();
+                    self.table_set(&globals, str(d.0)?, frame.get(a.0))?;
+                }
+                Op::TGETV(a, b, c) | Op::TGETR(a, b, c) => {
+                    let value = self.index(&frame.get(b.0), &frame.get(c.0))?;
+                    frame.set(a.0, value);
+                }
+                Op::TGETS(a, b, c) => {
+                    let value = self.index(&frame.get(b.0), &str(c.0)?)?;
+                    frame.set(a.0, value);
+                }
+                Op::TGETB(a, b, c) => {
+                    let value = self.index(&frame.get(b.0), &Value::Num(c.0 as f64))?;
+                    frame.set(a.0, value);
+                }
+                Op::TSETV(a, b, c) | Op::TSETR(a, b, c) => {
+                    self.set_index(&frame.get(b.0), frame.get(c.0), frame.get(a.0))?
+                }
+                Op::TSETS(a, b, c) => self.set_index(&frame.get(b.0), str(c.0)?, frame.get(a.0))?,
+                Op::TSETB(a, b, c) => {
+                    self.set_index(&frame.get(b.0), Value::Num(c.0 as f64), frame.get(a.0))?
+                }
+                Op::TSETM(a, d) => {
+                    // start index is kept in the low word of number constant
+                    let start = match num_consts.get(d.0 as usize) {
+                        Some(NumConst::Num(lo, _)) => *lo as f64,
+                        Some(NumConst::Int(int)) => *int as f64,
+                        None => {
+                            return Err(error(format!("number constant {} is out of range", d.0)))
+                        }
+                    };
+
+                    let table = frame.get(a.0.wrapping_sub(1));
+                    for (idx, value) in frame.range(a.0, frame.multres)?.into_iter().enumerate() {
+                        self.set_index(&table, Value::Num(start + idx as f64), value)?;
+                    }
+                }
+                // calls and varargs
+                Op::CALL(a, b, c) | Op::CALLM(a, b, c) => {
+                    let count = match ins.op {
+                        Op::CALLM(..) => c.0 as usize + frame.multres,
+                        _ => (c.0 as usize).saturating_sub(1),
+                    };
+
+                    let args = frame.range(a.0 + 1 + fr2, count)?;
+                    let fixed = (b.0 as usize).checked_sub(1);
+                    break Transfer::Call(frame.get(a.0), args, a.0, fixed);
+                }
+                Op::CALLT(a, d) | Op::CALLMT(a, d) => {
+                    let count = match ins.op {
+                        Op::CALLMT(..) => d.0 as usize + frame.multres,
+                        _ => (d.0 as usize).saturating_sub(1),
+                    };
+
+                    let args = frame.range(a.0 + 1 + fr2, count)?;
+                    break Transfer::TailCall(frame.get(a.0), args);
+                }
+                Op::ITERC(a, b, _) | Op::ITERN(a, b, _) => {
+                    let base = a.0;
+                    let (func, state, control) = (
+                        frame.get(base.wrapping_sub(3)),
+                        frame.get(base.wrapping_sub(2)),
+                        frame.get(base.wrapping_sub(1)),
+                    );
+
+                    frame.set(base, func.clone());
+                    frame.set(base + 1 + fr2, state.clone());
+                    frame.set(base + 2 + fr2, control.clone());
+
+                    let fixed = (b.0 as usize).checked_sub(1);
+                    break Transfer::Call(func, vec![state, control], base, fixed);
+                }
+                Op::VARG(a, b, _) => {
+                    let fixed = (b.0 as usize).checked_sub(1);
+                    frame.store(a.0, frame.varargs.clone(), fixed)?;
+                }
+                // iterator specialization is checked by `ITERN` itself
+                Op::ISNEXT(_, _) => pc = jump(&ins)?,
+                // returns
+                Op::RETM(a, d) => {
+                    break Transfer::Return(frame.range(a.0, d.0 as usize + frame.multres)?)
+                }
+                Op::RET(a, d) => {
+                    break Transfer::Return(frame.range(a.0, (d.0 as usize).saturating_sub(1))?)
+                }
+                Op::RET0(_, _) => break Transfer::Return(vec![]),
+                Op::RET1(a, _) => break Transfer::Return(vec![frame.get(a.0)]),
+                // loops
+                Op::FORI(a, _) | Op::JFORI(a, _) => {
+                    let idx = for_number(frame.get(a.0), "initial value")?;
+                    let stop = for_number(frame.get(a.0 + 1), "limit")?;
+                    let step = for_number(frame.get(a.0 + 2), "step")?;
+
+                    if for_continues(idx, stop, step) {
+                        frame.set(a.0 + 3, Value::Num(idx));
+                    } else {
+                        pc = jump(&ins)?;
+                    }
+                }
+                Op::FORL(a, _) | Op::IFORL(a, _) => {
+                    let step = for_number(frame.get(a.0 + 2), "step")?;
+                    let idx = for_number(frame.get(a.0), "initial value")? + step;
+                    let stop = for_number(frame.get(a.0 + 1), "limit")?;
+
+                    frame.set(a.0, Value::Num(idx));
+
+                    if for_continues(idx, stop, step) {
+                        frame.set(a.0 + 3, Value::Num(idx));
+                        pc = jump(&ins)?;
+                    }
+                }
+                Op::ITERL(a, _) | Op::IITERL(a, _) => {
+                    let value = frame.get(a.0);
+
+                    if !matches!(value, Value::Nil) {
+                        frame.set(a.0.wrapping_sub(1), value);
+                        pc = jump(&ins)?;
+                    }
+                }
+                Op::LOOP(_, _) | Op::ILOOP(_, _) => {}
+                Op::JMP(_, _) => pc = jump(&ins)?,
+                // traces and function headers never appear in dumps
+                Op::JFORL(..)
+                | Op::JITERL(..)
+                | Op::JLOOP(..)
+                | Op::FUNCF(..)
+                | Op::IFUNCF(..)
+                | Op::JFUNCF(..)
+                | Op::FUNCV(..)
+                | Op::IFUNCV(..)
+                | Op::JFUNCV(..)
+                | Op::FUNCC(..)
+                | Op::FUNCCW(..) => return Err(DecompileError::UnexpectedInsOpcode),
+            }
+        };
+
+        *saved_pc = pc;
+        Ok(transfer)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use crate::bytecode_reader::{GlobalConst, NumConst};
+    use crate::disasm::{ins_abc, ins_ad};
+    use crate::ByteCodeProto;
+
+    // local function decrypt(s)
+    //   local r = ""
+    //   for i = 1, #s do r = r .. string.char(string.byte(s, i) - 1) end
+    //   return r
+    // end
+    pub fn decoder() -> ByteCodeProto {
+        ByteCodeProto::from_parts(
+            0,
+            1,
+            11,
+            vec![],
+            vec![
+                GlobalConst::Str(b"byte".to_vec()),
+                GlobalConst::Str(b"char".to_vec()),
+                GlobalConst::Str(b"string".to_vec()),
+                GlobalConst::Str(vec![]),
+            ],
+            vec![NumConst::Int(1)],
+            vec![
+                ins_ad(0x27, 1, 0),      // KSTR 1 ""
+                ins_ad(0x29, 2, 1),      // KSHORT 2 1
+                ins_ad(0x15, 3, 0),      // LEN 3 0
+                ins_ad(0x29, 4, 1),      // KSHORT 4 1
+                ins_ad(0x4d, 2, 0x800c), // FORI 2 => 17
+                ins_ad(0x12, 6, 1),      // MOV 6 1
+                ins_ad(0x36, 7, 1),      // GGET 7 "string"
+                ins_abc(0x39, 7, 7, 2),  // TGETS 7 7 "char"
+                ins_ad(0x36, 8, 1),      // GGET 8 "string"
+                ins_abc(0x39, 8, 8, 3),  // TGETS 8 8 "byte"
+                ins_ad(0x12, 9, 0),      // MOV 9 0
+                ins_ad(0x12, 10, 5),     // MOV 10 5
+                ins_abc(0x42, 8, 2, 3),  // CALL 8 2 3
+                ins_abc(0x17, 8, 8, 0),  // SUBVN 8 8 1
+                ins_abc(0x42, 7, 2, 2),  // CALL 7 2 2
+                ins_abc(0x26, 1, 6, 7),  // CAT 1 6 7
+                ins_ad(0x4f, 2, 0x7ff4), // FORL 2 => 5
+                ins_ad(0x4c, 1, 2),      // RET1 1 2
+            ],
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_reader::{GlobalConst, NumConst, PROTO_UV_LOCAL};
+    use crate::disasm::{ins_abc, ins_ad};
+    use crate::emulator::test_utils::decoder;
+    use crate::emulator::{Emulator, Limits, Table, Value};
+    use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+
+    fn proto(
+        num_params: u8,
+        frame_size: u8,
+        up_values: Vec<u16>,
+        global_consts: Vec<GlobalConst>,
+        bc_raw: Vec<u32>,
+    ) -> ByteCodeProto {
+        let num_consts = vec![NumConst::Int(1)];
+        ByteCodeProto::from_parts(
+            0,
+            num_params,
+            frame_size,
+            up_values,
+            global_consts,
+            num_consts,
+            bc_raw,
+        )
+        .unwrap()
+    }
+
+    fn run(prototypes: Vec<ByteCodeProto>, limits: Limits) -> Result<Vec<Value>, DecompileError> {
+        let dump = ByteCodeDump::from_prototypes(0, vec![], prototypes);
+        let mut emulator = Emulator::new(&dump, limits);
+        emulator.open_stdlib()?;
+        emulator.run(vec![])
+    }
+
+    fn str(value: &Value) -> String {
+        match value {
+            Value::Str(str) => String::from_utf8_lossy(str).into_owned(),
+            value => panic!("string expected, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn decrypt_string() -> Result<(), DecompileError> {
+        // return decrypt("ifmmp")
+        let main = proto(
+            0,
+            2,
+            vec![],
+            vec![
+                GlobalConst::Str(b"ifmmp".to_vec()),
+                GlobalConst::ProtoChild(0),
+            ],
+            vec![
+                ins_ad(0x33, 0, 0),     // FNEW 0 decrypt
+                ins_ad(0x27, 1, 1),     // KSTR 1 "ifmmp"
+                ins_abc(0x42, 0, 2, 2), // CALL 0 2 2
+                ins_ad(0x4c, 0, 2),     // RET1 0 2
+            ],
+        );
+
+        let result = run(vec![decoder(), main], Limits::default())?;
+        assert_eq!(result.len(), 1);
+        assert_eq!(str(&result[0]), "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn step_limit() {
+        let main = proto(0, 1, vec![], vec![], vec![ins_ad(0x58, 0, 0x7fff)]);
+        let limits = Limits {
+            max_steps: 100,
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            run(vec![main], limits),
+            Err(DecompileError::StepLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn memory_limit() {
+        // local s = "ab"; while true do s = s .. s end
+        let main = proto(
+            0,
+            2,
+            vec![],
+            vec![GlobalConst::Str(b"ab".to_vec())],
+            vec![
+                ins_ad(0x27, 0, 0),      // KSTR 0 "ab"
+                ins_ad(0x12, 1, 0),      // MOV 1 0
+                ins_abc(0x26, 0, 0, 1),  // CAT 0 0 1
+                ins_ad(0x58, 2, 0x7ffd), // JMP => 1
+            ],
+        );
+        let limits = Limits {
+            max_memory: 1 << 16,
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            run(vec![main], limits),
+            Err(DecompileError::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn shared_up_value() -> Result<(), DecompileError> {
+        // local n = 10; local function inc() n = n + 1; return n end
+        // inc(); local r = inc(); return n, r
+        let inc = proto(
+            0,
+            1,
+            vec![PROTO_UV_LOCAL],
+            vec![],
+            vec![
+                ins_ad(0x2d, 0, 0),     // UGET 0 n
+                ins_abc(0x16, 0, 0, 0), // ADDVN 0 0 1
+                ins_ad(0x2e, 0, 0),     // USETV n 0
+                ins_ad(0x4c, 0, 2),     // RET1 0 2
+            ],
+        );
+        let main = proto(
+            0,
+            3,
+            vec![],
+            vec![GlobalConst::ProtoChild(0)],
+            vec![
+                ins_ad(0x29, 0, 10),    // KSHORT 0 10
+                ins_ad(0x33, 1, 0),     // FNEW 1 inc
+                ins_ad(0x12, 2, 1),     // MOV 2 1
+                ins_abc(0x42, 2, 1, 1), // CALL 2 1 1
+                ins_ad(0x12, 2, 1),     // MOV 2 1
+                ins_abc(0x42, 2, 2, 1), // CALL 2 2 1
+                ins_ad(0x12, 1, 0),     // MOV 1 0
+                ins_ad(0x4a, 1, 3),     // RET 1 3
+            ],
+        );
+
+        let result = run(vec![inc, main], Limits::default())?;
+        assert!(matches!(result[..], [Value::Num(n), Value::Num(r)] if n == 12.0 && r == 12.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deep_recursion_on_small_stack() {
+        // function f() f() end; f()
+        let f = proto(
+            0,
+            1,
+            vec![],
+            vec![GlobalConst::Str(b"f".to_vec())],
+            vec![
+                ins_ad(0x36, 0, 0),     // GGET 0 "f"
+                ins_abc(0x42, 0, 1, 1), // CALL 0 1 1
+                ins_ad(0x4b, 0, 1),     // RET0 0 1
+            ],
+        );
+        let main = proto(
+            0,
+            1,
+            vec![],
+            vec![GlobalConst::Str(b"f".to_vec()), GlobalConst::ProtoChild(0)],
+            vec![
+                ins_ad(0x33, 0, 0),     // FNEW 0 f
+                ins_ad(0x37, 0, 1),     // GSET 0 "f"
+                ins_ad(0x36, 0, 1),     // GGET 0 "f"
+                ins_abc(0x42, 0, 1, 1), // CALL 0 1 1
+                ins_ad(0x4b, 0, 1),     // RET0 0 1
+            ],
+        );
+
+        // lua calls don't use host stack, so the depth limit is hit first
+        let result = std::thread::Builder::new()
+            .stack_size(128 << 10)
+            .spawn(|| {
+                let limits = Limits {
+                    max_depth: 10_000,
+                    ..Limits::default()
+                };
+                matches!(
+                    run(vec![f, main], limits),
+                    Err(DecompileError::CallDepthExceeded)
+                )
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert!(result);
+    }
+
+    #[test]
+    fn frame_memory_is_released() -> Result<(), DecompileError> {
+        // local function f() end
+        // for i = 1, 10000 do f() end
+        let f = proto(0, 1, vec![], vec![], vec![ins_ad(0x4b, 0, 1)]);
+        let main = proto(
+            0,
+            7,
+            vec![],
+            vec![GlobalConst::ProtoChild(0)],
+            vec![
+                ins_ad(0x33, 0, 0),      // FNEW 0 f
+                ins_ad(0x29, 1, 1),      // KSHORT 1 1
+                ins_ad(0x29, 2, 10_000), // KSHORT 2 10000
+                ins_ad(0x29, 3, 1),      // KSHORT 3 1
+                ins_ad(0x4d, 1, 0x8003), // FORI 1 => 8
+                ins_ad(0x12, 5, 0),      // MOV 5 0
+                ins_abc(0x42, 5, 1, 1),  // CALL 5 1 1
+                ins_ad(0x4f, 1, 0x7ffd), // FORL 1 => 5
+                ins_ad(0x4b, 0, 1),      // RET0 0 1
+            ],
+        );
+
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![f, main]);
+        let limits = Limits {
+            max_memory: 4096,
+            ..Limits::default()
+        };
+        let mut emulator = Emulator::new(&dump, limits);
+        emulator.run(vec![])?;
+
+        // only main and `f` closures are left
+        assert_eq!(emulator.memory_used(), 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_string_constant() {
+        let main = proto(
+            0,
+            1,
+            vec![],
+            vec![],
+            vec![
+                ins_ad(0x27, 0, 5), // KSTR 0 5
+                ins_ad(0x4b, 0, 1), // RET0 0 1
+            ],
+        );
+
+        assert!(matches!(
+            run(vec![main], Limits::default()),
+            Err(DecompileError::Emulation(msg)) if msg == "string constant 5 is out of range"
+        ));
+    }
+
+    fn library_fn(emulator: &Emulator, library: &str, name: &str) -> Value {
+        let library = match emulator.globals().borrow().get_str(library) {
+            Value::Table(library) => library,
+            value => panic!("table expected, got {:?}", value),
+        };
+        let func = library.borrow().get_str(name);
+        func
+    }
+
+    #[test]
+    fn stdlib_bad_arguments() -> Result<(), DecompileError> {
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![]);
+        let limits = Limits {
+            max_memory: 10_000,
+            ..Limits::default()
+        };
+        let mut emulator = Emulator::new(&dump, limits);
+        emulator.open_stdlib()?;
+
+        let tonumber = emulator.globals().borrow().get_str("tonumber");
+        assert!(matches!(
+            emulator.call(&tonumber, vec![Value::str(b"10"), Value::Num(99.0)]),
+            Err(DecompileError::Emulation(msg))
+                if msg == "bad argument #2 to 'tonumber' (base out of range)"
+        ));
+
+        let mut table = Table::default();
+        for idx in 1..=100 {
+            table.set(Value::Num(idx as f64), Value::Str(vec![b'x'; 1000].into()))?;
+        }
+        let table = Value::table(table);
+
+        let unpack = emulator.globals().borrow().get_str("unpack");
+        assert!(matches!(
+            emulator.call(&unpack, vec![table.clone(), Value::Num(-1e19), Value::Num(0.0)]),
+            Err(DecompileError::Emulation(msg)) if msg == "too many results to unpack"
+        ));
+
+        // limit is hit before whole result is built
+        let concat = library_fn(&emulator, "table", "concat");
+        assert!(matches!(
+            emulator.call(&concat, vec![table]),
+            Err(DecompileError::MemoryLimitExceeded)
+        ));
+        assert!(emulator.memory_used() < 12_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_results() -> Result<(), DecompileError> {
+        // return unpack({}, 1, 30000)
+        let main = proto(
+            0,
+            4,
+            vec![],
+            vec![GlobalConst::Str(b"unpack".to_vec())],
+            vec![
+                ins_ad(0x36, 0, 0),      // GGET 0 "unpack"
+                ins_ad(0x34, 1, 0),      // TNEW 1 0
+                ins_ad(0x29, 2, 1),      // KSHORT 2 1
+                ins_ad(0x29, 3, 30_000), // KSHORT 3 30000
+                ins_abc(0x42, 0, 0, 4),  // CALL 0 0 4
+                ins_ad(0x49, 0, 0),      // RETM 0 0
+            ],
+        );
+
+        assert!(matches!(
+            run(vec![main], Limits::default()),
+            Err(DecompileError::Emulation(msg)) if msg == "too many results to unpack"
+        ));
+
+        // return many()
+        let main = proto(
+            0,
+            1,
+            vec![],
+            vec![GlobalConst::Str(b"many".to_vec())],
+            vec![
+                ins_ad(0x36, 0, 0),     // GGET 0 "many"
+                ins_abc(0x42, 0, 0, 1), // CALL 0 0 1
+                ins_ad(0x49, 0, 0),     // RETM 0 0
+            ],
+        );
+        let dump = ByteCodeDump::from_prototypes(0, vec![], vec![main]);
+        let limits = Limits {
+            max_memory: 50_000,
+            ..Limits::default()
+        };
+
+        let run_many = |count: usize| {
+            let mut emulator = Emulator::new(&dump, limits);
+            let many = Value::native(move |_, _| Ok(vec![Value::Nil; count]));
+            emulator
+                .globals()
+                .borrow_mut()
+                .set(Value::str(b"many"), many)?;
+            emulator.run(vec![])
+        };
+
+        // slots past 65535 can't be addressed
+        assert!(matches!(
+            run_many(70_000),
+            Err(DecompileError::Emulation(msg)) if msg == "stack overflow"
+        ));
+        // frame grown by results is charged
+        assert!(matches!(
+            run_many(5000),
+            Err(DecompileError::MemoryLimitExceeded)
+        ));
+        assert_eq!(run_many(100)?.len(), 100);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,492 @@
+use super::{Emulator, Table, TableRef, Value, MAX_RESULTS, STR_HEADER_SIZE};
+use crate::DecompileError;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type NativeResult = Result<Vec<Value>, DecompileError>;
+
+fn error(msg: String) -> DecompileError {
+    DecompileError::Emulation(msg)
+}
+
+fn arg(args: &[Value], idx: usize) -> Value {
+    args.get(idx).cloned().unwrap_or(Value::Nil)
+}
+
+fn bad_arg(idx: usize, name: &str, expected: &str, got: &Value) -> DecompileError {
+    error(format!(
+        "bad argument #{} to '{}' ({} expected, got {})",
+        idx + 1,
+        name,
+        expected,
+        got.type_name()
+    ))
+}
+
+fn num_arg(args: &[Value], idx: usize, name: &str) -> Result<f64, DecompileError> {
+    let value = arg(args, idx);
+    value
+        .to_number()
+        .ok_or_else(|| bad_arg(idx, name, "number", &value))
+}
+
+fn opt_num_arg(
+    args: &[Value],
+    idx: usize,
+    name: &str,
+    default: f64,
+) -> Result<f64, DecompileError> {
+    match arg(args, idx) {
+        Value::Nil => Ok(default),
+        _ => num_arg(args, idx, name),
+    }
+}
+
+fn str_arg(args: &[Value], idx: usize, name: &str) -> Result<Rc<[u8]>, DecompileError> {
+    let value = arg(args, idx);
+    value
+        .to_bytes()
+        .ok_or_else(|| bad_arg(idx, name, "string", &value))
+}
+
+fn table_arg(args: &[Value], idx: usize, name: &str) -> Result<TableRef, DecompileError> {
+    match arg(args, idx) {
+        Value::Table(table) => Ok(table),
+        value => Err(bad_arg(idx, name, "table", &value)),
+    }
+}
+
+fn one(value: Value) -> NativeResult {
+    Ok(vec![value])
+}
+
+fn register<F>(table: &TableRef, name: &str, func: F) -> Result<(), DecompileError>
+where
+    F: Fn(&mut Emulator, Vec<Value>) -> NativeResult + 'static,
+{
+    table
+        .borrow_mut()
+        .set(Value::str(name.as_bytes()), Value::native(func))?;
+    Ok(())
+}
+
+fn library(globals: &TableRef, name: &str) -> Result<TableRef, DecompileError> {
+    let table = Rc::new(RefCell::new(Table::default()));
+    globals
+        .borrow_mut()
+        .set(Value::str(name.as_bytes()), Value::Table(table.clone()))?;
+    Ok(table)
+}
+
+// relative string position of `string.sub` and `string.byte`, result is 1-based
+fn str_pos(pos: f64, len: usize) -> i64 {
+    let pos = pos as i64;
+    match pos < 0 {
+        true => len as i64 + pos + 1,
+        false => pos,
+    }
+}
+
+fn str_range(bytes: &[u8], start: f64, end: f64) -> &[u8] {
+    let len = bytes.len();
+    let start = str_pos(start, len).max(1);
+    let end = str_pos(end, len).min(len as i64);
+
+    match start > end {
+        true => &[],
+        false => &bytes[start as usize - 1..end as usize],
+    }
+}
+
+// `bit` library operates on 32-bit integers normalized like `bit.tobit`
+fn tobit(num: f64) -> i32 {
+    (num + 6755399441055744.0).to_bits() as u32 as i32
+}
+
+fn bit_arg(args: &[Value], idx: usize, name: &str) -> Result<i32, DecompileError> {
+    Ok(tobit(num_arg(args, idx, name)?))
+}
+
+fn open_base(globals: &TableRef) -> Result<(), DecompileError> {
+    register(globals, "type", |_, args| {
+        one(Value::str(arg(&args, 0).type_name().as_bytes()))
+    })?;
+    register(globals, "tostring", |emu, args| match arg(&args, 0) {
+        Value::Str(str) => one(Value::Str(str)),
+        Value::Num(num) => one(emu.new_str(crate::listing::fmt_lua_num(num).into_bytes())?),
+        Value::Nil => one(Value::str(b"nil")),
+        Value::Bool(value) => one(Value::str(value.to_string().as_bytes())),
+        value => one(emu.new_str(format!("{:?}", value).into_bytes())?),
+    })?;
+    register(globals, "tonumber", |_, args| {
+        let value = arg(&args, 0);
+        match arg(&args, 1) {
+            Value::Nil => one(value.to_number().map_or(Value::Nil, Value::Num)),
+            base => {
+                let base = num_arg(&[base], 0, "tonumber")?;
+                if !(2.0..=36.0).contains(&base) {
+                    return Err(error(
+                        "bad argument #2 to 'tonumber' (base out of range)".to_string(),
+                    ));
+                }
+
+                let base = base as u32;
+                let bytes = str_arg(&args, 0, "tonumber")?;
+                let parsed = std::str::from_utf8(&bytes)
+                    .ok()
+                    .and_then(|str| i64::from_str_radix(str.trim(), base).ok());
+                one(parsed.map_or(Value::Nil, |num| Value::Num(num as f64)))
+            }
+        }
+    })?;
+    register(globals, "select", |_, args| match arg(&args, 0) {
+        Value::Str(str) if &*str == b"#" => one(Value::Num((args.len() - 1) as f64)),
+        _ => {
+            let idx = num_arg(&args, 0, "select")? as i64;
+            let count = args.len() as i64 - 1;
+            let start = match idx < 0 {
+                true => count + idx,
+                false => idx - 1,
+            };
+
+            if idx == 0 || start < 0 {
+                return Err(error(
+                    "bad argument #1 to 'select' (index out of range)".to_string(),
+                ));
+            }
+
+            Ok(args.into_iter().skip(start as usize + 1).collect())
+        }
+    })?;
+    register(globals, "assert", |_, args| {
+        match arg(&args, 0).is_truthy() {
+            true => Ok(args),
+            false => Err(error(match arg(&args, 1).to_bytes() {
+                Some(msg) => String::from_utf8_lossy(&msg).into_owned(),
+                None => "assertion failed!".to_string(),
+            })),
+        }
+    })?;
+    register(globals, "error", |_, args| {
+        let msg = arg(&args, 0);
+        Err(error(match msg.to_bytes() {
+            Some(msg) => String::from_utf8_lossy(&msg).into_owned(),
+            None => format!("{:?}", msg),
+        }))
+    })?;
+
+    let next = Value::native(|_, args| {
+        let table = table_arg(&args, 0, "next")?;
+        let entry = table.borrow().next(&arg(&args, 1))?;
+
+        match entry {
+            Some((key, value)) => Ok(vec![key, value]),
+            None => one(Value::Nil),
+        }
+    });
+
+    globals
+        .borrow_mut()
+        .set(Value::str(b"next"), next.clone())?;
+    register(globals, "pairs", move |_, args| {
+        table_arg(&args, 0, "pairs")?;
+        Ok(vec![next.clone(), arg(&args, 0), Value::Nil])
+    })?;
+
+    let ipairs_aux = Value::native(|_, args| {
+        let table = table_arg(&args, 0, "ipairs")?;
+        let idx = num_arg(&args, 1, "ipairs")? + 1.0;
+        let value = table.borrow().get(&Value::Num(idx));
+
+        match value {
+            Value::Nil => one(Value::Nil),
+            value => Ok(vec![Value::Num(idx), value]),
+        }
+    });
+
+    register(globals, "ipairs", move |_, args| {
+        table_arg(&args, 0, "ipairs")?;
+        Ok(vec![ipairs_aux.clone(), arg(&args, 0), Value::Num(0.0)])
+    })?;
+    register(globals, "unpack", |emu, args| {
+        let table = table_arg(&args, 0, "unpack")?;
+        let start = opt_num_arg(&args, 1, "unpack", 1.0)? as i64;
+        let end = match arg(&args, 2) {
+            Value::Nil => table.borrow().len() as i64,
+            _ => num_arg(&args, 2, "unpack")? as i64,
+        };
+
+        let count = match start > end {
+            true => 0,
+            false => end
+                .checked_sub(start)
+                .and_then(|count| count.checked_add(1))
+                .and_then(|count| usize::try_from(count).ok())
+                .filter(|&count| count < MAX_RESULTS)
+                .ok_or_else(|| error("too many results to unpack".to_string()))?,
+        };
+        emu.charge(count.saturating_mul(std::mem::size_of::<Value>()))?;
+
+        let table = table.borrow();
+        Ok((start..=end)
+            .map(|idx| table.get(&Value::Num(idx as f64)))
+            .collect())
+    })?;
+    register(globals, "rawget", |_, args| {
+        let table = table_arg(&args, 0, "rawget")?;
+        let value = table.borrow().get(&arg(&args, 1));
+        one(value)
+    })?;
+    register(globals, "rawset", |emu, args| {
+        let table = table_arg(&args, 0, "rawset")?;
+        emu.table_set(&table, arg(&args, 1), arg(&args, 2))?;
+        one(Value::Table(table))
+    })?;
+    register(globals, "rawequal", |_, args| {
+        one(Value::Bool(arg(&args, 0).raw_eq(&arg(&args, 1))))
+    })?;
+
+    Ok(())
+}
+
+fn open_string(globals: &TableRef) -> Result<(), DecompileError> {
+    let string = library(globals, "string")?;
+
+    register(&string, "len", |_, args| {
+        one(Value::Num(str_arg(&args, 0, "len")?.len() as f64))
+    })?;
+    register(&string, "sub", |emu, args| {
+        let bytes = str_arg(&args, 0, "sub")?;
+        let start = opt_num_arg(&args, 1, "sub", 1.0)?;
+        let end = opt_num_arg(&args, 2, "sub", -1.0)?;
+        one(emu.new_str(str_range(&bytes, start, end).to_vec())?)
+    })?;
+    register(&string, "byte", |_, args| {
+        let bytes = str_arg(&args, 0, "byte")?;
+        let start = opt_num_arg(&args, 1, "byte", 1.0)?;
+        let end = opt_num_arg(&args, 2, "byte", start)?;
+        Ok(str_range(&bytes, start, end)
+            .iter()
+            .map(|&byte| Value::Num(byte as f64))
+            .collect())
+    })?;
+    register(&string, "char", |emu, args| {
+        let mut bytes = Vec::with_capacity(args.len());
+
+        for idx in 0..args.len() {
+            let byte = num_arg(&args, idx, "char")?;
+            if !(0.0..256.0).contains(&byte) {
+                return Err(error(format!(
+                    "bad argument #{} to 'char' (invalid value)",
+                    idx + 1
+                )));
+            }
+            bytes.push(byte as u8);
+        }
+
+        one(emu.new_str(bytes)?)
+    })?;
+    register(&string, "rep", |emu, args| {
+        let bytes = str_arg(&args, 0, "rep")?;
+        let count = num_arg(&args, 1, "rep")?.max(0.0) as usize;
+
+        // check limit before allocating the result
+        emu.charge(bytes.len().saturating_mul(count))?;
+        one(Value::Str(bytes.repeat(count).into()))
+    })?;
+    register(&string, "reverse", |emu, args| {
+        let mut bytes = str_arg(&args, 0, "reverse")?.to_vec();
+        bytes.reverse();
+        one(emu.new_str(bytes)?)
+    })?;
+    register(&string, "upper", |emu, args| {
+        one(emu.new_str(str_arg(&args, 0, "upper")?.to_ascii_uppercase())?)
+    })?;
+    register(&string, "lower", |emu, args| {
+        one(emu.new_str(str_arg(&args, 0, "lower")?.to_ascii_lowercase())?)
+    })?;
+
+    Ok(())
+}
+
+fn open_table(globals: &TableRef) -> Result<(), DecompileError> {
+    let table = library(globals, "table")?;
+
+    register(&table, "concat", |emu, args| {
+        let table = table_arg(&args, 0, "concat")?;
+        let sep = match arg(&args, 1) {
+            Value::Nil => Rc::from(&b""[..]),
+            _ => str_arg(&args, 1, "concat")?,
+        };
+        let start = opt_num_arg(&args, 2, "concat", 1.0)? as i64;
+        let end = match arg(&args, 3) {
+            Value::Nil => table.borrow().len() as i64,
+            _ => num_arg(&args, 3, "concat")? as i64,
+        };
+
+        // buffer is accounted while it grows
+        emu.charge(STR_HEADER_SIZE)?;
+
+        let mut bytes = vec![];
+        for idx in start..=end {
+            let value = table.borrow().get(&Value::Num(idx as f64));
+            match value.to_bytes() {
+                Some(piece) => {
+                    emu.charge(piece.len() + sep.len())?;
+                    bytes.extend_from_slice(&piece);
+                }
+                None => {
+                    return Err(error(format!(
+                        "invalid value (at index {}) in table for 'concat'",
+                        idx
+                    )))
+                }
+            }
+
+            if idx != end {
+                bytes.extend_from_slice(&sep);
+            }
+        }
+
+        one(Value::Str(bytes.into()))
+    })?;
+    register(&table, "insert", |emu, args| {
+        let table = table_arg(&args, 0, "insert")?;
+        let len = table.borrow().len();
+
+        let (pos, value) = match args.len() {
+            2 => (len + 1, arg(&args, 1)),
+            3 => (num_arg(&args, 1, "insert")? as usize, arg(&args, 2)),
+            _ => return Err(error("wrong number of arguments to 'insert'".to_string())),
+        };
+
+        for idx in (pos..=len).rev() {
+            let moved = table.borrow().get(&Value::Num(idx as f64));
+            emu.table_set(&table, Value::Num((idx + 1) as f64), moved)?;
+        }
+
+        emu.table_set(&table, Value::Num(pos as f64), value)?;
+        Ok(vec![])
+    })?;
+    register(&table, "remove", |emu, args| {
+        let table = table_arg(&args, 0, "remove")?;
+        let len = table.borrow().len();
+
+        if len == 0 {
+            return Ok(vec![]);
+        }
+
+        let pos = opt_num_arg(&args, 1, "remove", len as f64)? as usize;
+        let removed = table.borrow().get(&Value::Num(pos as f64));
+
+        for idx in pos..len {
+            let moved = table.borrow().get(&Value::Num((idx + 1) as f64));
+            emu.table_set(&table, Value::Num(idx as f64), moved)?;
+        }
+
+        emu.table_set(&table, Value::Num(len as f64), Value::Nil)?;
+        one(removed)
+    })?;
+
+    Ok(())
+}
+
+fn open_math(globals: &TableRef) -> Result<(), DecompileError> {
+    let math = library(globals, "math")?;
+
+    let unary = [
+        ("floor", f64::floor as fn(f64) -> f64),
+        ("ceil", f64::ceil),
+        ("abs", f64::abs),
+        ("sqrt", f64::sqrt),
+    ];
+
+    for (name, op) in unary {
+        register(&math, name, move |_, args| {
+            one(Value::Num(op(num_arg(&args, 0, name)?)))
+        })?;
+    }
+
+    register(&math, "max", |_, args| {
+        let mut max = num_arg(&args, 0, "max")?;
+        for idx in 1..args.len() {
+            max = max.max(num_arg(&args, idx, "max")?);
+        }
+        one(Value::Num(max))
+    })?;
+    register(&math, "min", |_, args| {
+        let mut min = num_arg(&args, 0, "min")?;
+        for idx in 1..args.len() {
+            min = min.min(num_arg(&args, idx, "min")?);
+        }
+        one(Value::Num(min))
+    })?;
+    register(&math, "fmod", |_, args| {
+        let a = num_arg(&args, 0, "fmod")?;
+        let b = num_arg(&args, 1, "fmod")?;
+        one(Value::Num(a % b))
+    })?;
+
+    let mut math = math.borrow_mut();
+    math.set(Value::str(b"huge"), Value::Num(f64::INFINITY))?;
+    math.set(Value::str(b"pi"), Value::Num(std::f64::consts::PI))?;
+
+    Ok(())
+}
+
+fn open_bit(globals: &TableRef) -> Result<(), DecompileError> {
+    let bit = library(globals, "bit")?;
+
+    register(&bit, "tobit", |_, args| {
+        one(Value::Num(bit_arg(&args, 0, "tobit")? as f64))
+    })?;
+    register(&bit, "bnot", |_, args| {
+        one(Value::Num(!bit_arg(&args, 0, "bnot")? as f64))
+    })?;
+
+    let folds = [
+        ("band", (|a, b| a & b) as fn(i32, i32) -> i32),
+        ("bor", |a, b| a | b),
+        ("bxor", |a, b| a ^ b),
+    ];
+
+    for (name, op) in folds {
+        register(&bit, name, move |_, args| {
+            let mut result = bit_arg(&args, 0, name)?;
+            for idx in 1..args.len() {
+                result = op(result, bit_arg(&args, idx, name)?);
+            }
+            one(Value::Num(result as f64))
+        })?;
+    }
+
+    // shift count uses only the low 5 bits
+    let shifts = [
+        (
+            "lshift",
+            (|a: i32, n| ((a as u32) << n) as i32) as fn(i32, u32) -> i32,
+        ),
+        ("rshift", |a, n| ((a as u32) >> n) as i32),
+        ("arshift", |a, n| a >> n),
+    ];
+
+    for (name, op) in shifts {
+        register(&bit, name, move |_, args| {
+            let a = bit_arg(&args, 0, name)?;
+            let n = bit_arg(&args, 1, name)? as u32 & 31;
+            one(Value::Num(op(a, n) as f64))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Registers deterministic subset of `base`, `string`, `table`, `math` and `bit` libraries,
+/// functions with side effects like `print` or `io` are left for user stubs
+pub fn open(globals: &TableRef) -> Result<(), DecompileError> {
+    open_base(globals)?;
+    open_string(globals)?;
+    open_table(globals)?;
+    open_math(globals)?;
+    open_bit(globals)
+}
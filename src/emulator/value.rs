@@ -0,0 +1,222 @@
+use crate::emulator::Emulator;
+use crate::listing::fmt_lua_num;
+use crate::DecompileError;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+/// Shared slot of up value, frame register and closures capturing it point to the same cell
+pub type UpValue = Rc<RefCell<Value>>;
+
+/// Native function stub, receives emulator to account memory or call back and arguments
+pub type NativeFn = dyn Fn(&mut Emulator, Vec<Value>) -> Result<Vec<Value>, DecompileError>;
+
+/// Lua function instance, prototype index in the dump and captured up values
+pub struct Closure {
+    pub proto: usize,
+    pub up_values: Vec<UpValue>,
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Num(f64),
+    /// lua strings are byte strings
+    Str(Rc<[u8]>),
+    Table(TableRef),
+    Closure(Rc<Closure>),
+    Native(Rc<NativeFn>),
+}
+
+impl Value {
+    pub fn str(bytes: &[u8]) -> Value {
+        Value::Str(bytes.into())
+    }
+
+    pub fn native<F>(func: F) -> Value
+    where
+        F: Fn(&mut Emulator, Vec<Value>) -> Result<Vec<Value>, DecompileError> + 'static,
+    {
+        Value::Native(Rc::new(func))
+    }
+
+    pub fn table(table: Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Name returned by lua `type`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Num(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Closure(_) | Value::Native(_) => "function",
+        }
+    }
+
+    /// Number with string coercion of arithmetic operands
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Num(a) => Some(*a),
+            Value::Str(a) => crate::const_fold::str_to_number(std::str::from_utf8(a).ok()?),
+            _ => None,
+        }
+    }
+
+    /// Bytes of string or number operand of concatenation
+    pub fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Value::Str(a) => Some(a.clone()),
+            Value::Num(a) => Some(fmt_lua_num(*a).as_bytes().into()),
+            _ => None,
+        }
+    }
+
+    /// Raw equality, reference types are compared by identity
+    pub fn raw_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(a) => write!(f, "{}", a),
+            Value::Num(a) => write!(f, "{}", fmt_lua_num(*a)),
+            Value::Str(a) => write!(f, "{:?}", String::from_utf8_lossy(a)),
+            Value::Table(a) => write!(f, "table: {:p}", Rc::as_ptr(a)),
+            Value::Closure(a) => write!(f, "function: proto({})", a.proto),
+            Value::Native(a) => write!(f, "function: builtin: {:p}", Rc::as_ptr(a)),
+        }
+    }
+}
+
+// hashable identity of table key, integral floats and integers are the same key
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+enum Key {
+    Bool(bool),
+    Num(u64),
+    Str(Rc<[u8]>),
+    Ref(usize),
+}
+
+impl Key {
+    fn new(value: &Value) -> Result<Option<Key>, DecompileError> {
+        Ok(Some(match value {
+            Value::Nil => return Ok(None),
+            Value::Bool(a) => Key::Bool(*a),
+            Value::Num(a) if a.is_nan() => {
+                return Err(DecompileError::Emulation("table index is NaN".to_string()))
+            }
+            // -0 and 0 are the same key
+            Value::Num(a) => Key::Num((a + 0.0).to_bits()),
+            Value::Str(a) => Key::Str(a.clone()),
+            Value::Table(a) => Key::Ref(Rc::as_ptr(a) as *const u8 as usize),
+            Value::Closure(a) => Key::Ref(Rc::as_ptr(a) as *const u8 as usize),
+            Value::Native(a) => Key::Ref(Rc::as_ptr(a) as *const u8 as usize),
+        }))
+    }
+}
+
+/// Lua table without metatable. Entries keep insertion order, so `next` is deterministic,
+/// removed entries stay with nil value.
+#[derive(Default)]
+pub struct Table {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+}
+
+impl Table {
+    pub fn get(&self, key: &Value) -> Value {
+        match Key::new(key) {
+            Ok(Some(key)) => match self.index.get(&key) {
+                Some(&idx) => self.entries[idx].1.clone(),
+                None => Value::Nil,
+            },
+            _ => Value::Nil,
+        }
+    }
+
+    /// Stores value, returns true if new entry was allocated
+    pub fn set(&mut self, key: Value, value: Value) -> Result<bool, DecompileError> {
+        let hash_key = match Key::new(&key)? {
+            Some(hash_key) => hash_key,
+            None => return Err(DecompileError::Emulation("table index is nil".to_string())),
+        };
+
+        if let Some(&idx) = self.index.get(&hash_key) {
+            self.entries[idx].1 = value;
+            return Ok(false);
+        }
+
+        if let Value::Nil = value {
+            return Ok(false);
+        }
+
+        self.index.insert(hash_key, self.entries.len());
+        self.entries.push((key, value));
+
+        Ok(true)
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::str(key.as_bytes()))
+    }
+
+    /// Border of array part, `#` operator
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+
+        while !matches!(self.get(&Value::Num((len + 1) as f64)), Value::Nil) {
+            len += 1;
+        }
+
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|(_, value)| matches!(value, Value::Nil))
+    }
+
+    /// Entry following the key in iteration order, nil key starts iteration
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, DecompileError> {
+        let start = match Key::new(key)? {
+            None => 0,
+            Some(key) => match self.index.get(&key) {
+                Some(&idx) => idx + 1,
+                None => {
+                    return Err(DecompileError::Emulation(
+                        "invalid key to 'next'".to_string(),
+                    ))
+                }
+            },
+        };
+
+        Ok(self.entries[start..]
+            .iter()
+            .find(|(_, value)| !matches!(value, Value::Nil))
+            .cloned())
+    }
+}
@@ -14,4 +14,22 @@ pub enum DecompileError {
     UnexpectedInsOpcode,
     #[error("Invalid primitive type value.")]
     InvalidPriValue,
+    #[error("Jump target is out of prototype bytecode.")]
+    InvalidJumpTarget,
+    #[error("Prototype child is not found in the dump.")]
+    InvalidProtoChild,
+    #[error("Constant index is out of prototype constants.")]
+    InvalidConstIndex,
+    #[error("Invalid bytecode listing at line {0}: {1}")]
+    InvalidListing(usize, &'static str),
+    #[error("Emulation step limit exceeded.")]
+    StepLimitExceeded,
+    #[error("Emulation memory limit exceeded.")]
+    MemoryLimitExceeded,
+    #[error("Emulation call depth limit exceeded.")]
+    CallDepthExceeded,
+    #[error("Emulation error: {0}")]
+    Emulation(String),
+    #[error("Invalid opcode map: {0}")]
+    InvalidOpcodeMap(String),
 }
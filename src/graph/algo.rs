@@ -5,7 +5,7 @@ use crate::graph::Graph;
 // constant value for undefined dominator
 const UNDEFINED: usize = ::std::usize::MAX;
 
-struct DominatorTree {
+pub struct DominatorTree {
     /// Control flow graph entry node index
     root: u32,
     /// `HashMap<a, b>` where a, b node indexes => `b idom a`
@@ -13,7 +13,7 @@ struct DominatorTree {
 }
 
 impl DominatorTree {
-    fn root(&self) -> u32 { self.root }
+    pub fn root(&self) -> u32 { self.root }
 
     /// Returns immediate dominator for passed node index
     pub fn idom(&self, node: u32) -> Option<u32> {
@@ -35,9 +35,15 @@ impl DominatorTree {
             None
         }
     }
+
+    /// Checks if node `a` dominates node `b`
+    pub fn dominates(&self, a: u32, b: u32) -> bool {
+        self.dominators(b)
+            .is_some_and(|mut dominators| dominators.any(|node| node == a))
+    }
 }
 
-struct DominatorsIter<'a> {
+pub struct DominatorsIter<'a> {
     dominators: &'a DominatorTree,
     node: Option<u32>,
 }
@@ -67,7 +73,7 @@ fn intersect(dominators: &[usize], mut finger1: usize, mut finger2: usize) -> us
 }
 
 
-fn dominator_tree<N: Clone, E: Clone>(graph: &Graph<N, E>, root: u32) -> DominatorTree {
+pub fn dominator_tree<N: Clone, E: Clone>(graph: &Graph<N, E>, root: u32) -> DominatorTree {
     // visit graph in dfs postorder and collect predecessors for every visited node
     let mut dfs = graph.dfs_post_order_visitor(root);
 
@@ -87,8 +93,6 @@ fn dominator_tree<N: Clone, E: Clone>(graph: &Graph<N, E>, root: u32) -> Dominat
         }
     }
 
-    println!("POST ORDER: {:?}", post_order);
-
     let length = post_order.len();
 
     debug_assert!(post_order.last() == Some(&root));
@@ -162,3 +166,32 @@ fn dominator_tree<N: Clone, E: Clone>(graph: &Graph<N, E>, root: u32) -> Dominat
             .collect(),
     }
 }
+
+// virtual exit node index for post dominator tree
+pub const VIRTUAL_EXIT: u32 = ::std::u32::MAX;
+
+/// Builds post dominator tree rooted at virtual exit node which is connected with every node
+/// without outgoing edges. Nodes which can't reach any exit (infinite loops) aren't contained.
+pub fn post_dominator_tree<N: Clone, E: Clone>(graph: &Graph<N, E>) -> DominatorTree {
+    let mut reversed: Graph<(), ()> = Graph::new();
+
+    reversed.add_node(VIRTUAL_EXIT, ());
+    for &idx in graph.nodes().keys() {
+        reversed.add_node(idx, ());
+    }
+
+    for &idx in graph.nodes().keys() {
+        let mut has_outputs = false;
+
+        for edge in graph.outputs(idx) {
+            reversed.add_edge((), graph.edge_to(edge), idx);
+            has_outputs = true;
+        }
+
+        if !has_outputs {
+            reversed.add_edge((), VIRTUAL_EXIT, idx);
+        }
+    }
+
+    dominator_tree(&reversed, VIRTUAL_EXIT)
+}
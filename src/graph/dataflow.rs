@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::graph::Graph;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Dataflow problem over graph nodes, `solve` iterates it to the fixed point
+pub trait DataflowAnalysis<N, E> {
+    type Fact: Clone + PartialEq;
+
+    fn direction(&self) -> Direction;
+
+    /// Fact at the root node entry (forward) or at exits of nodes without successors (backward)
+    fn boundary(&self) -> Self::Fact;
+
+    /// Initial fact of every node, it has to be neutral for `meet`
+    fn initial(&self) -> Self::Fact;
+
+    /// Joins fact coming from another predecessor (forward) or successor (backward)
+    fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact);
+
+    /// Maps fact at the node start to the fact at the node end for forward analysis and
+    /// vice versa for backward one
+    fn transfer(&self, index: u32, node: &N, fact: &Self::Fact) -> Self::Fact;
+}
+
+/// Facts at the start and at the end of every node in program order
+#[derive(Debug)]
+pub struct DataflowResult<F> {
+    pub entry: HashMap<u32, F>,
+    pub exit: HashMap<u32, F>,
+}
+
+/// Solves dataflow problem with worklist. Nodes reachable from `root` are visited in reverse
+/// post order for forward problems and in post order for backward ones, the rest of nodes follow.
+pub fn solve<N: Clone, E: Clone, A: DataflowAnalysis<N, E>>(
+    graph: &Graph<N, E>,
+    root: u32,
+    analysis: &A,
+) -> DataflowResult<A::Fact> {
+    let direction = analysis.direction();
+
+    let mut order = vec![];
+    if graph.exists(root) {
+        let mut dfs = graph.dfs_post_order_visitor(root);
+        while let Some(node_idx) = dfs.next(graph) {
+            order.push(node_idx);
+        }
+    }
+
+    if direction == Direction::Forward {
+        order.reverse();
+    }
+
+    let visited: HashSet<u32> = order.iter().cloned().collect();
+    order.extend(graph.nodes().keys().filter(|idx| !visited.contains(idx)));
+
+    let mut result = DataflowResult {
+        entry: HashMap::with_capacity(order.len()),
+        exit: HashMap::with_capacity(order.len()),
+    };
+
+    for &idx in &order {
+        result.entry.insert(idx, analysis.initial());
+        result.exit.insert(idx, analysis.initial());
+    }
+
+    let mut queued: HashSet<u32> = order.iter().cloned().collect();
+    let mut worklist: VecDeque<u32> = order.into_iter().collect();
+
+    while let Some(idx) = worklist.pop_front() {
+        queued.remove(&idx);
+
+        // meet facts of the flow predecessors
+        let fact = match direction {
+            Direction::Forward => {
+                let mut fact = match idx == root {
+                    true => analysis.boundary(),
+                    false => analysis.initial(),
+                };
+
+                for edge in graph.inputs(idx) {
+                    let from = graph.edge(edge).unwrap().from();
+                    analysis.meet(&mut fact, &result.exit[&from]);
+                }
+
+                fact
+            }
+            Direction::Backward => match graph.outputs(idx).next() {
+                None => analysis.boundary(),
+                Some(_) => {
+                    let mut fact = analysis.initial();
+
+                    for edge in graph.outputs(idx) {
+                        analysis.meet(&mut fact, &result.entry[&graph.edge_to(edge)]);
+                    }
+
+                    fact
+                }
+            },
+        };
+
+        let transferred = analysis.transfer(idx, graph.node_weight(idx).unwrap(), &fact);
+
+        let (input, output) = match direction {
+            Direction::Forward => (&mut result.entry, &mut result.exit),
+            Direction::Backward => (&mut result.exit, &mut result.entry),
+        };
+
+        input.insert(idx, fact);
+
+        if output[&idx] == transferred {
+            continue;
+        }
+        output.insert(idx, transferred);
+
+        // facts of the flow successors depend on the changed one
+        let dependents: Vec<u32> = match direction {
+            Direction::Forward => graph.outputs(idx).map(|edge| graph.edge_to(edge)).collect(),
+            Direction::Backward => graph
+                .inputs(idx)
+                .map(|edge| graph.edge(edge).unwrap().from())
+                .collect(),
+        };
+
+        for dependent in dependents {
+            if queued.insert(dependent) {
+                worklist.push_back(dependent);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use crate::graph::Graph;
+    use crate::graph::dataflow::{solve, DataflowAnalysis, Direction};
+
+    // collects nodes which are passed on the way to the node (forward) or from it (backward)
+    struct Passed(Direction);
+
+    impl DataflowAnalysis<u32, ()> for Passed {
+        type Fact = BTreeSet<u32>;
+
+        fn direction(&self) -> Direction {
+            self.0
+        }
+
+        fn boundary(&self) -> Self::Fact {
+            BTreeSet::new()
+        }
+
+        fn initial(&self) -> Self::Fact {
+            BTreeSet::new()
+        }
+
+        fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+            fact.extend(other.iter().cloned());
+        }
+
+        fn transfer(&self, _: u32, node: &u32, fact: &Self::Fact) -> Self::Fact {
+            let mut fact = fact.clone();
+            fact.insert(*node);
+            fact
+        }
+    }
+
+    //   1 -> 2 -> 3 -> 4
+    //        ^    |
+    //        +----+
+    fn loop_graph() -> Graph<u32, ()> {
+        let mut graph = Graph::new();
+
+        for idx in 1..=4 {
+            graph.add_node(idx, idx * 10);
+        }
+
+        graph.add_edge((), 1, 2);
+        graph.add_edge((), 2, 3);
+        graph.add_edge((), 3, 2);
+        graph.add_edge((), 3, 4);
+
+        graph
+    }
+
+    #[test]
+    fn test_forward() {
+        let result = solve(&loop_graph(), 1, &Passed(Direction::Forward));
+
+        assert_eq!(result.entry[&1], BTreeSet::new());
+        assert_eq!(result.entry[&2], [10, 20, 30].into_iter().collect());
+        assert_eq!(result.exit[&4], [10, 20, 30, 40].into_iter().collect());
+    }
+
+    #[test]
+    fn test_backward() {
+        let result = solve(&loop_graph(), 1, &Passed(Direction::Backward));
+
+        assert_eq!(result.exit[&4], BTreeSet::new());
+        assert_eq!(result.exit[&3], [20, 30, 40].into_iter().collect());
+        assert_eq!(result.entry[&1], [10, 20, 30, 40].into_iter().collect());
+    }
+}
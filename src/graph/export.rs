@@ -0,0 +1,201 @@
+use std::fmt::Write;
+
+use crate::graph::Graph;
+
+/// Provides labels and styles for nodes and edges of the exported graph
+pub trait GraphFormatter<N, E> {
+    /// Node label, lines are separated with `\n`
+    fn node_label(&self, index: u32, node: &N) -> String;
+
+    fn edge_label(&self, _edge: &E) -> String {
+        String::new()
+    }
+
+    /// Edge colour name understood by both Graphviz and Mermaid (`red`, `green`, ...)
+    fn edge_color(&self, _edge: &E) -> Option<&'static str> {
+        None
+    }
+}
+
+// escape label for Graphviz, lines are left justified
+fn dot_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\l"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+// escape label for Mermaid, quotes are replaced with entity codes
+fn mermaid_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '\n' => escaped.push_str("<br/>"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+impl<N: Clone, E: Clone> Graph<N, E> {
+    /// Exports graph in Graphviz DOT format
+    pub fn to_dot<F: GraphFormatter<N, E>>(&self, formatter: &F) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "digraph {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for (index, node) in self.iter_node_weights() {
+            let label = formatter.node_label(index, node);
+            writeln!(out, "    n{} [label=\"{}\\l\"];", index, dot_escape(&label)).unwrap();
+        }
+
+        for (index, _) in self.iter_node_weights() {
+            // outputs are iterated from the last added edge
+            let mut outputs: Vec<u32> = self.outputs(index).collect();
+            outputs.reverse();
+
+            for edge_index in outputs {
+                let weight = self.edge_weight(edge_index).unwrap();
+                let mut attrs = vec![];
+
+                let label = formatter.edge_label(weight);
+                if !label.is_empty() {
+                    attrs.push(format!("label=\"{}\"", dot_escape(&label)));
+                }
+                if let Some(color) = formatter.edge_color(weight) {
+                    attrs.push(format!("color={}", color));
+                }
+
+                write!(out, "    n{} -> n{}", index, self.edge_to(edge_index)).unwrap();
+                if !attrs.is_empty() {
+                    write!(out, " [{}]", attrs.join(", ")).unwrap();
+                }
+                writeln!(out, ";").unwrap();
+            }
+        }
+
+        writeln!(out, "}}").unwrap();
+
+        out
+    }
+
+    /// Exports graph as Mermaid flowchart
+    pub fn to_mermaid<F: GraphFormatter<N, E>>(&self, formatter: &F) -> String {
+        let mut out = String::new();
+        let mut link_styles = vec![];
+
+        writeln!(out, "flowchart TD").unwrap();
+
+        for (index, node) in self.iter_node_weights() {
+            let label = formatter.node_label(index, node);
+            writeln!(out, "    n{}[\"{}\"]", index, mermaid_escape(&label)).unwrap();
+        }
+
+        // mermaid styles links by their definition order
+        let mut link_index = 0;
+        for (index, _) in self.iter_node_weights() {
+            let mut outputs: Vec<u32> = self.outputs(index).collect();
+            outputs.reverse();
+
+            for edge_index in outputs {
+                let weight = self.edge_weight(edge_index).unwrap();
+                let label = formatter.edge_label(weight);
+                let to = self.edge_to(edge_index);
+
+                if label.is_empty() {
+                    writeln!(out, "    n{} --> n{}", index, to).unwrap();
+                } else {
+                    writeln!(out, "    n{} -->|\"{}\"| n{}", index, mermaid_escape(&label), to)
+                        .unwrap();
+                }
+
+                if let Some(color) = formatter.edge_color(weight) {
+                    link_styles.push(format!("    linkStyle {} stroke:{}", link_index, color));
+                }
+                link_index += 1;
+            }
+        }
+
+        for style in link_styles {
+            writeln!(out, "{}", style).unwrap();
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph::export::GraphFormatter;
+    use crate::graph::Graph;
+
+    struct Formatter;
+
+    impl GraphFormatter<&'static str, bool> for Formatter {
+        fn node_label(&self, index: u32, node: &&'static str) -> String {
+            format!("{}:\n{}", index, node)
+        }
+
+        fn edge_label(&self, edge: &bool) -> String {
+            format!("{}", edge)
+        }
+
+        fn edge_color(&self, edge: &bool) -> Option<&'static str> {
+            if *edge { Some("green") } else { None }
+        }
+    }
+
+    fn graph() -> Graph<&'static str, bool> {
+        let mut graph = Graph::new();
+
+        graph.add_node(0, "if \"a\"");
+        graph.add_node(1, "b");
+        graph.add_node(2, "c");
+
+        graph.add_edge(true, 0, 1);
+        graph.add_edge(false, 0, 2);
+
+        graph
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let expected = "digraph {\n\
+            \x20   node [shape=box, fontname=\"monospace\"];\n\
+            \x20   n0 [label=\"0:\\lif \\\"a\\\"\\l\"];\n\
+            \x20   n1 [label=\"1:\\lb\\l\"];\n\
+            \x20   n2 [label=\"2:\\lc\\l\"];\n\
+            \x20   n0 -> n1 [label=\"true\", color=green];\n\
+            \x20   n0 -> n2 [label=\"false\"];\n\
+            }\n";
+
+        assert_eq!(graph().to_dot(&Formatter), expected);
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let expected = "flowchart TD\n\
+            \x20   n0[\"0:<br/>if #quot;a#quot;\"]\n\
+            \x20   n1[\"1:<br/>b\"]\n\
+            \x20   n2[\"2:<br/>c\"]\n\
+            \x20   n0 -->|\"true\"| n1\n\
+            \x20   n0 -->|\"false\"| n2\n\
+            \x20   linkStyle 0 stroke:green\n";
+
+        assert_eq!(graph().to_mermaid(&Formatter), expected);
+    }
+}
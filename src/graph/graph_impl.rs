@@ -37,6 +37,8 @@ pub struct Edge<E> {
     to: u32,
     next_outgoing_edge: Option<u32>,
     next_incoming_edge: Option<u32>,
+    /// removed edges are unlinked from nodes and dropped by `Graph::compact()`
+    removed: bool,
 }
 
 impl<E> Edge<E> {
@@ -60,6 +62,7 @@ impl<E: Clone> Clone for Edge<E> {
             to: self.to,
             next_incoming_edge: self.next_incoming_edge,
             next_outgoing_edge: self.next_outgoing_edge,
+            removed: self.removed,
         }
     }
 
@@ -69,6 +72,7 @@ impl<E: Clone> Clone for Edge<E> {
         self.to = source.to;
         self.next_incoming_edge = source.next_incoming_edge;
         self.next_outgoing_edge = source.next_outgoing_edge;
+        self.removed = source.removed;
     }
 }
 
@@ -112,7 +116,7 @@ impl<'graph, E> Iterator for Inputs<'graph, E> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Graph<N, E> {
     nodes: BTreeMap<u32, Node<N>>,
     edges: Vec<Edge<E>>,
@@ -134,12 +138,17 @@ impl<N: Clone, E: Clone> Graph<N, E> {
 
     #[inline(always)]
     pub fn edges(&self) -> Vec<Edge<E>> {
-        self.edges.clone()
+        self.edges.iter().filter(|e| !e.removed).cloned().collect()
     }
 
     #[inline(always)]
     pub fn edge(&self, idx: u32) -> Option<&Edge<E>> {
-        self.edges.get(idx as usize)
+        self.edges.get(idx as usize).filter(|e| !e.removed)
+    }
+
+    #[inline(always)]
+    pub fn edge_count(&self) -> usize {
+        self.edges.iter().filter(|e| !e.removed).count()
     }
 
     #[inline(always)]
@@ -177,14 +186,14 @@ impl<N: Clone, E: Clone> Graph<N, E> {
     }
 
     #[inline(always)]
-    /// Returns index of outgoing node
+    /// Returns index of outgoing node, edge has to exist and not be removed
     pub fn edge_to(&self, index: u32) -> u32 {
-        self.edges.get(index as usize).unwrap().to
+        self.edge(index).expect("edge doesn't exist").to
     }
 
     #[inline(always)]
     pub fn edge_weight(&self, index: u32) -> Option<&E> {
-        self.edges.get(index as usize).map(|e| e.weight())
+        self.edge(index).map(|e| e.weight())
     }
 
     #[inline(always)]
@@ -244,11 +253,178 @@ impl<N: Clone, E: Clone> Graph<N, E> {
             to,
             next_incoming_edge,
             next_outgoing_edge,
+            removed: false,
         });
 
         index
     }
 
+    // remove edge from outgoing edge list of its source node
+    fn unlink_outgoing(&mut self, index: u32) {
+        let from = self.edges[index as usize].from;
+        let next = self.edges[index as usize].next_outgoing_edge;
+
+        let node = self.nodes.get_mut(&from).unwrap();
+        if node.next_outgoing_edge == Some(index) {
+            node.next_outgoing_edge = next;
+            return;
+        }
+
+        let mut current = node.next_outgoing_edge;
+        while let Some(edge_index) = current {
+            let edge = &mut self.edges[edge_index as usize];
+            if edge.next_outgoing_edge == Some(index) {
+                edge.next_outgoing_edge = next;
+                return;
+            }
+            current = edge.next_outgoing_edge;
+        }
+    }
+
+    // remove edge from incoming edge list of its destination node
+    fn unlink_incoming(&mut self, index: u32) {
+        let to = self.edges[index as usize].to;
+        let next = self.edges[index as usize].next_incoming_edge;
+
+        let node = self.nodes.get_mut(&to).unwrap();
+        if node.next_incoming_edge == Some(index) {
+            node.next_incoming_edge = next;
+            return;
+        }
+
+        let mut current = node.next_incoming_edge;
+        while let Some(edge_index) = current {
+            let edge = &mut self.edges[edge_index as usize];
+            if edge.next_incoming_edge == Some(index) {
+                edge.next_incoming_edge = next;
+                return;
+            }
+            current = edge.next_incoming_edge;
+        }
+    }
+
+    /// Removes edge from graph, edge index stays reserved until `compact()` is called
+    pub fn remove_edge(&mut self, index: u32) -> Option<E> {
+        self.edge(index)?;
+
+        self.unlink_outgoing(index);
+        self.unlink_incoming(index);
+
+        let edge = self.edge_mut(index).unwrap();
+        edge.removed = true;
+        edge.next_outgoing_edge = None;
+        edge.next_incoming_edge = None;
+
+        Some(edge.weight.clone())
+    }
+
+    /// Removes node with all its incoming and outgoing edges
+    pub fn remove_node(&mut self, index: u32) -> Option<N> {
+        if !self.exists(index) {
+            return None;
+        }
+
+        let outputs: Vec<u32> = self.outputs(index).collect();
+        let inputs: Vec<u32> = self.inputs(index).collect();
+
+        for edge_index in outputs.into_iter().chain(inputs) {
+            self.remove_edge(edge_index);
+        }
+
+        self.nodes.remove(&index).map(|node| node.weight)
+    }
+
+    /// Changes destination node of the edge, returns `false` and keeps graph unchanged when
+    /// edge or destination node doesn't exist
+    pub fn redirect_edge(&mut self, index: u32, to: u32) -> bool {
+        if self.edge(index).is_none() || !self.exists(to) {
+            return false;
+        }
+
+        self.unlink_incoming(index);
+
+        let to_node = self.nodes.get_mut(&to).unwrap();
+        let next_incoming_edge = to_node.next_incoming_edge.replace(index);
+
+        let edge = self.edge_mut(index).unwrap();
+        edge.to = to;
+        edge.next_incoming_edge = next_incoming_edge;
+
+        true
+    }
+
+    /// Merges `other` node into node with passed index. Edges between them are removed,
+    /// outgoing edges of `other` start from merged node and incoming ones point to it.
+    /// Returns `false` and keeps graph unchanged when some node doesn't exist or they are same.
+    pub fn merge_nodes<F: FnOnce(&mut N, N)>(&mut self, index: u32, other: u32, merger: F) -> bool {
+        if index == other || !self.exists(index) || !self.exists(other) {
+            return false;
+        }
+
+        let between: Vec<u32> = self
+            .outputs(index)
+            .filter(|&e| self.edge_to(e) == other)
+            .chain(self.outputs(other).filter(|&e| self.edge_to(e) == index))
+            .collect();
+
+        for edge_index in between {
+            self.remove_edge(edge_index);
+        }
+
+        let inputs: Vec<u32> = self.inputs(other).collect();
+        for edge_index in inputs {
+            self.redirect_edge(edge_index, index);
+        }
+
+        // move outgoing edges in front of merged node outgoing list
+        let outputs: Vec<u32> = self.outputs(other).collect();
+        if let Some(&last) = outputs.last() {
+            let node = self.node_mut(index).unwrap();
+            let next_outgoing_edge = node.next_outgoing_edge.replace(outputs[0]);
+
+            for &edge_index in &outputs {
+                self.edge_mut(edge_index).unwrap().from = index;
+            }
+            self.edge_mut(last).unwrap().next_outgoing_edge = next_outgoing_edge;
+        }
+
+        let other_node = self.nodes.remove(&other).unwrap();
+        merger(&mut self.node_mut(index).unwrap().weight, other_node.weight);
+
+        true
+    }
+
+    /// Drops removed edges and renumbers the rest keeping edge lists order. Returns new
+    /// edge index for every old one.
+    pub fn compact(&mut self) -> Vec<Option<u32>> {
+        let mut remap = Vec::with_capacity(self.edges.len());
+        let mut next_index = 0u32;
+
+        for edge in &self.edges {
+            if edge.removed {
+                remap.push(None);
+            } else {
+                remap.push(Some(next_index));
+                next_index += 1;
+            }
+        }
+
+        let relink = |link: Option<u32>| link.and_then(|idx| remap[idx as usize]);
+
+        self.edges.retain(|edge| !edge.removed);
+        for edge in self.edges.iter_mut() {
+            edge.next_outgoing_edge = relink(edge.next_outgoing_edge);
+            edge.next_incoming_edge = relink(edge.next_incoming_edge);
+        }
+
+        for node in self.nodes.values_mut() {
+            node.next_outgoing_edge = relink(node.next_outgoing_edge);
+            node.next_incoming_edge = relink(node.next_incoming_edge);
+        }
+
+        remap
+    }
+
     pub fn add_node(&mut self, index: u32, weight: N) -> Option<u32> {
         self.nodes
             .insert(
@@ -318,6 +494,114 @@ impl<N: Clone, E: Clone> Graph<N, E> {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use crate::graph::Graph;
+
+    fn outputs(graph: &Graph<u32, u32>, node: u32) -> Vec<(u32, u32)> {
+        graph.outputs(node).map(|e| (*graph.edge_weight(e).unwrap(), graph.edge_to(e))).collect()
+    }
+
+    fn inputs(graph: &Graph<u32, u32>, node: u32) -> Vec<(u32, u32)> {
+        graph
+            .inputs(node)
+            .map(|e| (*graph.edge_weight(e).unwrap(), graph.edge(e).unwrap().from()))
+            .collect()
+    }
 
+    //      1
+    //    /   \
+    //   2     3
+    //    \   /
+    //      4
+    fn diamond() -> Graph<u32, u32> {
+        let mut graph: Graph<u32, u32> = Graph::new();
+
+        for idx in 1..=4 {
+            graph.add_node(idx, idx);
+        }
+
+        graph.add_edge(12, 1, 2);
+        graph.add_edge(13, 1, 3);
+        graph.add_edge(24, 2, 4);
+        graph.add_edge(34, 3, 4);
+
+        graph
+    }
 
+    #[test]
+    fn test_remove_edge() {
+        let mut graph = diamond();
 
+        assert_eq!(graph.remove_edge(1), Some(13));
+        assert_eq!(graph.remove_edge(1), None);
+        assert_eq!(graph.edge_weight(1), None);
+
+        assert_eq!(outputs(&graph, 1), vec![(12, 2)]);
+        assert_eq!(inputs(&graph, 3), vec![]);
+        assert_eq!(graph.edge_count(), 3);
+    }
+
+    #[test]
+    fn test_remove_node() {
+        let mut graph = diamond();
+
+        assert_eq!(graph.remove_node(2), Some(2));
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(outputs(&graph, 1), vec![(13, 3)]);
+        assert_eq!(inputs(&graph, 4), vec![(34, 3)]);
+    }
+
+    #[test]
+    fn test_redirect_edge() {
+        let mut graph = diamond();
+
+        // 2 -> 4 becomes 2 -> 3
+        assert!(graph.redirect_edge(2, 3));
+
+        assert_eq!(outputs(&graph, 2), vec![(24, 3)]);
+        assert_eq!(inputs(&graph, 3), vec![(24, 2), (13, 1)]);
+        assert_eq!(inputs(&graph, 4), vec![(34, 3)]);
+
+        // missing destination or edge
+        assert!(!graph.redirect_edge(2, 7));
+        assert!(!graph.redirect_edge(9, 3));
+        assert_eq!(outputs(&graph, 2), vec![(24, 3)]);
+        assert_eq!(inputs(&graph, 3), vec![(24, 2), (13, 1)]);
+    }
+
+    #[test]
+    fn test_merge_nodes() {
+        let mut graph = diamond();
+
+        assert!(!graph.merge_nodes(2, 2, |a, b| *a += b));
+        assert!(!graph.merge_nodes(2, 7, |a, b| *a += b));
+        assert!(!graph.merge_nodes(7, 2, |a, b| *a += b));
+        assert_eq!(graph.edge_count(), 4);
+
+        assert!(graph.merge_nodes(2, 4, |a, b| *a += b));
+
+        assert!(!graph.exists(4));
+        assert_eq!(graph.node_weight(2), Some(&6));
+        assert_eq!(outputs(&graph, 2), vec![]);
+        assert_eq!(outputs(&graph, 3), vec![(34, 2)]);
+        assert_eq!(inputs(&graph, 2), vec![(34, 3), (12, 1)]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut graph = diamond();
+
+        graph.remove_edge(0);
+        graph.remove_edge(3);
+
+        assert_eq!(graph.compact(), vec![None, Some(0), Some(1), None]);
+
+        assert_eq!(graph.edge_count(), 2);
+        assert_eq!(outputs(&graph, 1), vec![(13, 3)]);
+        assert_eq!(outputs(&graph, 2), vec![(24, 4)]);
+        assert_eq!(inputs(&graph, 4), vec![(24, 2)]);
+        assert_eq!(inputs(&graph, 2), vec![]);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod graph_impl;
 pub mod visit;
-mod algo;
+pub mod algo;
+pub mod export;
+pub mod dataflow;
 
-pub use graph_impl::{Graph, Node, Edge};
\ No newline at end of file
+pub use graph_impl::{Graph, Node, Edge};
+pub use export::GraphFormatter;
\ No newline at end of file
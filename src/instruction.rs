@@ -0,0 +1,368 @@
+use crate::disasm::disasm;
+use crate::op::{Op, OpInfo, OpMode, OP_INFO};
+use crate::DecompileError;
+
+/// Decoded instruction operand, variant is the operand mode of the opcode field
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Operand {
+    Dst(u16),
+    Base(u16),
+    Var(u16),
+    RBase(u16),
+    UV(u16),
+    Lit(u16),
+    LitS(i16),
+    Pri(u16),
+    Num(u16),
+    Str(u16),
+    Tab(u16),
+    Func(u16),
+    /// jump offset relative to the next instruction
+    Jump(i32),
+    CData(u16),
+}
+
+impl Operand {
+    fn decode(mode: OpMode, val: u16) -> Option<Operand> {
+        Some(match mode {
+            OpMode::None => return None,
+            OpMode::Dst => Operand::Dst(val),
+            OpMode::Base => Operand::Base(val),
+            OpMode::Var => Operand::Var(val),
+            OpMode::RBase => Operand::RBase(val),
+            OpMode::UV => Operand::UV(val),
+            OpMode::Lit => Operand::Lit(val),
+            OpMode::LitS => Operand::LitS(val as i16),
+            OpMode::Pri => Operand::Pri(val),
+            OpMode::Num => Operand::Num(val),
+            OpMode::Str => Operand::Str(val),
+            OpMode::Tab => Operand::Tab(val),
+            OpMode::Func => Operand::Func(val),
+            OpMode::Jump => Operand::Jump(val as i32 - 0x8000),
+            OpMode::CData => Operand::CData(val),
+        })
+    }
+}
+
+/// Decoded instruction, `pc` is the index of instruction in prototype bytecode
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
+    pub pc: u32,
+    pub op: Op,
+    pub raw: u32,
+}
+
+// slots from `start` to `end` inclusive, empty for invalid ranges
+#[inline(always)]
+fn slot_range(start: u16, end: i32) -> Vec<u16> {
+    (start as i32..=end).map(|slot| slot as u16).collect()
+}
+
+impl Instruction {
+    pub fn decode(pc: u32, raw: u32) -> Result<Self, DecompileError> {
+        Ok(Instruction {
+            pc,
+            op: disasm(raw)?,
+            raw,
+        })
+    }
+
+    /// Decodes instructions of a bytecode slice, `start_pc` is the index of the first one
+    pub fn decode_all(start_pc: u32, bc_raw: &[u32]) -> Result<Vec<Self>, DecompileError> {
+        bc_raw
+            .iter()
+            .enumerate()
+            .map(|(offset, &raw)| Instruction::decode(start_pc + offset as u32, raw))
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn info(&self) -> &'static OpInfo {
+        // opcode was checked by disasm
+        &OP_INFO[(self.raw & 0xff) as usize]
+    }
+
+    #[inline(always)]
+    pub fn mnemonic(&self) -> &'static str {
+        self.info().name
+    }
+
+    #[inline(always)]
+    fn a(&self) -> u16 {
+        ((self.raw >> 8) & 0xff) as u16
+    }
+
+    #[inline(always)]
+    fn b(&self) -> u16 {
+        (self.raw >> 24) as u16
+    }
+
+    #[inline(always)]
+    fn c(&self) -> u16 {
+        ((self.raw >> 16) & 0xff) as u16
+    }
+
+    #[inline(always)]
+    fn d(&self) -> u16 {
+        (self.raw >> 16) as u16
+    }
+
+    /// Operands in A, B, C/D order, unused fields are skipped
+    pub fn operands(&self) -> Vec<Operand> {
+        let info = self.info();
+
+        let cd = match info.b {
+            OpMode::None => Operand::decode(info.cd, self.d()),
+            _ => Operand::decode(info.cd, self.c()),
+        };
+
+        [
+            Operand::decode(info.a, self.a()),
+            Operand::decode(info.b, self.b()),
+            cd,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Index of the jump destination, `None` for non jump instructions or negative target
+    pub fn jump_target(&self) -> Option<u32> {
+        if self.info().cd != OpMode::Jump {
+            return None;
+        }
+
+        let dest = self.pc as i64 + 1 + self.d() as i64 - 0x8000;
+        u32::try_from(dest).ok()
+    }
+
+    /// Checks if instruction can transfer control to the jump target
+    #[inline(always)]
+    pub fn is_branch(&self) -> bool {
+        self.info().cd == OpMode::Jump
+    }
+
+    /// Checks if instruction ends basic block: branches, returns and tail calls
+    pub fn is_terminator(&self) -> bool {
+        self.is_branch()
+            || matches!(
+                self.op,
+                Op::RET(..)
+                    | Op::RET0(..)
+                    | Op::RET1(..)
+                    | Op::RETM(..)
+                    | Op::CALLT(..)
+                    | Op::CALLMT(..)
+            )
+    }
+
+    /// Slots written by the instruction. Instructions with multiple results (`B == 0`)
+    /// report only the base slot.
+    pub fn defs(&self) -> Vec<u16> {
+        let a = self.a();
+
+        match self.op {
+            Op::ISTC(..)
+            | Op::ISFC(..)
+            | Op::MOV(..)
+            | Op::NOT(..)
+            | Op::UNM(..)
+            | Op::LEN(..)
+            | Op::ADDVN(..)
+            | Op::SUBVN(..)
+            | Op::MULVN(..)
+            | Op::DIVVN(..)
+            | Op::MODVN(..)
+            | Op::ADDNV(..)
+            | Op::SUBNV(..)
+            | Op::MULNV(..)
+            | Op::DIVNV(..)
+            | Op::MODNV(..)
+            | Op::ADDVV(..)
+            | Op::SUBVV(..)
+            | Op::MULVV(..)
+            | Op::DIVVV(..)
+            | Op::MODVV(..)
+            | Op::POW(..)
+            | Op::CAT(..)
+            | Op::KSTR(..)
+            | Op::KCDATA(..)
+            | Op::KSHORT(..)
+            | Op::KNUM(..)
+            | Op::KPRI(..)
+            | Op::UGET(..)
+            | Op::FNEW(..)
+            | Op::TNEW(..)
+            | Op::TDUP(..)
+            | Op::GGET(..)
+            | Op::TGETV(..)
+            | Op::TGETS(..)
+            | Op::TGETB(..)
+            | Op::TGETR(..) => vec![a],
+            Op::KNIL(..) => slot_range(a, self.d() as i32),
+            Op::CALL(_, b, _) | Op::CALLM(_, b, _) | Op::VARG(_, b, _) => match b.0 {
+                0 => vec![a],
+                b => slot_range(a, a as i32 + b as i32 - 2),
+            },
+            // iterator function, state and control variable are copied into A..A+2 before the
+            // call, ITERN becomes ITERC when ISNEXT check fails
+            Op::ITERC(_, b, _) | Op::ITERN(_, b, _) => {
+                slot_range(a, (a as i32 + b.0 as i32 - 2).max(a as i32 + 2))
+            }
+            // loop counter and its visible copy
+            Op::FORI(..) | Op::JFORI(..) => vec![a + 3],
+            Op::FORL(..) | Op::IFORL(..) | Op::JFORL(..) => vec![a, a + 3],
+            // control variable is copied before the iterator slot
+            Op::ITERL(..) | Op::IITERL(..) | Op::JITERL(..) => {
+                slot_range(a.saturating_sub(1), a as i32 - 1)
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Slots read by the instruction. Variadic operands (`MULTRES`) are not included.
+    pub fn uses(&self) -> Vec<u16> {
+        let (a, b, c, d) = (self.a(), self.b(), self.c(), self.d());
+
+        match self.op {
+            Op::ISLT(..)
+            | Op::ISGE(..)
+            | Op::ISLE(..)
+            | Op::ISGT(..)
+            | Op::ISEQV(..)
+            | Op::ISNEV(..) => vec![a, d],
+            Op::ISEQS(..)
+            | Op::ISNES(..)
+            | Op::ISEQN(..)
+            | Op::ISNEN(..)
+            | Op::ISEQP(..)
+            | Op::ISNEP(..)
+            | Op::ISTYPE(..)
+            | Op::ISNUM(..)
+            | Op::GSET(..)
+            | Op::RET1(..)
+            | Op::ITERL(..)
+            | Op::IITERL(..)
+            | Op::JITERL(..) => vec![a],
+            Op::ISTC(..)
+            | Op::ISFC(..)
+            | Op::IST(..)
+            | Op::ISF(..)
+            | Op::MOV(..)
+            | Op::NOT(..)
+            | Op::UNM(..)
+            | Op::LEN(..)
+            | Op::USETV(..) => vec![d],
+            Op::ADDVN(..)
+            | Op::SUBVN(..)
+            | Op::MULVN(..)
+            | Op::DIVVN(..)
+            | Op::MODVN(..)
+            | Op::ADDNV(..)
+            | Op::SUBNV(..)
+            | Op::MULNV(..)
+            | Op::DIVNV(..)
+            | Op::MODNV(..)
+            | Op::TGETS(..)
+            | Op::TGETB(..) => vec![b],
+            Op::ADDVV(..)
+            | Op::SUBVV(..)
+            | Op::MULVV(..)
+            | Op::DIVVV(..)
+            | Op::MODVV(..)
+            | Op::POW(..)
+            | Op::TGETV(..)
+            | Op::TGETR(..) => vec![b, c],
+            Op::CAT(..) => slot_range(b, c as i32),
+            Op::TSETV(..) | Op::TSETR(..) => vec![a, b, c],
+            Op::TSETS(..) | Op::TSETB(..) => vec![a, b],
+            Op::TSETM(..) => slot_range(a.saturating_sub(1), a as i32),
+            // function and C - 1 arguments, CALLM has C fixed arguments before MULTRES
+            Op::CALL(..) => slot_range(a, a as i32 + c as i32 - 1),
+            Op::CALLM(..) => slot_range(a, a as i32 + c as i32),
+            Op::CALLT(..) => slot_range(a, a as i32 + d as i32 - 1),
+            Op::CALLMT(..) => slot_range(a, a as i32 + d as i32),
+            // iterator function, state and control variable
+            Op::ITERC(..) | Op::ITERN(..) | Op::ISNEXT(..) => {
+                slot_range(a.saturating_sub(3), a as i32 - 1)
+            }
+            Op::RET(..) => slot_range(a, a as i32 + d as i32 - 2),
+            Op::RETM(..) => slot_range(a, a as i32 + d as i32 - 1),
+            Op::FORI(..) | Op::JFORI(..) | Op::FORL(..) | Op::IFORL(..) | Op::JFORL(..) => {
+                slot_range(a, a as i32 + 2)
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::{ins_abc, ins_ad};
+    use crate::instruction::{Instruction, Operand};
+    use crate::DecompileError;
+
+    #[test]
+    fn decode_operands() -> Result<(), DecompileError> {
+        let ins = Instruction::decode(3, ins_abc(0x39, 2, 1, 0))?; // TGETS 2 1 0
+
+        assert_eq!(ins.mnemonic(), "TGETS");
+        assert_eq!(
+            ins.operands(),
+            vec![Operand::Dst(2), Operand::Var(1), Operand::Str(0)]
+        );
+        assert_eq!(ins.defs(), vec![2]);
+        assert_eq!(ins.uses(), vec![1]);
+        assert!(!ins.is_branch());
+
+        let ins = Instruction::decode(0, ins_ad(0x29, 0, 0xffff))?; // KSHORT 0 -1
+        assert_eq!(ins.operands(), vec![Operand::Dst(0), Operand::LitS(-1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jump_targets() -> Result<(), DecompileError> {
+        let ins = Instruction::decode(5, ins_ad(0x58, 1, 0x7ffd))?; // JMP => 3
+
+        assert_eq!(ins.operands(), vec![Operand::RBase(1), Operand::Jump(-3)]);
+        assert_eq!(ins.jump_target(), Some(3));
+        assert!(ins.is_branch() && ins.is_terminator());
+
+        let ins = Instruction::decode(0, ins_ad(0x58, 1, 0x7ffe))?; // JMP => -1
+        assert_eq!(ins.jump_target(), None);
+
+        let ins = Instruction::decode(0, ins_ad(0x4b, 0, 1))?; // RET0 0 1
+        assert!(!ins.is_branch() && ins.is_terminator());
+        assert_eq!(ins.jump_target(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn slot_defs_uses() -> Result<(), DecompileError> {
+        let ins = Instruction::decode(0, ins_abc(0x42, 2, 3, 3))?; // CALL 2 3 3
+        assert_eq!(ins.defs(), vec![2, 3]);
+        assert_eq!(ins.uses(), vec![2, 3, 4]);
+
+        let ins = Instruction::decode(0, ins_ad(0x2c, 1, 4))?; // KNIL 1 4
+        assert_eq!(ins.defs(), vec![1, 2, 3, 4]);
+
+        let ins = Instruction::decode(0, ins_ad(0x4f, 0, 0x7ffe))?; // FORL 0
+        assert_eq!(ins.defs(), vec![0, 3]);
+        assert_eq!(ins.uses(), vec![0, 1, 2]);
+
+        let ins = Instruction::decode(0, ins_abc(0x45, 3, 2, 3))?; // ITERC 3 2 3
+        assert_eq!(ins.defs(), vec![3, 4, 5]);
+        assert_eq!(ins.uses(), vec![0, 1, 2]);
+
+        let ins = Instruction::decode(0, ins_abc(0x45, 3, 5, 3))?; // ITERC 3 5 3
+        assert_eq!(ins.defs(), vec![3, 4, 5, 6]);
+
+        let ins = Instruction::decode(0, ins_ad(0x4c, 5, 2))?; // RET1 5 2
+        assert_eq!(ins.uses(), vec![5]);
+        assert!(ins.defs().is_empty());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
+use crate::graph::GraphFormatter;
+use crate::listing::fmt_lua_num_exact;
 use crate::ir::Expr::Str;
+use crate::resolver::BranchKind;
+use crate::type_inference::Type;
 use crate::types::Pri;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::fmt::{write, Formatter};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expr {
     // variable slot
     Var(Var),
@@ -17,6 +22,8 @@ pub enum Expr {
     Uv(u16),
     Bool(bool),
     Nil,
+    // folded number value
+    Number(f64),
 
     // comparison expressions
     Lt([Box<Expr>; 2]),
@@ -45,7 +52,45 @@ pub enum Expr {
     Table([Box<Expr>; 2]), // (table, index)
 }
 
+/// Options of lua output
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FmtOptions {
+    /// keep global accesses as `_G["name"]`
+    pub strict_globals: bool,
+}
+
+/// Formats wrapped expression or instruction with passed options
+pub struct Pretty<'a, T>(pub &'a T, pub FmtOptions);
+
+const LUA_KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Checks if string can be written as a field or global name. Names of slot variables
+/// like `v1` are excluded as they would be confused with them.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+
+    let is_slot = name.len() > 1
+        && name.starts_with('v')
+        && name[1..].bytes().all(|c| c.is_ascii_digit());
+
+    valid && !is_slot && !LUA_KEYWORDS.contains(&name)
+}
+
 impl Expr {
+    pub fn display(&self, options: FmtOptions) -> Pretty<'_, Expr> {
+        Pretty(self, options)
+    }
+
     pub fn var(val: u16) -> Box<Expr> {
         Box::new(Expr::Var(Var(val)))
     }
@@ -153,14 +198,71 @@ impl Expr {
     pub fn len(a: Box<Expr>) -> Box<Expr> {
         Box::new(Expr::Len(a))
     }
+
+    /// Collects slots of all variables used in expression
+    pub fn collect_vars(&self, vars: &mut BTreeSet<u16>) {
+        match self {
+            Expr::Var(var) => {
+                vars.insert(var.0);
+            }
+            Expr::Not(a) | Expr::Len(a) | Expr::Minus(a) => a.collect_vars(vars),
+            Expr::Lt(args)
+            | Expr::Ge(args)
+            | Expr::Le(args)
+            | Expr::Gt(args)
+            | Expr::Eq(args)
+            | Expr::Ne(args)
+            | Expr::Add(args)
+            | Expr::Sub(args)
+            | Expr::Mul(args)
+            | Expr::Div(args)
+            | Expr::Mod(args)
+            | Expr::Pow(args)
+            | Expr::Table(args) => {
+                args[0].collect_vars(vars);
+                args[1].collect_vars(vars);
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks if expression can be dropped when its value isn't used. Table reads are kept
+    /// cause they can call `__index`, metamethods of arithmetic are ignored.
+    pub fn is_pure(&self) -> bool {
+        match self {
+            Expr::Table(_) => false,
+            Expr::Not(a) | Expr::Len(a) | Expr::Minus(a) => a.is_pure(),
+            Expr::Lt(args)
+            | Expr::Ge(args)
+            | Expr::Le(args)
+            | Expr::Gt(args)
+            | Expr::Eq(args)
+            | Expr::Ne(args)
+            | Expr::Add(args)
+            | Expr::Sub(args)
+            | Expr::Mul(args)
+            | Expr::Div(args)
+            | Expr::Mod(args)
+            | Expr::Pow(args) => args[0].is_pure() && args[1].is_pure(),
+            _ => true,
+        }
+    }
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display(FmtOptions::default()))
+    }
+}
+
+impl fmt::Display for Pretty<'_, Expr> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let o = self.1;
+
         write!(
             f,
             "{}",
-            match self {
+            match self.0 {
                 Expr::Var(a) => format!("v{}", a.0),
                 Expr::Str(a) => format!("\"{}\"", a.escape_debug()),
                 Expr::Num(a) => format!("Num({})", a),
@@ -170,30 +272,38 @@ impl fmt::Display for Expr {
                 Expr::Bool(a) => format!("Bool({})", a),
                 Expr::Cdata(a) => format!("CData({})", a),
                 Expr::Nil => "Nil".to_string(),
-                Expr::Lt([a, b]) => format!("{} < {}", a, b),
-                Expr::Ge([a, b]) => format!("{} ≥ {}", a, b),
-                Expr::Le([a, b]) => format!("{} ≤ {}", a, b),
-                Expr::Gt([a, b]) => format!("{} > {}", a, b),
-                Expr::Eq([a, b]) => format!("{} == {}", a, b),
-                Expr::Ne([a, b]) => format!("{} != {}", a, b),
-                Expr::Not(a) => format!("!{}", a),
-                Expr::Len(a) => format!("len({})", a),
-                Expr::Minus(a) => format!("-{}", a),
-                Expr::Add([a, b]) => format!("{} + {}", a, b),
-                Expr::Sub([a, b]) => format!("{} - {}", a, b),
-                Expr::Mul([a, b]) => format!("{} * {}", a, b),
-                Expr::Div([a, b]) => format!("{} / {}", a, b),
-                Expr::Mod([a, b]) => format!("{} % {}", a, b),
-                Expr::Pow([a, b]) => format!("{}^{}", a, b),
+                Expr::Number(a) => fmt_lua_num_exact(*a),
+                Expr::Lt([a, b]) => format!("{} < {}", a.display(o), b.display(o)),
+                Expr::Ge([a, b]) => format!("{} ≥ {}", a.display(o), b.display(o)),
+                Expr::Le([a, b]) => format!("{} ≤ {}", a.display(o), b.display(o)),
+                Expr::Gt([a, b]) => format!("{} > {}", a.display(o), b.display(o)),
+                Expr::Eq([a, b]) => format!("{} == {}", a.display(o), b.display(o)),
+                Expr::Ne([a, b]) => format!("{} != {}", a.display(o), b.display(o)),
+                Expr::Not(a) => format!("!{}", a.display(o)),
+                Expr::Len(a) => format!("len({})", a.display(o)),
+                Expr::Minus(a) => format!("-{}", a.display(o)),
+                Expr::Add([a, b]) => format!("{} + {}", a.display(o), b.display(o)),
+                Expr::Sub([a, b]) => format!("{} - {}", a.display(o), b.display(o)),
+                Expr::Mul([a, b]) => format!("{} * {}", a.display(o), b.display(o)),
+                Expr::Div([a, b]) => format!("{} / {}", a.display(o), b.display(o)),
+                Expr::Mod([a, b]) => format!("{} % {}", a.display(o), b.display(o)),
+                Expr::Pow([a, b]) => format!("{}^{}", a.display(o), b.display(o)),
                 Expr::GlobalTable => format!("_G"),
-                Expr::Table([a, b]) => format!("{}[{}]", a, b),
+                Expr::Table([a, b]) => match (a.as_ref(), b.as_ref()) {
+                    (Expr::GlobalTable, Expr::Str(name)) if !o.strict_globals && is_name(name) => {
+                        name.clone()
+                    }
+                    (Expr::GlobalTable, _) => format!("_G[{}]", b.display(o)),
+                    (_, Expr::Str(name)) if is_name(name) => format!("{}.{}", a.display(o), name),
+                    _ => format!("{}[{}]", a.display(o), b.display(o)),
+                },
                 Expr::Closure(a) => format!("closure(proto({}))", a),
             }
         )
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Var(pub u16);
 
 impl fmt::Display for Var {
@@ -226,7 +336,7 @@ impl VarInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Insn {
     SetVars(Box<[Var]>, Box<Expr>),
     SetGlobalTableVar([Box<Expr>; 2]),
@@ -235,13 +345,24 @@ pub enum Insn {
     TailCall(Box<[Expr]>),
     Cat(Var, Box<[Expr]>),
     If(Box<Expr>),
+    // test value and copy it to the variable on the copy edge (ISTC/ISFC)
+    IfCopy(Var, Box<Expr>),
     For(Box<[Expr]>),
     While(Box<Expr>),
     Repeat(Box<Expr>),
     Return(Box<[Expr]>),
+    SetUpValue(u16, Box<Expr>),
+    // run time type check, execution stops when slot has another type
+    TypeGuard(Var, Type),
+    // instruction which isn't lifted yet, keeps slots it writes and reads
+    Unlifted(&'static str, Box<[Var]>, Box<[Var]>),
 }
 
 impl Insn {
+    pub fn display(&self, options: FmtOptions) -> Pretty<'_, Insn> {
+        Pretty(self, options)
+    }
+
     pub fn set_var(var: Var, exp: Box<Expr>) -> Insn {
         Insn::SetVars(vec![var].into_boxed_slice(), exp)
     }
@@ -253,14 +374,76 @@ impl Insn {
     pub fn set_table_var(var: Var, idx: Box<Expr>, exp: Box<Expr>) -> Insn {
         Insn::SetTableVar(var, [idx, exp])
     }
+
+    /// Slots written by instruction, `IfCopy` writes its slot on the copy edge only
+    pub fn defs(&self) -> Vec<u16> {
+        match self {
+            Insn::SetVars(vars, _) | Insn::Call(vars, _) | Insn::Unlifted(_, vars, _) => {
+                vars.iter().map(|var| var.0).collect()
+            }
+            Insn::Cat(var, _) | Insn::IfCopy(var, _) => vec![var.0],
+            // visible loop variable follows start, stop and step slots
+            Insn::For(args) => match args.first() {
+                Some(Expr::Var(base)) => vec![base.0 + 3],
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    /// Slots read by instruction
+    pub fn uses(&self) -> BTreeSet<u16> {
+        let mut vars = BTreeSet::new();
+
+        match self {
+            Insn::SetVars(_, expr)
+            | Insn::If(expr)
+            | Insn::IfCopy(_, expr)
+            | Insn::While(expr)
+            | Insn::Repeat(expr)
+            | Insn::SetUpValue(_, expr) => expr.collect_vars(&mut vars),
+            Insn::SetGlobalTableVar(args) => {
+                args[0].collect_vars(&mut vars);
+                args[1].collect_vars(&mut vars);
+            }
+            Insn::SetTableVar(table, args) => {
+                vars.insert(table.0);
+                args[0].collect_vars(&mut vars);
+                args[1].collect_vars(&mut vars);
+            }
+            Insn::Call(_, exprs)
+            | Insn::TailCall(exprs)
+            | Insn::Cat(_, exprs)
+            | Insn::For(exprs)
+            | Insn::Return(exprs) => {
+                for expr in exprs.iter() {
+                    expr.collect_vars(&mut vars);
+                }
+            }
+            Insn::TypeGuard(var, _) => {
+                vars.insert(var.0);
+            }
+            Insn::Unlifted(_, _, slots) => vars.extend(slots.iter().map(|var| var.0)),
+        }
+
+        vars
+    }
 }
 
 impl fmt::Display for Insn {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display(FmtOptions::default()))
+    }
+}
+
+impl fmt::Display for Pretty<'_, Insn> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let o = self.1;
+
         write!(
             f,
             "{}",
-            match self {
+            match self.0 {
                 Insn::SetVars(vars, expr) => {
                     let mut res = "".to_string();
 
@@ -272,12 +455,22 @@ impl fmt::Display for Insn {
                         }
                     }
 
-                    res.push_str(&format!(" = {}", expr));
+                    res.push_str(&format!(" = {}", expr.display(o)));
 
                     res
                 }
-                Insn::SetGlobalTableVar(args) => format!("_G[{}] = {}", args[0], args[1]),
-                Insn::SetTableVar(table, args) => format!("{}[{}] = {}", table, args[0], args[1]),
+                Insn::SetGlobalTableVar(args) => match args[0].as_ref() {
+                    Expr::Str(name) if !o.strict_globals && is_name(name) => {
+                        format!("{} = {}", name, args[1].display(o))
+                    }
+                    _ => format!("_G[{}] = {}", args[0].display(o), args[1].display(o)),
+                },
+                Insn::SetTableVar(table, args) => match args[0].as_ref() {
+                    Expr::Str(name) if is_name(name) => {
+                        format!("{}.{} = {}", table, name, args[1].display(o))
+                    }
+                    _ => format!("{}[{}] = {}", table, args[0].display(o), args[1].display(o)),
+                },
                 Insn::Call(rets, args) => {
                     let mut res = "".to_string();
 
@@ -291,13 +484,13 @@ impl fmt::Display for Insn {
                         res.push_str(" = ");
                     }
 
-                    res.push_str(&format!("{}(", args[0]));
+                    res.push_str(&format!("{}(", args[0].display(o)));
 
                     if args.len() > 1 {
-                        res.push_str(&format!("{}", args[1]));
+                        res.push_str(&format!("{}", args[1].display(o)));
 
                         for arg in args[2..].iter() {
-                            res.push_str(&format!(", {}", arg));
+                            res.push_str(&format!(", {}", arg.display(o)));
                         }
                     }
 
@@ -306,41 +499,60 @@ impl fmt::Display for Insn {
                     res
                 }
                 Insn::Cat(var, exprs) => {
-                    let mut res = format!("{} = {}", var, exprs[0]);
+                    let mut res = format!("{} = {}", var, exprs[0].display(o));
 
                     if exprs.len() > 1 {
                         for expr in exprs[1..].iter() {
-                            res.push_str(&format!(" ~ {}", expr));
+                            res.push_str(&format!(" ~ {}", expr.display(o)));
                         }
                     }
 
                     res
                 }
-                Insn::If(expr) => format!("if {}", expr),
-                Insn::For(args) => format!("for {}, {}, {}", args[0], args[1], args[2]),
-                Insn::While(expr) => format!("while {}", expr),
+                Insn::If(expr) => format!("if {}", expr.display(o)),
+                Insn::IfCopy(var, expr) => {
+                    format!("if {} ({} = {})", expr.display(o), var, expr.display(o))
+                }
+                Insn::For(args) => format!(
+                    "for {}, {}, {}",
+                    args[0].display(o),
+                    args[1].display(o),
+                    args[2].display(o)
+                ),
+                Insn::While(expr) => format!("while {}", expr.display(o)),
                 Insn::Repeat(..) => format!(""),
+                Insn::SetUpValue(uv, expr) => format!("UV({}) = {}", uv, expr.display(o)),
+                Insn::TypeGuard(var, ty) => format!("assert(type({}) == \"{}\")", var, ty),
+                Insn::Unlifted(name, defs, uses) => {
+                    let defs: Vec<String> = defs.iter().map(|var| var.to_string()).collect();
+                    let uses: Vec<String> = uses.iter().map(|var| var.to_string()).collect();
+
+                    match defs.is_empty() {
+                        true => format!("-- {} {}", name, uses.join(", ")),
+                        false => format!("-- {} {} = {}", name, defs.join(", "), uses.join(", ")),
+                    }
+                }
                 Insn::Return(expr) => {
                     let mut res = "return".to_string();
 
                     if expr.len() >= 1 {
-                        res.push_str(&format!(" {}", expr[0]));
+                        res.push_str(&format!(" {}", expr[0].display(o)));
 
                         for ret in expr[1..].iter() {
-                            res.push_str(&format!(", {}", ret));
+                            res.push_str(&format!(", {}", ret.display(o)));
                         }
                     }
 
                     res
                 }
                 Insn::TailCall(args) => {
-                    let mut res = format!("return {}(", args[0]);
+                    let mut res = format!("return {}(", args[0].display(o));
 
                     if args.len() > 1 {
-                        res.push_str(&format!("{}", args[1]));
+                        res.push_str(&format!("{}", args[1].display(o)));
 
                         for arg in args[2..].iter() {
-                            res.push_str(&format!(", {}", arg))
+                            res.push_str(&format!(", {}", arg.display(o)))
                         }
                     }
 
@@ -353,7 +565,10 @@ impl fmt::Display for Insn {
     }
 }
 
-#[derive(Default)]
+/// Index of the entry block, lifted graph keeps basic block indices so it starts with the first instruction
+pub const ENTRY_BLOCK: u32 = 0;
+
+#[derive(Debug, Clone, Default)]
 pub struct Block {
     data: Vec<Insn>,
 }
@@ -363,15 +578,85 @@ impl Block {
         self.data.push(ins);
     }
 
-    pub fn iter_insn(&self) -> impl Iterator<Item = &Insn> {
+    pub fn iter_insn(&self) -> impl DoubleEndedIterator<Item = &Insn> {
         self.data.iter()
     }
+
+    pub fn insns_mut(&mut self) -> &mut Vec<Insn> {
+        &mut self.data
+    }
+
+    /// Returns condition which holds when outgoing edge of passed kind is taken
+    pub fn branch_condition(&self, kind: BranchKind) -> Option<Box<Expr>> {
+        let cond = match self.data.last()? {
+            Insn::If(expr) | Insn::IfCopy(_, expr) => expr.clone(),
+            _ => return None,
+        };
+
+        match kind.polarity()? {
+            true => Some(cond),
+            false => Some(Expr::not(cond)),
+        }
+    }
+
+    /// Returns assignment which is performed when outgoing edge of passed kind is taken
+    pub fn branch_copy(&self, kind: BranchKind) -> Option<Insn> {
+        match self.data.last()? {
+            Insn::IfCopy(var, expr) if kind.is_copy() => Some(Insn::set_var(var.clone(), expr.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Formats lifted blocks with their instructions
+pub struct LiftedBlockFormatter;
+
+impl GraphFormatter<Block, BranchKind> for LiftedBlockFormatter {
+    fn node_label(&self, index: u32, node: &Block) -> String {
+        let mut lines = vec![format!("Block({})", index)];
+        lines.extend(node.iter_insn().map(|ins| format!("{}", ins)));
+
+        lines.join("\n")
+    }
+
+    fn edge_label(&self, edge: &BranchKind) -> String {
+        format!("{:?}", edge)
+    }
+
+    fn edge_color(&self, edge: &BranchKind) -> Option<&'static str> {
+        Some(edge.color())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use crate::graph::Graph;
+    use crate::ir::{Block, Insn};
+    use crate::resolver::BranchKind;
+
+    pub fn block(insns: Vec<Insn>) -> Block {
+        let mut block = Block::default();
+        for insn in insns {
+            block.push_insn(insn);
+        }
+        block
+    }
+
+    pub fn lines(graph: &Graph<Block, BranchKind>, idx: u32) -> Vec<String> {
+        graph
+            .node_weight(idx)
+            .unwrap()
+            .iter_insn()
+            .map(|insn| insn.to_string())
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ir::Expr;
+    use crate::ir::{Block, Expr, FmtOptions, Insn};
     use crate::ir::Var;
+    use crate::resolver::BranchKind;
 
     #[test]
     fn expressions_fmt() {
@@ -381,4 +666,41 @@ mod tests {
 
         assert_eq!("v1 ≥ Nil", format!("{}", expr3));
     }
+
+    #[test]
+    fn field_access_fmt() {
+        let field = |table: Box<Expr>, name: &str| Expr::table(table, Expr::str(name.to_string()));
+        let strict = FmtOptions {
+            strict_globals: true,
+        };
+
+        let expr = field(field(Box::new(Expr::GlobalTable), "lib"), "sys");
+        assert_eq!("lib.sys", format!("{}", expr));
+        assert_eq!("_G[\"lib\"].sys", format!("{}", expr.display(strict)));
+
+        assert_eq!("v0[\"end\"]", format!("{}", field(Expr::var(0), "end")));
+        assert_eq!("v0[\"a b\"]", format!("{}", field(Expr::var(0), "a b")));
+        assert_eq!("v0[\"1x\"]", format!("{}", field(Expr::var(0), "1x")));
+        assert_eq!("_G[\"v1\"]", format!("{}", field(Box::new(Expr::GlobalTable), "v1")));
+
+        let insn = Insn::set_global_table_var(Expr::str("x".to_string()), Expr::var(1));
+        assert_eq!("x = v1", format!("{}", insn));
+        assert_eq!("_G[\"x\"] = v1", format!("{}", insn.display(strict)));
+
+        let insn = Insn::set_table_var(Var(0), Expr::str("_y2".to_string()), Expr::short(1));
+        assert_eq!("v0._y2 = Short(1)", format!("{}", insn));
+    }
+
+    #[test]
+    fn branch_conditions() {
+        let mut block = Block::default();
+        block.push_insn(Insn::IfCopy(Var(0), Expr::var(1)));
+
+        assert_eq!("v1", format!("{}", block.branch_condition(BranchKind::TrueCopy).unwrap()));
+        assert_eq!("!v1", format!("{}", block.branch_condition(BranchKind::False).unwrap()));
+        assert!(block.branch_condition(BranchKind::Unconditional).is_none());
+
+        assert_eq!("v0 = v1", format!("{}", block.branch_copy(BranchKind::TrueCopy).unwrap()));
+        assert!(block.branch_copy(BranchKind::False).is_none());
+    }
 }
@@ -1,7 +1,8 @@
-use crate::disasm::disasm;
+use crate::instruction::Instruction;
 use crate::ir::{Block, Expr, Insn, Var, VarInfo};
 use crate::op::Op;
 use crate::resolver::BranchKind;
+use crate::type_inference::Type;
 use crate::{ByteCodeProto, DecompileError, Graph};
 use std::collections::HashMap;
 
@@ -38,82 +39,54 @@ impl Lifter {
         let mut graph: Graph<Block, BranchKind> = bc_proto.basic_block_graph_ref().structure_copy();
 
         for (block_idx, basic_block) in bc_proto.basic_block_graph_ref().iter_node_weights() {
-            let mut iter = basic_block.data().iter();
+            let instructions = Instruction::decode_all(block_idx, basic_block.data())?;
 
             let analyzed_block = graph.node_weight_mut(block_idx).unwrap();
 
-            while let Some(&raw_ins) = iter.next() {
-                match disasm(raw_ins)? {
-                    // comparison
-                    Op::ISLT(a, b) => {
+            for ins in instructions {
+                match ins.op {
+                    // comparison, odd opcodes are negated even ones and lifted in positive
+                    // form, polarity is kept by edge kinds
+                    Op::ISLT(a, b) | Op::ISGE(a, b) => {
                         analyzed_block
                             .push_insn(Insn::If(Expr::lt(Expr::var(a.0), Expr::var(b.0))));
                     }
-                    Op::ISGE(a, b) => {
-                        analyzed_block
-                            .push_insn(Insn::If(Expr::ge(Expr::var(a.0), Expr::var(b.0))));
-                    }
-                    Op::ISLE(a, b) => {
+                    Op::ISLE(a, b) | Op::ISGT(a, b) => {
                         analyzed_block
                             .push_insn(Insn::If(Expr::le(Expr::var(a.0), Expr::var(b.0))));
                     }
-                    Op::ISGT(a, b) => {
-                        analyzed_block
-                            .push_insn(Insn::If(Expr::gt(Expr::var(a.0), Expr::var(b.0))));
-                    }
-                    Op::ISEQV(a, b) => {
+                    Op::ISEQV(a, b) | Op::ISNEV(a, b) => {
                         analyzed_block
                             .push_insn(Insn::If(Expr::eq(Expr::var(a.0), Expr::var(b.0))));
                     }
-                    Op::ISNEV(a, b) => {
-                        analyzed_block
-                            .push_insn(Insn::If(Expr::ne(Expr::var(a.0), Expr::var(b.0))));
-                    }
-                    Op::ISEQS(a, b) => {
-                        let str = bc_proto.str_from_global_table(b.0).unwrap();
-                        analyzed_block
-                            .push_insn(Insn::If(Expr::eq(Expr::var(a.0), Expr::str(str.clone()))));
-                    }
-                    Op::ISNES(a, b) => {
-                        let str = bc_proto.str_from_global_table(b.0).unwrap();
+                    Op::ISEQS(a, b) | Op::ISNES(a, b) => {
+                        let str = bc_proto
+                            .str_from_global_table(b.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
                         analyzed_block
-                            .push_insn(Insn::If(Expr::ne(Expr::var(a.0), Expr::str(str.clone()))));
+                            .push_insn(Insn::If(Expr::eq(Expr::var(a.0), Expr::str(str))));
                     }
-                    Op::ISEQN(a, b) => {
+                    Op::ISEQN(a, b) | Op::ISNEN(a, b) => {
                         analyzed_block
                             .push_insn(Insn::If(Expr::eq(Expr::var(a.0), Expr::num(b.0))));
                     }
-                    Op::ISNEN(a, b) => {
-                        analyzed_block
-                            .push_insn(Insn::If(Expr::ne(Expr::var(a.0), Expr::num(b.0))));
-                    }
-                    Op::ISEQP(a, b) => {
+                    Op::ISEQP(a, b) | Op::ISNEP(a, b) => {
                         analyzed_block
                             .push_insn(Insn::If(Expr::eq(Expr::var(a.0), Expr::primitive(b))));
                     }
-                    Op::ISNEP(a, b) => {
-                        analyzed_block
-                            .push_insn(Insn::If(Expr::ne(Expr::var(a.0), Expr::primitive(b))));
-                    }
-                    // unary copy and test
-                    Op::ISTC(a, b) => {
+                    // unary copy and test, copy is done only on the jump edge
+                    Op::ISTC(a, b) | Op::ISFC(a, b) => {
                         let var = self.var_for_slot(a.0, false, false);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::var(b.0)));
-                        analyzed_block.push_insn(Insn::If(Expr::var(b.0)));
+                        analyzed_block.push_insn(Insn::IfCopy(var, Expr::var(b.0)));
                     }
-                    Op::ISFC(a, b) => {
-                        let var = self.var_for_slot(a.0, false, false);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::var(b.0)));
-                        analyzed_block.push_insn(Insn::If(Expr::not(Expr::var(b.0))));
-                    }
-                    Op::IST(a) => {
+                    Op::IST(a) | Op::ISF(a) => {
                         analyzed_block.push_insn(Insn::If(Expr::var(a.0)));
                     }
-                    Op::ISF(a) => {
-                        analyzed_block.push_insn(Insn::If(Expr::not(Expr::var(a.0))));
+                    Op::ISTYPE(a, b) => analyzed_block
+                        .push_insn(Insn::TypeGuard(Var(a.0), Type::from_type_tag(b.0))),
+                    Op::ISNUM(a, _) => {
+                        analyzed_block.push_insn(Insn::TypeGuard(Var(a.0), Type::Number))
                     }
-                    Op::ISTYPE(_, _) => unimplemented!("ISTYPE"),
-                    Op::ISNUM(_, _) => unimplemented!("ISNUM"),
                     // unary
                     Op::MOV(a, b) => {
                         let var = self.var_for_slot(a.0, false, false);
@@ -260,9 +233,11 @@ impl Lifter {
                     }
                     // constants
                     Op::KSTR(a, b) => {
-                        let str = bc_proto.str_from_global_table(b.0).unwrap();
+                        let str = bc_proto
+                            .str_from_global_table(b.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
                         let var = self.var_for_slot(a.0, false, false);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::str(str.clone())))
+                        analyzed_block.push_insn(Insn::set_var(var, Expr::str(str)))
                     }
                     Op::KCDATA(a, b) => {
                         let var = self.var_for_slot(a.0, false, false);
@@ -301,21 +276,19 @@ impl Lifter {
                         analyzed_block.push_insn(Insn::set_var(var, Expr::uv(b.0)))
                     }
                     Op::USETV(a, b) => {
-                        let var = self.var_for_slot(a.0, false, true);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::var(b.0)))
+                        analyzed_block.push_insn(Insn::SetUpValue(a.0, Expr::var(b.0)))
                     }
                     Op::USETS(a, b) => {
-                        let var = self.var_for_slot(a.0, false, true);
-                        let str = bc_proto.str_from_global_table(b.0).unwrap();
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::str(str.clone())))
+                        let str = bc_proto
+                            .str_from_global_table(b.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
+                        analyzed_block.push_insn(Insn::SetUpValue(a.0, Expr::str(str)))
                     }
                     Op::USETN(a, b) => {
-                        let var = self.var_for_slot(a.0, false, true);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::num(b.0)))
+                        analyzed_block.push_insn(Insn::SetUpValue(a.0, Expr::num(b.0)))
                     }
                     Op::USETP(a, b) => {
-                        let var = self.var_for_slot(a.0, false, true);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::primitive(b)))
+                        analyzed_block.push_insn(Insn::SetUpValue(a.0, Expr::primitive(b)))
                     }
                     Op::UCLO(_, _) => {
                         // close all up values from slot rbase
@@ -325,23 +298,23 @@ impl Lifter {
                         analyzed_block.push_insn(Insn::set_var(var, Expr::closure(b.0)))
                     }
                     // tables
-                    Op::TNEW(_, _) => {}
-                    Op::TDUP(a, b) => {
-                        let var = self.var_for_slot(a.0, false, false);
-                        analyzed_block.push_insn(Insn::set_var(var, Expr::var(b.0)))
-                    }
+                    Op::TNEW(_, _) | Op::TDUP(_, _) => analyzed_block.push_insn(unlifted(&ins)),
                     Op::GGET(a, b) => {
-                        let str = bc_proto.str_from_global_table(b.0).unwrap();
+                        let str = bc_proto
+                            .str_from_global_table(b.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
                         let var = self.var_for_slot(a.0, false, false);
                         analyzed_block.push_insn(Insn::set_var(
                             var,
-                            Expr::table(Box::new(Expr::GlobalTable), Expr::str(str.clone())),
+                            Expr::table(Box::new(Expr::GlobalTable), Expr::str(str)),
                         ));
                     }
                     Op::GSET(a, b) => {
-                        let str = bc_proto.str_from_global_table(b.0).unwrap();
+                        let str = bc_proto
+                            .str_from_global_table(b.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
                         analyzed_block.push_insn(Insn::set_global_table_var(
-                            Expr::str(str.clone()),
+                            Expr::str(str),
                             Expr::var(a.0),
                         ));
                     }
@@ -353,11 +326,13 @@ impl Lifter {
                         ));
                     }
                     Op::TGETS(a, b, c) => {
-                        let str = bc_proto.str_from_global_table(c.0).unwrap();
+                        let str = bc_proto
+                            .str_from_global_table(c.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
                         let var = self.var_for_slot(a.0, false, false);
                         analyzed_block.push_insn(Insn::set_var(
                             var,
-                            Expr::table(Expr::var(b.0), Expr::str(str.clone())),
+                            Expr::table(Expr::var(b.0), Expr::str(str)),
                         ));
                     }
                     Op::TGETB(a, b, c) => {
@@ -378,10 +353,12 @@ impl Lifter {
                     }
                     Op::TSETS(a, b, c) => {
                         let var = self.var_for_slot(b.0, true, false);
-                        let str = bc_proto.str_from_global_table(c.0).unwrap();
+                        let str = bc_proto
+                            .str_from_global_table(c.0)
+                            .ok_or(DecompileError::InvalidConstIndex)?;
                         analyzed_block.push_insn(Insn::set_table_var(
                             var,
-                            Expr::str(str.clone()),
+                            Expr::str(str),
                             Expr::var(a.0),
                         ));
                     }
@@ -393,10 +370,10 @@ impl Lifter {
                             Expr::var(a.0),
                         ));
                     }
-                    Op::TSETM(a, b) => {}
+                    Op::TSETM(_, _) => analyzed_block.push_insn(unlifted(&ins)),
                     Op::TSETR(_, _, _) => unimplemented!("TGETR"),
                     // call and vararg
-                    Op::CALLM(a, b, c) => {
+                    Op::CALLM(a, b, _) => {
                         let mut returns: Vec<Var> = vec![];
 
                         if b.0 != 0 {
@@ -406,13 +383,9 @@ impl Lifter {
                             }
                         }
 
-                        let mut args: Vec<Expr> = vec![];
-
-                        if c.0 as u16 + self.multres > 0 {
-                            for idx in a.0..(a.0 + b.0 as u16 + self.multres) {
-                                args.push(Expr::Var(Var(idx)))
-                            }
-                        }
+                        // function and fixed arguments, MULTRES ones follow them
+                        let args: Vec<Expr> =
+                            ins.uses().into_iter().map(|idx| Expr::Var(Var(idx))).collect();
 
                         analyzed_block.push_insn(Insn::Call(
                             returns.into_boxed_slice(),
@@ -457,7 +430,7 @@ impl Lifter {
                             args.into_boxed_slice(),
                         ));
                     }
-                    Op::CALLMT(_, _) => {}
+                    Op::CALLMT(_, _) => analyzed_block.push_insn(unlifted(&ins)),
                     Op::CALLT(a, b) => {
                         let mut res: Vec<Expr> = vec![];
 
@@ -467,28 +440,16 @@ impl Lifter {
 
                         analyzed_block.push_insn(Insn::TailCall(res.into_boxed_slice()));
                     }
-                    Op::ITERC(_, _, _) => {}
-                    Op::ITERN(_, _, _) => {}
-                    Op::VARG(_, _, _) => {}
-                    Op::ISNEXT(_, _) => {}
-                    // returns
-                    Op::RETM(a, b) => {
-                        let mut res: Vec<Expr> =
-                            Vec::with_capacity((a.0 + b.0 as u16 - 2) as usize);
-
-                        for idx in a.0..=(b.0 as u16 - 1) {
-                            res.push(Expr::Var(Var(idx)))
-                        }
-
-                        analyzed_block.push_insn(Insn::Return(res.into_boxed_slice()));
+                    Op::ITERC(_, _, _) | Op::ITERN(_, _, _) => {
+                        analyzed_block.push_insn(unlifted(&ins))
                     }
-                    Op::RET(a, b) => {
-                        let mut res: Vec<Expr> =
-                            Vec::with_capacity((a.0 + b.0 as u16 - 2) as usize);
-
-                        for idx in a.0..=(b.0 as u16 - 2) {
-                            res.push(Expr::Var(Var(idx)))
-                        }
+                    Op::VARG(_, _, _) => analyzed_block.push_insn(unlifted(&ins)),
+                    Op::ISNEXT(_, _) => analyzed_block.push_insn(unlifted(&ins)),
+                    // returns
+                    // RETM returns D fixed results and MULTRES, RET returns D - 1 results
+                    Op::RETM(_, _) | Op::RET(_, _) => {
+                        let res: Vec<Expr> =
+                            ins.uses().into_iter().map(|idx| Expr::Var(Var(idx))).collect();
 
                         analyzed_block.push_insn(Insn::Return(res.into_boxed_slice()));
                     }
@@ -509,13 +470,13 @@ impl Lifter {
 
                         analyzed_block.push_insn(Insn::For(args.into_boxed_slice()));
                     }
-                    Op::JFORI(_, _) => {}
-                    Op::FORL(_, _) => {}
-                    Op::IFORL(_, _) => {}
-                    Op::JFORL(_, _) => {}
-                    Op::ITERL(_, _) => {}
-                    Op::IITERL(_, _) => {}
-                    Op::JITERL(_, _) => {}
+                    Op::JFORI(_, _)
+                    | Op::FORL(_, _)
+                    | Op::IFORL(_, _)
+                    | Op::JFORL(_, _)
+                    | Op::ITERL(_, _)
+                    | Op::IITERL(_, _)
+                    | Op::JITERL(_, _) => analyzed_block.push_insn(unlifted(&ins)),
                     Op::LOOP(a, _) => {
                         analyzed_block.push_insn(Insn::While(Expr::var(a.0)));
                     }
@@ -535,17 +496,14 @@ impl Lifter {
             }
         }
 
-        print_lifted_graph(&graph);
-
         Ok(graph)
     }
 }
 
-fn print_lifted_graph(graph: &Graph<Block, BranchKind>) {
-    for (idx, block) in graph.iter_node_weights() {
-        println!("Block({})", idx);
-        for ins in block.iter_insn() {
-            println!("{}", ins);
-        }
-    }
+// placeholder which keeps slots written and read by instruction visible for analyses
+fn unlifted(ins: &Instruction) -> Insn {
+    let defs: Vec<Var> = ins.defs().into_iter().map(Var).collect();
+    let uses: Vec<Var> = ins.uses().into_iter().map(Var).collect();
+
+    Insn::Unlifted(ins.mnemonic(), defs.into_boxed_slice(), uses.into_boxed_slice())
 }
@@ -0,0 +1,449 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::bytecode_reader::GlobalConst;
+use crate::op::{op_info, OpMode};
+use crate::{ByteCodeDump, ByteCodeProto};
+
+// longer string constants are cut in comments
+const MAX_STR_COMMENT: usize = 40;
+
+/// Formats number the same way as lua `tostring` (`%.14g`)
+pub fn fmt_lua_num(val: f64) -> String {
+    fmt_num_digits(val, 14)
+}
+
+/// Formats number as the shortest `%.Ng` literal which is parsed back to the same value
+pub fn fmt_lua_num_exact(val: f64) -> String {
+    (14..17)
+        .map(|digits| fmt_num_digits(val, digits))
+        .find(|num| num.parse::<f64>().is_ok_and(|num| num == val))
+        .unwrap_or_else(|| fmt_num_digits(val, 17))
+}
+
+// `%.Ng` formatting, trailing zeros are trimmed
+fn fmt_num_digits(val: f64, digits: usize) -> String {
+    if val.is_nan() {
+        return "nan".to_string();
+    }
+
+    if val.is_infinite() {
+        return if val > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    if val == 0.0 {
+        return if val.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+
+    fn trim_zeros(num: &str) -> &str {
+        if num.contains('.') {
+            num.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            num
+        }
+    }
+
+    // exponent decides between fixed and scientific notation
+    let sci = format!("{:.*e}", digits - 1, val);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+
+    if !(-4..digits as i32).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(mantissa), sign, exp.abs())
+    } else {
+        trim_zeros(&format!("{:.*}", (digits as i32 - 1 - exp) as usize, val)).to_string()
+    }
+}
+
+// quote string constant, control chars are escaped
+fn fmt_str_comment(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len());
+
+    for ch in str.chars() {
+        match ch {
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if ch.is_ascii_control() => write!(escaped, "\\{:03}", ch as u8).unwrap(),
+            ch => escaped.push(ch),
+        }
+    }
+
+    if str.len() > MAX_STR_COMMENT {
+        let mut end = MAX_STR_COMMENT;
+        while !escaped.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        format!("\"{}\"~", &escaped[..end])
+    } else {
+        format!("\"{}\"", escaped)
+    }
+}
+
+// chunk name without `@`/`=` prefix, `?` for stripped dumps
+fn chunk_name(dump: &ByteCodeDump) -> String {
+    let name = dump.name();
+
+    if name.is_empty() {
+        return "?".to_string();
+    }
+
+    name.strip_prefix(&['@', '='][..]).unwrap_or(&name).to_string()
+}
+
+// pcs are counted from 1, function header instruction is not dumped
+#[inline(always)]
+fn jump_dest(pc: usize, d: u16) -> i64 {
+    pc as i64 + d as i64 - 0x7fff
+}
+
+fn const_comment(
+    dump: &ByteCodeDump,
+    proto: &ByteCodeProto,
+    name: &str,
+    mode: OpMode,
+    d: u16,
+) -> Option<String> {
+    match mode {
+        OpMode::Str => proto
+            .str_from_global_table(d)
+            .map(|str| fmt_str_comment(&str)),
+        OpMode::Num => proto.num_consts().get(d as usize).map(|num| {
+            let mut val = num.to_f64();
+            // TSETM keeps start index in the low bits of a double
+            if name == "TSETM" {
+                val -= 2f64.powi(52);
+            }
+
+            fmt_lua_num(val)
+        }),
+        OpMode::Func => {
+            let idx = proto.global_consts().len().checked_sub(d as usize + 1)?;
+
+            match proto.global_consts().get(idx)? {
+                GlobalConst::ProtoChild(child) => {
+                    let child = dump.prototypes().get(*child)?;
+                    Some(format!("{}:{}", chunk_name(dump), child.first_line()))
+                }
+                _ => None,
+            }
+        }
+        OpMode::UV => uv_comment(proto, d),
+        _ => None,
+    }
+}
+
+// luajit prints empty name of up value when debug info is stripped
+fn uv_comment(proto: &ByteCodeProto, idx: u16) -> Option<String> {
+    if idx as usize >= proto.up_values().len() {
+        return None;
+    }
+
+    Some(proto.up_value_name(idx).unwrap_or_default())
+}
+
+/// Formats single instruction line of `luajit -bl` listing, `idx` is an index in prototype bytecode
+pub fn ins_listing_line(
+    dump: &ByteCodeDump,
+    proto: &ByteCodeProto,
+    idx: usize,
+    target: bool,
+) -> String {
+    let ins_raw = proto.bc_raw()[idx];
+    let pc = idx + 1;
+    let prefix = if target { "=>" } else { "  " };
+
+    let info = match op_info(ins_raw) {
+        Some(info) => info,
+        None => return format!("{:04} {} ??? 0x{:08x}", pc, prefix, ins_raw),
+    };
+
+    let a = (ins_raw >> 8) & 0xff;
+    let a = if info.a == OpMode::None {
+        String::new()
+    } else {
+        a.to_string()
+    };
+    let line = format!("{:04} {} {:<6} {:>3} ", pc, prefix, info.name, a);
+
+    let mut d = (ins_raw >> 16) as u16;
+    if info.cd == OpMode::Jump {
+        return format!("{}=> {:04}", line, jump_dest(pc, d));
+    }
+
+    if info.b != OpMode::None {
+        d &= 0xff;
+    } else if info.cd == OpMode::None {
+        return line;
+    }
+
+    let mut comment = const_comment(dump, proto, info.name, info.cd, d);
+
+    // name of up value in A operand precedes constant, e.g. `; name ; "str"`
+    if info.a == OpMode::UV {
+        if let Some(name) = uv_comment(proto, (ins_raw >> 8) as u16 & 0xff) {
+            comment = Some(match comment {
+                Some(comment) => format!("{} ; {}", name, comment),
+                None => name,
+            });
+        }
+    }
+
+    if info.b != OpMode::None {
+        let b = ins_raw >> 24;
+
+        return match comment {
+            Some(comment) => format!("{}{:3} {:3}  ; {}", line, b, d, comment),
+            None => format!("{}{:3} {:3}", line, b, d),
+        };
+    }
+
+    if let Some(comment) = comment {
+        return format!("{}{:3}      ; {}", line, d, comment);
+    }
+
+    if info.cd == OpMode::LitS {
+        format!("{}{:3}", line, d as i16)
+    } else {
+        format!("{}{:3}", line, d)
+    }
+}
+
+/// Writes prototype listing in `luajit -bl` format
+pub fn write_proto_listing<W: Write>(
+    out: &mut W,
+    dump: &ByteCodeDump,
+    proto_idx: usize,
+) -> std::fmt::Result {
+    let proto = &dump.prototypes()[proto_idx];
+
+    writeln!(
+        out,
+        "-- BYTECODE -- {}:{}-{}",
+        chunk_name(dump),
+        proto.first_line(),
+        proto.first_line() + proto.num_lines()
+    )?;
+
+    // collect jump destinations to mark them with `=>`
+    let targets: HashSet<i64> = proto
+        .bc_raw()
+        .iter()
+        .enumerate()
+        .filter(|(_, &ins_raw)| op_info(ins_raw).is_some_and(|info| info.cd == OpMode::Jump))
+        .map(|(idx, &ins_raw)| jump_dest(idx + 1, (ins_raw >> 16) as u16))
+        .collect();
+
+    for idx in 0..proto.bc_raw().len() {
+        let target = targets.contains(&(idx as i64 + 1));
+        writeln!(out, "{}", ins_listing_line(dump, proto, idx, target))?;
+    }
+
+    writeln!(out)
+}
+
+/// Returns listing of all dump prototypes, children are listed before their parents like in `luajit -bl`
+pub fn dump_listing(dump: &ByteCodeDump) -> String {
+    let mut out = String::new();
+
+    for proto_idx in 0..dump.prototypes().len() {
+        write_proto_listing(&mut out, dump, proto_idx).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::{ins_abc, ins_ad};
+    use std::fs::{self, File};
+
+    use crate::assembler::assemble_listing;
+    use crate::listing::{dump_listing, fmt_lua_num, fmt_lua_num_exact};
+    use crate::utils::parse_luajit_bytecode_file;
+    use crate::{read_bytecode_dump, write_bytecode_dump, write_uleb128, ByteCodeDump, BC_F_STRIP};
+
+    // dump of `@test.lua` with single prototype at lines 1-4
+    fn test_dump() -> Vec<u8> {
+        let bc = [
+            ins_ad(0x27, 0, 0),      // KSTR
+            ins_ad(0x29, 1, 0xffff), // KSHORT
+            ins_ad(0x00, 0, 1),      // ISLT
+            ins_ad(0x58, 2, 0x8001), // JMP
+            ins_ad(0x2a, 1, 0),      // KNUM
+            ins_ad(0x36, 2, 1),      // GGET
+            ins_abc(0x42, 2, 1, 2),  // CALL
+            ins_ad(0x4b, 0, 1),      // RET0
+        ];
+
+        let mut proto = vec![0, 0, 4, 0];
+        write_uleb128(&mut proto, 2).unwrap(); // global constants
+        write_uleb128(&mut proto, 1).unwrap(); // num constants
+        write_uleb128(&mut proto, bc.len() as u32).unwrap();
+
+        // debug info: line per instruction and empty variable info
+        let debug: Vec<u8> = vec![0, 1, 1, 1, 2, 3, 3, 3, 0];
+        write_uleb128(&mut proto, debug.len() as u32).unwrap();
+        write_uleb128(&mut proto, 1).unwrap();
+        write_uleb128(&mut proto, 3).unwrap();
+
+        for ins in bc {
+            proto.extend_from_slice(&ins.to_le_bytes());
+        }
+
+        for str in ["print", "hello\n"] {
+            write_uleb128(&mut proto, 5 + str.len() as u32).unwrap();
+            proto.extend_from_slice(str.as_bytes());
+        }
+
+        // 3.5 as 33 bit uleb with number flag and high word
+        let num = 3.5f64.to_bits();
+        let lo = num as u32 as u64;
+        let mut val = lo << 1 | 1;
+        while val >= 0x80 {
+            proto.push((val & 0x7f) as u8 | 0x80);
+            val >>= 7;
+        }
+        proto.push(val as u8);
+        write_uleb128(&mut proto, (num >> 32) as u32).unwrap();
+
+        proto.extend_from_slice(&debug);
+
+        let mut dump = vec![0x1b, 0x4c, 0x4a, 2, 0];
+        write_uleb128(&mut dump, 9).unwrap();
+        dump.extend_from_slice(b"@test.lua");
+        write_uleb128(&mut dump, proto.len() as u32).unwrap();
+        dump.extend_from_slice(&proto);
+        dump.push(0);
+
+        dump
+    }
+
+    // dump of `@uv.lua` with prototype using up values `a` and `counter`
+    fn uv_dump() -> Vec<u8> {
+        let bc = [
+            ins_ad(0x2d, 0, 1), // UGET
+            ins_ad(0x2f, 0, 0), // USETS
+            ins_ad(0x2e, 1, 0), // USETV
+            ins_ad(0x4b, 0, 1), // RET0
+        ];
+
+        let mut proto = vec![0, 0, 2, 2];
+        write_uleb128(&mut proto, 1).unwrap(); // global constants
+        write_uleb128(&mut proto, 0).unwrap(); // num constants
+        write_uleb128(&mut proto, bc.len() as u32).unwrap();
+
+        // debug info: line per instruction, up value names and empty variable info
+        let debug: Vec<u8> = [&[0, 1, 1, 2][..], b"a\0counter\0", &[0]].concat();
+        write_uleb128(&mut proto, debug.len() as u32).unwrap();
+        write_uleb128(&mut proto, 1).unwrap();
+        write_uleb128(&mut proto, 2).unwrap();
+
+        for ins in bc {
+            proto.extend_from_slice(&ins.to_le_bytes());
+        }
+        for uv in [0xc000u16, 0x0001] {
+            proto.extend_from_slice(&uv.to_le_bytes());
+        }
+
+        write_uleb128(&mut proto, 5 + 1).unwrap();
+        proto.push(b'x');
+        proto.extend_from_slice(&debug);
+
+        let mut dump = vec![0x1b, 0x4c, 0x4a, 2, 0];
+        write_uleb128(&mut dump, 7).unwrap();
+        dump.extend_from_slice(b"@uv.lua");
+        write_uleb128(&mut dump, proto.len() as u32).unwrap();
+        dump.extend_from_slice(&proto);
+        dump.push(0);
+
+        dump
+    }
+
+    #[test]
+    fn lua_num_fmt() {
+        assert_eq!(fmt_lua_num(3.0), "3");
+        assert_eq!(fmt_lua_num(-0.5), "-0.5");
+        assert_eq!(fmt_lua_num(0.1), "0.1");
+        assert_eq!(fmt_lua_num(1e100), "1e+100");
+        assert_eq!(fmt_lua_num(0.00001), "1e-05");
+        assert_eq!(fmt_lua_num(123456789012345.0), "1.2345678901234e+14");
+
+        assert_eq!(fmt_lua_num_exact(0.1), "0.1");
+        assert_eq!(fmt_lua_num_exact(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(fmt_lua_num_exact(2f64.powi(53)), "9007199254740992");
+        assert_eq!(fmt_lua_num_exact(1e100), "1e+100");
+    }
+
+    #[test]
+    fn listing_format() {
+        let dump = read_bytecode_dump(&mut test_dump().as_slice()).unwrap();
+
+        let expected = "-- BYTECODE -- test.lua:1-4\n\
+            0001    KSTR     0   0      ; \"hello\\n\"\n\
+            0002    KSHORT   1  -1\n\
+            0003    ISLT     0   1\n\
+            0004    JMP      2 => 0006\n\
+            0005    KNUM     1   0      ; 3.5\n\
+            0006 => GGET     2   1      ; \"print\"\n\
+            0007    CALL     2   1   2\n\
+            0008    RET0     0   1\n\
+            \n";
+
+        assert_eq!(dump_listing(&dump), expected);
+    }
+
+    #[test]
+    fn listing_up_value_names() {
+        let dump = read_bytecode_dump(&mut uv_dump().as_slice()).unwrap();
+
+        let expected = "-- BYTECODE -- uv.lua:1-3\n\
+            0001    UGET     0   1      ; counter\n\
+            0002    USETS    0   0      ; a ; \"x\"\n\
+            0003    USETV    1   0      ; counter\n\
+            0004    RET0     0   1\n\
+            \n";
+
+        assert_eq!(dump_listing(&dump), expected);
+
+        // names are empty without debug info
+        let stripped = ByteCodeDump::from_prototypes(BC_F_STRIP, vec![], dump.prototypes().clone());
+        let mut data = vec![];
+        write_bytecode_dump(&mut data, &stripped).unwrap();
+        let stripped = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        assert!(dump_listing(&stripped).contains("0002    USETS    0   0      ;  ; \"x\"\n"));
+
+        // up value names aren't taken for constants by assembler
+        let data = assemble_listing(expected, Some(&dump)).unwrap();
+        let assembled = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            assembled.prototypes()[0].str_from_global_table(0).unwrap(),
+            "x"
+        );
+    }
+
+    #[test]
+    fn listing_round_trip() {
+        let dump = read_bytecode_dump(&mut test_dump().as_slice()).unwrap();
+
+        let path = std::env::temp_dir().join("jilua_listing_round_trip.txt");
+        fs::write(&path, dump_listing(&dump)).unwrap();
+
+        let parser_prototypes = parse_luajit_bytecode_file(File::open(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(parser_prototypes.len(), dump.prototypes().len());
+
+        for (proto, parser_blocks) in dump.prototypes().iter().zip(parser_prototypes.iter()) {
+            let graph = proto.basic_block_graph_ref();
+            assert_eq!(graph.node_count(), parser_blocks.len());
+
+            for (block_idx, block) in graph.iter_node_weights() {
+                let parser_block = parser_blocks.get(&(block_idx as u16)).unwrap();
+                assert_eq!(parser_block.len(), block.len());
+            }
+        }
+    }
+}
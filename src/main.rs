@@ -0,0 +1,159 @@
+use jilua::audit::Auditor;
+use jilua::listing::dump_listing;
+use jilua::{read_bytecode_dump, ByteCodeDump, DecompileError};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: jilua <dump> [--dot <dir>]\n       \
+    jilua audit <dump> [--sink <pattern>]... [--json]";
+
+/// What is done with the dump
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// listing or DOT files of basic block graphs, one file per prototype
+    Dump { dot_dir: Option<PathBuf> },
+    /// report of dangerous calls, sinks are added to the default ones
+    Audit { sinks: Vec<String>, json: bool },
+}
+
+/// Command line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    dump: PathBuf,
+    command: Command,
+}
+
+// `None` when arguments don't match usage
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Option<Options> {
+    let mut args = args.peekable();
+    let audit = args.next_if(|arg| arg == "audit").is_some();
+
+    let mut dump = None;
+    let mut dot_dir = None;
+    let mut sinks = vec![];
+    let mut json = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" if !audit => dot_dir = Some(PathBuf::from(args.next()?)),
+            "--sink" if audit => sinks.push(args.next()?),
+            "--json" if audit => json = true,
+            _ if dump.is_none() && !arg.starts_with("--") => dump = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    let command = match audit {
+        true => Command::Audit { sinks, json },
+        false => Command::Dump { dot_dir },
+    };
+
+    Some(Options {
+        dump: dump?,
+        command,
+    })
+}
+
+fn read_dump(path: &Path) -> Result<ByteCodeDump, DecompileError> {
+    let data = fs::read(path)?;
+    read_bytecode_dump(&mut data.as_slice())
+}
+
+// listing is printed when no output is requested
+fn run(options: Options) -> Result<(), DecompileError> {
+    let dump = read_dump(&options.dump)?;
+
+    match options.command {
+        Command::Dump { dot_dir: Some(dir) } => dump.write_dot_files(&dir),
+        Command::Dump { dot_dir: None } => {
+            print!("{}", dump_listing(&dump));
+            Ok(())
+        }
+        Command::Audit { sinks, json } => {
+            let auditor = sinks
+                .iter()
+                .fold(Auditor::new(), |auditor, sink| auditor.sink(sink));
+            let report = auditor.audit(&dump)?;
+
+            match json {
+                true => println!("{}", report.to_json()),
+                false => print!("{}", report),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(options) = parse_args(env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("jilua: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_args, Command, Options};
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Option<Options> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_dot_flag() {
+        assert_eq!(
+            parse(&["main.ljbc", "--dot", "out"]),
+            Some(Options {
+                dump: PathBuf::from("main.ljbc"),
+                command: Command::Dump {
+                    dot_dir: Some(PathBuf::from("out")),
+                },
+            })
+        );
+        assert_eq!(
+            parse(&["main.ljbc"]).unwrap().command,
+            Command::Dump { dot_dir: None }
+        );
+
+        assert_eq!(parse(&[]), None);
+        assert_eq!(parse(&["main.ljbc", "--dot"]), None);
+        assert_eq!(parse(&["a.ljbc", "b.ljbc"]), None);
+    }
+
+    #[test]
+    fn parse_audit_command() {
+        assert_eq!(
+            parse(&["audit", "main.ljbc", "--sink", "lib.sys.*", "--json"]),
+            Some(Options {
+                dump: PathBuf::from("main.ljbc"),
+                command: Command::Audit {
+                    sinks: vec!["lib.sys.*".to_string()],
+                    json: true,
+                },
+            })
+        );
+        assert_eq!(
+            parse(&["audit", "main.ljbc"]).unwrap().command,
+            Command::Audit {
+                sinks: vec![],
+                json: false,
+            }
+        );
+
+        assert_eq!(parse(&["audit"]), None);
+        assert_eq!(parse(&["audit", "main.ljbc", "--sink"]), None);
+        assert_eq!(parse(&["audit", "main.ljbc", "--dot", "out"]), None);
+        assert_eq!(parse(&["main.ljbc", "--json"]), None);
+    }
+}
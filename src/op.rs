@@ -1,6 +1,6 @@
 use crate::types::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Op {
     // Comparison ops
     ISLT(Var, Var),
@@ -115,3 +115,147 @@ pub enum Op {
     FUNCC(RBase),
     FUNCCW(RBase),
 }
+
+/// Operand mode of an instruction field, same as `BCM*` modes of `lj_bc.h`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OpMode {
+    None,
+    Dst,
+    Base,
+    Var,
+    RBase,
+    UV,
+    Lit,
+    LitS,
+    Pri,
+    Num,
+    Str,
+    Tab,
+    Func,
+    Jump,
+    CData,
+}
+
+/// Mnemonic and operand modes of an opcode. B mode is `None` for instructions with D operand.
+#[derive(Debug, Clone, Copy)]
+pub struct OpInfo {
+    pub name: &'static str,
+    pub a: OpMode,
+    pub b: OpMode,
+    pub cd: OpMode,
+}
+
+impl OpInfo {
+    const fn new(name: &'static str, a: OpMode, b: OpMode, cd: OpMode) -> Self {
+        OpInfo { name, a, b, cd }
+    }
+}
+
+pub const OP_COUNT: usize = 97;
+
+/// Opcode info table indexed by opcode
+pub const OP_INFO: [OpInfo; OP_COUNT] = [
+    OpInfo::new("ISLT", OpMode::Var, OpMode::None, OpMode::Var),
+    OpInfo::new("ISGE", OpMode::Var, OpMode::None, OpMode::Var),
+    OpInfo::new("ISLE", OpMode::Var, OpMode::None, OpMode::Var),
+    OpInfo::new("ISGT", OpMode::Var, OpMode::None, OpMode::Var),
+    OpInfo::new("ISEQV", OpMode::Var, OpMode::None, OpMode::Var),
+    OpInfo::new("ISNEV", OpMode::Var, OpMode::None, OpMode::Var),
+    OpInfo::new("ISEQS", OpMode::Var, OpMode::None, OpMode::Str),
+    OpInfo::new("ISNES", OpMode::Var, OpMode::None, OpMode::Str),
+    OpInfo::new("ISEQN", OpMode::Var, OpMode::None, OpMode::Num),
+    OpInfo::new("ISNEN", OpMode::Var, OpMode::None, OpMode::Num),
+    OpInfo::new("ISEQP", OpMode::Var, OpMode::None, OpMode::Pri),
+    OpInfo::new("ISNEP", OpMode::Var, OpMode::None, OpMode::Pri),
+    OpInfo::new("ISTC", OpMode::Dst, OpMode::None, OpMode::Var),
+    OpInfo::new("ISFC", OpMode::Dst, OpMode::None, OpMode::Var),
+    OpInfo::new("IST", OpMode::None, OpMode::None, OpMode::Var),
+    OpInfo::new("ISF", OpMode::None, OpMode::None, OpMode::Var),
+    OpInfo::new("ISTYPE", OpMode::Var, OpMode::None, OpMode::Lit),
+    OpInfo::new("ISNUM", OpMode::Var, OpMode::None, OpMode::Lit),
+    OpInfo::new("MOV", OpMode::Dst, OpMode::None, OpMode::Var),
+    OpInfo::new("NOT", OpMode::Dst, OpMode::None, OpMode::Var),
+    OpInfo::new("UNM", OpMode::Dst, OpMode::None, OpMode::Var),
+    OpInfo::new("LEN", OpMode::Dst, OpMode::None, OpMode::Var),
+    OpInfo::new("ADDVN", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("SUBVN", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("MULVN", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("DIVVN", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("MODVN", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("ADDNV", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("SUBNV", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("MULNV", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("DIVNV", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("MODNV", OpMode::Dst, OpMode::Var, OpMode::Num),
+    OpInfo::new("ADDVV", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("SUBVV", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("MULVV", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("DIVVV", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("MODVV", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("POW", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("CAT", OpMode::Dst, OpMode::RBase, OpMode::RBase),
+    OpInfo::new("KSTR", OpMode::Dst, OpMode::None, OpMode::Str),
+    OpInfo::new("KCDATA", OpMode::Dst, OpMode::None, OpMode::CData),
+    OpInfo::new("KSHORT", OpMode::Dst, OpMode::None, OpMode::LitS),
+    OpInfo::new("KNUM", OpMode::Dst, OpMode::None, OpMode::Num),
+    OpInfo::new("KPRI", OpMode::Dst, OpMode::None, OpMode::Pri),
+    OpInfo::new("KNIL", OpMode::Base, OpMode::None, OpMode::Base),
+    OpInfo::new("UGET", OpMode::Dst, OpMode::None, OpMode::UV),
+    OpInfo::new("USETV", OpMode::UV, OpMode::None, OpMode::Var),
+    OpInfo::new("USETS", OpMode::UV, OpMode::None, OpMode::Str),
+    OpInfo::new("USETN", OpMode::UV, OpMode::None, OpMode::Num),
+    OpInfo::new("USETP", OpMode::UV, OpMode::None, OpMode::Pri),
+    OpInfo::new("UCLO", OpMode::RBase, OpMode::None, OpMode::Jump),
+    OpInfo::new("FNEW", OpMode::Dst, OpMode::None, OpMode::Func),
+    OpInfo::new("TNEW", OpMode::Dst, OpMode::None, OpMode::Lit),
+    OpInfo::new("TDUP", OpMode::Dst, OpMode::None, OpMode::Tab),
+    OpInfo::new("GGET", OpMode::Dst, OpMode::None, OpMode::Str),
+    OpInfo::new("GSET", OpMode::Var, OpMode::None, OpMode::Str),
+    OpInfo::new("TGETV", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("TGETS", OpMode::Dst, OpMode::Var, OpMode::Str),
+    OpInfo::new("TGETB", OpMode::Dst, OpMode::Var, OpMode::Lit),
+    OpInfo::new("TGETR", OpMode::Dst, OpMode::Var, OpMode::Var),
+    OpInfo::new("TSETV", OpMode::Var, OpMode::Var, OpMode::Var),
+    OpInfo::new("TSETS", OpMode::Var, OpMode::Var, OpMode::Str),
+    OpInfo::new("TSETB", OpMode::Var, OpMode::Var, OpMode::Lit),
+    OpInfo::new("TSETM", OpMode::Base, OpMode::None, OpMode::Num),
+    OpInfo::new("TSETR", OpMode::Var, OpMode::Var, OpMode::Var),
+    OpInfo::new("CALLM", OpMode::Base, OpMode::Lit, OpMode::Lit),
+    OpInfo::new("CALL", OpMode::Base, OpMode::Lit, OpMode::Lit),
+    OpInfo::new("CALLMT", OpMode::Base, OpMode::None, OpMode::Lit),
+    OpInfo::new("CALLT", OpMode::Base, OpMode::None, OpMode::Lit),
+    OpInfo::new("ITERC", OpMode::Base, OpMode::Lit, OpMode::Lit),
+    OpInfo::new("ITERN", OpMode::Base, OpMode::Lit, OpMode::Lit),
+    OpInfo::new("VARG", OpMode::Base, OpMode::Lit, OpMode::Lit),
+    OpInfo::new("ISNEXT", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("RETM", OpMode::Base, OpMode::None, OpMode::Lit),
+    OpInfo::new("RET", OpMode::RBase, OpMode::None, OpMode::Lit),
+    OpInfo::new("RET0", OpMode::RBase, OpMode::None, OpMode::Lit),
+    OpInfo::new("RET1", OpMode::RBase, OpMode::None, OpMode::Lit),
+    OpInfo::new("FORI", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("JFORI", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("FORL", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("IFORL", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("JFORL", OpMode::Base, OpMode::None, OpMode::Lit),
+    OpInfo::new("ITERL", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("IITERL", OpMode::Base, OpMode::None, OpMode::Jump),
+    OpInfo::new("JITERL", OpMode::Base, OpMode::None, OpMode::Lit),
+    OpInfo::new("LOOP", OpMode::RBase, OpMode::None, OpMode::Jump),
+    OpInfo::new("ILOOP", OpMode::RBase, OpMode::None, OpMode::Jump),
+    OpInfo::new("JLOOP", OpMode::RBase, OpMode::None, OpMode::Lit),
+    OpInfo::new("JMP", OpMode::RBase, OpMode::None, OpMode::Jump),
+    OpInfo::new("FUNCF", OpMode::RBase, OpMode::None, OpMode::None),
+    OpInfo::new("IFUNCF", OpMode::RBase, OpMode::None, OpMode::None),
+    OpInfo::new("JFUNCF", OpMode::RBase, OpMode::None, OpMode::Lit),
+    OpInfo::new("FUNCV", OpMode::RBase, OpMode::None, OpMode::None),
+    OpInfo::new("IFUNCV", OpMode::RBase, OpMode::None, OpMode::None),
+    OpInfo::new("JFUNCV", OpMode::RBase, OpMode::None, OpMode::Lit),
+    OpInfo::new("FUNCC", OpMode::RBase, OpMode::None, OpMode::None),
+    OpInfo::new("FUNCCW", OpMode::RBase, OpMode::None, OpMode::None),
+];
+
+/// Returns opcode info for raw instruction, `None` for unknown opcode
+#[inline(always)]
+pub fn op_info(ins_raw: u32) -> Option<&'static OpInfo> {
+    OP_INFO.get((ins_raw & 0xff) as usize)
+}
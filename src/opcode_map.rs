@@ -0,0 +1,219 @@
+use crate::disasm::disasm;
+use crate::op::{Op, OP_COUNT, OP_INFO};
+use crate::DecompileError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Map file contents, mnemonic to opcode number used by the custom build
+#[derive(Deserialize, Serialize)]
+struct OpcodeMapFile {
+    opcodes: BTreeMap<String, u8>,
+}
+
+/// Opcode numbering of a LuaJIT build. Raw opcodes are translated to the standard numbering
+/// when bytecode is read, so resolver and lifter always see standard instructions.
+///
+/// Map file lists every opcode of the build by mnemonic, TOML:
+/// ```toml
+/// [opcodes]
+/// ISLT = 0x05
+/// ISGE = 0x00
+/// ```
+/// or JSON: `{"opcodes": {"ISLT": 5, "ISGE": 0}}`. Opcodes missing from the file are unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeMap {
+    to_std: [Option<u8>; 256],
+    from_std: [Option<u8>; OP_COUNT],
+}
+
+impl Default for OpcodeMap {
+    /// Standard LuaJIT 2.1 numbering
+    fn default() -> Self {
+        let mut map = OpcodeMap::empty();
+        for op in 0..OP_COUNT as u8 {
+            map.to_std[op as usize] = Some(op);
+            map.from_std[op as usize] = Some(op);
+        }
+        map
+    }
+}
+
+impl OpcodeMap {
+    fn empty() -> Self {
+        OpcodeMap {
+            to_std: [None; 256],
+            from_std: [None; OP_COUNT],
+        }
+    }
+
+    /// Builds map from pairs of mnemonic and raw opcode
+    pub fn from_names<'a, I: IntoIterator<Item = (&'a str, u8)>>(
+        names: I,
+    ) -> Result<Self, DecompileError> {
+        let mut map = OpcodeMap::empty();
+
+        for (name, raw) in names {
+            let op = OP_INFO
+                .iter()
+                .position(|info| info.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| invalid(format!("unknown mnemonic {}", name)))?;
+
+            if map.to_std[raw as usize].is_some() {
+                return Err(invalid(format!("opcode 0x{:02x} is mapped twice", raw)));
+            }
+            if map.from_std[op].is_some() {
+                return Err(invalid(format!("{} is mapped twice", OP_INFO[op].name)));
+            }
+
+            map.to_std[raw as usize] = Some(op as u8);
+            map.from_std[op] = Some(raw);
+        }
+
+        Ok(map)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, DecompileError> {
+        let file: OpcodeMapFile = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        Self::from_file(file)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, DecompileError> {
+        let file: OpcodeMapFile = serde_json::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        Self::from_file(file)
+    }
+
+    /// Loads map file, `.json` files are parsed as JSON and everything else as TOML
+    pub fn load(path: &Path) -> Result<Self, DecompileError> {
+        let text = fs::read_to_string(path)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::from_json(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+
+    fn from_file(file: OpcodeMapFile) -> Result<Self, DecompileError> {
+        Self::from_names(file.opcodes.iter().map(|(name, &raw)| (name.as_str(), raw)))
+    }
+
+    fn to_file(&self) -> OpcodeMapFile {
+        let opcodes = (0..OP_COUNT)
+            .filter_map(|op| Some((OP_INFO[op].name.to_string(), self.from_std[op]?)))
+            .collect();
+        OpcodeMapFile { opcodes }
+    }
+
+    /// Map file in TOML format, opcodes are listed in the standard order
+    pub fn to_toml(&self) -> String {
+        let mut text = String::from("[opcodes]\n");
+        for (op, raw) in self.from_std.iter().enumerate() {
+            if let Some(raw) = raw {
+                text += &format!("{} = 0x{:02x}\n", OP_INFO[op].name, raw);
+            }
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_file()).unwrap()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == OpcodeMap::default()
+    }
+
+    /// Standard opcode of raw opcode of the build
+    pub fn to_standard(&self, raw: u8) -> Option<u8> {
+        self.to_std[raw as usize]
+    }
+
+    /// Raw opcode of the build for standard opcode
+    pub fn from_standard(&self, op: u8) -> Option<u8> {
+        self.from_std.get(op as usize).cloned().flatten()
+    }
+
+    /// Rewrites opcode byte of raw instruction to the standard numbering
+    pub fn decode(&self, ins_raw: u32) -> Result<u32, DecompileError> {
+        let op = self
+            .to_standard(ins_raw as u8)
+            .ok_or(DecompileError::UnknownInsOpcode)?;
+        Ok(ins_raw & !0xff | op as u32)
+    }
+
+    /// Rewrites opcode byte of standard instruction to the numbering of the build
+    pub fn encode(&self, ins_raw: u32) -> Result<u32, DecompileError> {
+        let raw = self
+            .from_standard(ins_raw as u8)
+            .ok_or(DecompileError::UnknownInsOpcode)?;
+        Ok(ins_raw & !0xff | raw as u32)
+    }
+
+    /// Disassembles raw instruction of the build
+    pub fn disasm(&self, ins_raw: u32) -> Result<Op, DecompileError> {
+        disasm(self.decode(ins_raw)?)
+    }
+}
+
+fn invalid(msg: String) -> DecompileError {
+    DecompileError::InvalidOpcodeMap(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::asm;
+    use crate::op::{Op, OP_INFO};
+    use crate::opcode_map::OpcodeMap;
+    use crate::types::{Dst, LitS, Var};
+
+    // ISLT and ISGE swapped, everything else keeps its number
+    fn swapped() -> String {
+        let mut text = String::from("[opcodes]\n");
+        for (op, info) in OP_INFO.iter().enumerate() {
+            let raw = match op {
+                0 => 1,
+                1 => 0,
+                op => op,
+            };
+            text += &format!("{} = 0x{:02x}\n", info.name, raw);
+        }
+        text
+    }
+
+    #[test]
+    fn load_toml_and_json() {
+        let map = OpcodeMap::from_toml(&swapped()).unwrap();
+        assert!(!map.is_identity());
+        assert_eq!(map.to_standard(0x00), Some(0x01));
+        assert_eq!(map.from_standard(0x01), Some(0x00));
+        assert_eq!(map.to_standard(0x61), None);
+
+        assert_eq!(OpcodeMap::from_toml(&map.to_toml()).unwrap(), map);
+        assert_eq!(OpcodeMap::from_json(&map.to_json()).unwrap(), map);
+
+        let json = OpcodeMap::from_json(r#"{"opcodes": {"ISLT": 1, "ISGE": 0}}"#).unwrap();
+        assert_eq!(json.to_standard(0x01), Some(0x00));
+        assert_eq!(json.to_standard(0x02), None);
+
+        assert!(OpcodeMap::from_json(r#"{"opcodes": {"ISLT": 1, "ISGE": 1}}"#).is_err());
+        assert!(OpcodeMap::from_toml("[opcodes]\nFOO = 1\n").is_err());
+        assert!(OpcodeMap::from_toml("[opcodes]\nISLT = 256\n").is_err());
+    }
+
+    #[test]
+    fn decode_shuffled() {
+        let map = OpcodeMap::from_toml(&swapped()).unwrap();
+        let standard = asm(&Op::ISLT(Var(1), Var(2)));
+        let raw = map.encode(standard).unwrap();
+
+        assert_eq!(raw & 0xff, 0x01);
+        assert_eq!(map.decode(raw).unwrap(), standard);
+        assert_eq!(asm(&map.disasm(raw).unwrap()), standard);
+
+        let identity = OpcodeMap::default();
+        let standard = asm(&Op::KSHORT(Dst(0), LitS(7)));
+        assert_eq!(identity.decode(standard).unwrap(), standard);
+        assert!(identity.decode(0xff).is_err());
+    }
+}
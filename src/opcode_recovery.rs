@@ -0,0 +1,498 @@
+use crate::bytecode_reader::GlobalConst;
+use crate::op::{OpMode, OP_COUNT, OP_INFO};
+use crate::opcode_map::OpcodeMap;
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+use std::fmt;
+
+// refinement rounds with successors decoded by the previous guess
+const MAX_ROUNDS: usize = 8;
+
+// inconsistent instruction outweighs several consistent ones
+const INVALID_PENALTY: f64 = 8.0;
+
+// JIT variants are turned back into their originals by `lj_bcwrite` and function headers
+// aren't dumped at all
+const NOT_DUMPED: &[&str] = &[
+    "JFORI", "IFORL", "JFORL", "IITERL", "JITERL", "ILOOP", "JLOOP", "FUNCF", "IFUNCF", "JFUNCF",
+    "FUNCV", "IFUNCV", "JFUNCV", "FUNCC", "FUNCCW",
+];
+
+// opcodes which can end the prototype
+const RETURNS: &[&str] = &["CALLMT", "CALLT", "RETM", "RET", "RET0", "RET1"];
+
+// opcodes with equal operand modes can't be told apart by constraints, more frequent
+// opcodes of typical code are preferred for more frequent raw opcodes
+const COMMON: &[&str] = &[
+    "MOV", "GGET", "TGETS", "CALL", "KSTR", "JMP", "RET0", "KPRI", "TSETS", "FNEW", "RET1",
+    "KSHORT", "UGET", "ISF", "IST", "TGETV", "TNEW", "CALLT", "KNIL", "ISEQS", "ISNES", "ADDVN",
+    "ISEQP", "ISNEP", "TDUP", "ISTC", "ISFC", "RET", "GSET", "ISEQV", "ISNEV", "CAT", "TSETV",
+    "ITERC", "ITERL", "ISNEXT", "FORI", "FORL", "LOOP", "UCLO", "VARG", "CALLM", "RETM", "KNUM",
+    "ISLT", "ISGE", "ISLE", "ISGT", "NOT", "LEN",
+];
+
+fn opcode(name: &str) -> u8 {
+    OP_INFO.iter().position(|info| info.name == name).unwrap() as u8
+}
+
+fn is_named(op: u8, names: &[&str]) -> bool {
+    names.contains(&OP_INFO[op as usize].name)
+}
+
+// global constants are indexed from the end
+fn global_const(proto: &ByteCodeProto, idx: u32) -> Option<&GlobalConst> {
+    let consts = proto.global_consts();
+    consts.get(consts.len().checked_sub(idx as usize + 1)?)
+}
+
+fn fits_mode(proto: &ByteCodeProto, pc: usize, mode: OpMode, val: u32) -> bool {
+    let frame_size = proto.frame_size() as u32;
+
+    match mode {
+        OpMode::None => val == 0,
+        OpMode::Dst | OpMode::Var => val < frame_size,
+        OpMode::Base | OpMode::RBase => val <= frame_size,
+        OpMode::UV => (val as usize) < proto.up_values().len(),
+        OpMode::Lit | OpMode::LitS => true,
+        OpMode::Pri => val <= 2,
+        OpMode::Num => (val as usize) < proto.num_consts().len(),
+        OpMode::Str => matches!(global_const(proto, val), Some(GlobalConst::Str(_))),
+        OpMode::Tab => matches!(global_const(proto, val), Some(GlobalConst::Table(_))),
+        OpMode::Func => matches!(global_const(proto, val), Some(GlobalConst::ProtoChild(_))),
+        OpMode::CData => matches!(
+            global_const(proto, val),
+            Some(GlobalConst::I64(..) | GlobalConst::U64(..) | GlobalConst::Complex(..))
+        ),
+        OpMode::Jump => {
+            let target = pc as i64 + 1 + val as i64 - 0x8000;
+            target >= 0 && (target as usize) < proto.bc_raw().len()
+        }
+    }
+}
+
+/// Previous guess of the map with jump targets of one prototype decoded by it
+struct Context<'a> {
+    map: &'a OpcodeMap,
+    targets: Vec<bool>,
+}
+
+impl<'a> Context<'a> {
+    fn new(proto: &ByteCodeProto, map: &'a OpcodeMap) -> Self {
+        let bc = proto.bc_raw();
+        let mut targets = vec![false; bc.len()];
+
+        for (pc, &ins) in bc.iter().enumerate() {
+            match map.to_standard(ins as u8) {
+                Some(op) if OP_INFO[op as usize].cd == OpMode::Jump => {
+                    let target = pc as i64 + 1 + (ins >> 16) as i64 - 0x8000;
+                    if let Some(target) = targets.get_mut(target as usize) {
+                        *target = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Context { map, targets }
+    }
+}
+
+/// Checks whether instruction at `pc` can be `op`. Successor is checked against `context`
+/// guess when it is given.
+fn fits(proto: &ByteCodeProto, pc: usize, op: u8, context: Option<&Context>) -> bool {
+    let bc = proto.bc_raw();
+    let ins = bc[pc];
+    let info = &OP_INFO[op as usize];
+
+    let operands_fit = fits_mode(proto, pc, info.a, (ins >> 8) & 0xff)
+        && match info.b {
+            OpMode::None => fits_mode(proto, pc, info.cd, ins >> 16),
+            b => {
+                fits_mode(proto, pc, b, ins >> 24)
+                    && fits_mode(proto, pc, info.cd, (ins >> 16) & 0xff)
+            }
+        };
+
+    if !operands_fit {
+        return false;
+    }
+
+    let next = match bc.get(pc + 1) {
+        Some(&next) => next,
+        None => return is_named(op, RETURNS),
+    };
+
+    let context = match context {
+        Some(context) => context,
+        None => return true,
+    };
+
+    // code after return is reached by jump only, `return` ends the block in Lua
+    if is_named(op, RETURNS) && !context.targets[pc + 1] {
+        return false;
+    }
+
+    let next = match context.map.to_standard(next as u8) {
+        Some(next) => next,
+        None => return true,
+    };
+
+    // comparisons and tests are followed by the jump of their branch
+    if op <= opcode("ISF") {
+        return next == opcode("JMP");
+    }
+
+    match info.name {
+        "ITERC" | "ITERN" => next == opcode("ITERL"),
+        _ => true,
+    }
+}
+
+/// Counts instructions of the dump which are consistent with the map. Unmapped raw opcodes
+/// are counted as inconsistent.
+pub fn score_map(dump: &ByteCodeDump, map: &OpcodeMap) -> usize {
+    dump.prototypes()
+        .iter()
+        .map(|proto| {
+            let context = Context::new(proto, map);
+            (0..proto.bc_raw().len())
+                .filter(|&pc| match map.to_standard(proto.bc_raw()[pc] as u8) {
+                    Some(op) => fits(proto, pc, op, Some(&context)),
+                    None => false,
+                })
+                .count()
+        })
+        .sum()
+}
+
+/// Recovered opcode of one raw opcode
+#[derive(Debug, Clone)]
+pub struct OpcodeGuess {
+    pub raw: u8,
+    /// standard opcode
+    pub op: u8,
+    /// instructions with the raw opcode
+    pub count: usize,
+    /// instructions consistent with the guess
+    pub valid: usize,
+    /// standard opcodes consistent with all instructions, 1 means the guess is certain
+    pub candidates: usize,
+}
+
+/// Result of opcode permutation recovery
+#[derive(Debug, Clone)]
+pub struct RecoveryReport {
+    /// map of raw opcodes found in the dump
+    pub map: OpcodeMap,
+    pub guesses: Vec<OpcodeGuess>,
+    pub valid: usize,
+    pub total: usize,
+}
+
+impl RecoveryReport {
+    /// Share of instructions consistent with the recovered map
+    pub fn confidence(&self) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => self.valid as f64 / total as f64,
+        }
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let certain = self
+            .guesses
+            .iter()
+            .filter(|guess| guess.candidates == 1)
+            .count();
+
+        writeln!(
+            f,
+            "-- {} of {} instructions consistent ({:.1}%), {} of {} opcodes certain",
+            self.valid,
+            self.total,
+            self.confidence() * 100.0,
+            certain,
+            self.guesses.len()
+        )?;
+
+        for guess in &self.guesses {
+            writeln!(
+                f,
+                "0x{:02x} {:<6} count {:<6} valid {:<6} candidates {}",
+                guess.raw,
+                OP_INFO[guess.op as usize].name,
+                guess.count,
+                guess.valid,
+                guess.candidates
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// assignment of rows to distinct columns maximizing total weight, rows mustn't outnumber columns
+fn assign(weights: &[Vec<f64>], columns: usize) -> Vec<usize> {
+    let rows = weights.len();
+    let mut u = vec![0.0; rows + 1];
+    let mut v = vec![0.0; columns + 1];
+    // row assigned to the column, 1-based with 0 for free column
+    let mut owner = vec![0usize; columns + 1];
+    let mut way = vec![0usize; columns + 1];
+
+    for row in 1..=rows {
+        owner[0] = row;
+        let mut col0 = 0;
+        let mut min_v = vec![f64::INFINITY; columns + 1];
+        let mut used = vec![false; columns + 1];
+
+        loop {
+            used[col0] = true;
+            let row0 = owner[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+
+            for col in 1..=columns {
+                if used[col] {
+                    continue;
+                }
+
+                let cur = -weights[row0 - 1][col - 1] - u[row0] - v[col];
+                if cur < min_v[col] {
+                    min_v[col] = cur;
+                    way[col] = col0;
+                }
+                if min_v[col] < delta {
+                    delta = min_v[col];
+                    col1 = col;
+                }
+            }
+
+            for col in 0..=columns {
+                if used[col] {
+                    u[owner[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min_v[col] -= delta;
+                }
+            }
+
+            col0 = col1;
+            if owner[col0] == 0 {
+                break;
+            }
+        }
+
+        while col0 != 0 {
+            let col1 = way[col0];
+            owner[col0] = owner[col1];
+            col0 = col1;
+        }
+    }
+
+    let mut assigned = vec![0; rows];
+    for (col, &row) in owner.iter().enumerate().skip(1) {
+        if row != 0 {
+            assigned[row - 1] = col - 1;
+        }
+    }
+    assigned
+}
+
+/// Infers opcode numbering of a build with shuffled opcodes from its dump read by
+/// `read_raw_bytecode_dump`. Every raw opcode is weighed against every standard opcode by
+/// operand constraints: slots fit the frame, constants have the right type, jumps land in
+/// the prototype, the last instruction returns and comparisons are followed by `JMP`.
+/// Opcodes with equal constraints are told apart by typical frequency only, see
+/// `OpcodeGuess::candidates`.
+pub fn recover_opcodes(dump: &ByteCodeDump) -> Result<RecoveryReport, DecompileError> {
+    let mut counts = [0usize; 256];
+    for proto in dump.prototypes() {
+        for &ins in proto.bc_raw() {
+            counts[ins as u8 as usize] += 1;
+        }
+    }
+
+    let raws: Vec<u8> = (0..=255u8)
+        .filter(|&raw| counts[raw as usize] > 0)
+        .collect();
+    let ops: Vec<u8> = (0..OP_COUNT as u8)
+        .filter(|&op| !is_named(op, NOT_DUMPED))
+        .collect();
+
+    if raws.len() > ops.len() {
+        return Err(DecompileError::InvalidOpcodeMap(format!(
+            "dump uses {} distinct opcodes",
+            raws.len()
+        )));
+    }
+
+    let total: usize = counts.iter().sum();
+    let prior = |op: u8| match COMMON
+        .iter()
+        .position(|&name| name == OP_INFO[op as usize].name)
+    {
+        Some(rank) => (COMMON.len() - rank) as f64 / COMMON.len() as f64,
+        None => 0.0,
+    };
+
+    // instructions of raw opcode consistent with standard opcode
+    let valid_counts = |map: Option<&OpcodeMap>| -> Vec<Vec<usize>> {
+        let mut valid = vec![vec![0usize; ops.len()]; raws.len()];
+
+        for proto in dump.prototypes() {
+            let context = map.map(|map| Context::new(proto, map));
+
+            for (pc, &ins) in proto.bc_raw().iter().enumerate() {
+                let row = raws.binary_search(&(ins as u8)).unwrap();
+                for (col, &op) in ops.iter().enumerate() {
+                    if fits(proto, pc, op, context.as_ref()) {
+                        valid[row][col] += 1;
+                    }
+                }
+            }
+        }
+
+        valid
+    };
+
+    let mut best: Option<(usize, OpcodeMap)> = None;
+    let mut context: Option<OpcodeMap> = None;
+
+    for _ in 0..MAX_ROUNDS {
+        let valid = valid_counts(context.as_ref());
+
+        // frequency prior only breaks ties, all of it is worth less than one instruction
+        let weights: Vec<Vec<f64>> = raws
+            .iter()
+            .zip(&valid)
+            .map(|(&raw, valid)| {
+                let count = counts[raw as usize];
+                ops.iter()
+                    .zip(valid)
+                    .map(|(&op, &valid)| {
+                        let invalid = count - valid;
+                        valid as f64 - INVALID_PENALTY * invalid as f64
+                            + 0.5 * prior(op) * count as f64 / (total + 1) as f64
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let assigned = assign(&weights, ops.len());
+        let map = OpcodeMap::from_names(
+            raws.iter()
+                .zip(&assigned)
+                .map(|(&raw, &col)| (OP_INFO[ops[col] as usize].name, raw)),
+        )?;
+
+        let score = score_map(dump, &map);
+        if best
+            .as_ref()
+            .is_none_or(|(best_score, _)| score > *best_score)
+        {
+            best = Some((score, map.clone()));
+        }
+
+        if context.as_ref() == Some(&map) {
+            break;
+        }
+        context = Some(map);
+    }
+
+    let (valid, map) = best.unwrap_or_else(|| (0, OpcodeMap::from_names([]).unwrap()));
+    let valid_per_op = valid_counts(Some(&map));
+
+    let guesses = raws
+        .iter()
+        .zip(&valid_per_op)
+        .map(|(&raw, valid)| {
+            let op = map.to_standard(raw).unwrap();
+            let count = counts[raw as usize];
+
+            OpcodeGuess {
+                raw,
+                op,
+                count,
+                valid: valid[ops.iter().position(|&col| col == op).unwrap()],
+                candidates: valid.iter().filter(|&&valid| valid == count).count(),
+            }
+        })
+        .collect();
+
+    Ok(RecoveryReport {
+        map,
+        guesses,
+        valid,
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_listing;
+    use crate::bytecode_reader::{
+        read_bytecode_dump, read_bytecode_dump_with_opcodes, read_raw_bytecode_dump,
+        write_bytecode_dump_with_opcodes,
+    };
+    use crate::op::OP_INFO;
+    use crate::opcode_map::OpcodeMap;
+    use crate::opcode_recovery::{recover_opcodes, score_map};
+
+    const LISTING: &str = "-- BYTECODE -- ?:0-0\n\
+        0001    KSTR     0   0      ; \"license\\tok\"\n\
+        0002    KSHORT   1  -1\n\
+        0003    ISLT     0   1\n\
+        0004    JMP      2 => 0006\n\
+        0005    KNUM     1   0      ; 3.5\n\
+        0006 => GGET     2   1      ; \"print\"\n\
+        0007    CALL     2   1   2\n\
+        0008    RET0     0   1\n\
+        \n";
+
+    // opcodes numbered backwards
+    fn reversed() -> OpcodeMap {
+        OpcodeMap::from_names(
+            OP_INFO
+                .iter()
+                .enumerate()
+                .map(|(op, info)| (info.name, 0x60 - op as u8)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn recover_reversed_opcodes() {
+        let data = assemble_listing(LISTING, None).unwrap();
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+
+        let mut shuffled = vec![];
+        write_bytecode_dump_with_opcodes(&mut shuffled, &dump, &reversed()).unwrap();
+        let raw_dump = read_raw_bytecode_dump(&mut shuffled.as_slice()).unwrap();
+
+        assert_eq!(score_map(&raw_dump, &reversed()), 8);
+        assert!(score_map(&raw_dump, &OpcodeMap::default()) < 8);
+
+        let report = recover_opcodes(&raw_dump).unwrap();
+        assert_eq!(report.valid, report.total);
+        assert_eq!(report.guesses.len(), 8);
+
+        for name in ["JMP", "CALL", "RET0", "KSHORT"] {
+            let op = OP_INFO.iter().position(|info| info.name == name).unwrap() as u8;
+            assert_eq!(report.map.to_standard(0x60 - op), Some(op), "{}", name);
+        }
+
+        // constraints don't distinguish `ISLT` from other comparisons
+        let islt = report
+            .guesses
+            .iter()
+            .find(|guess| guess.raw == 0x60)
+            .unwrap();
+        assert!(islt.candidates > 1);
+
+        // recovered map decodes the dump
+        let decoded = read_bytecode_dump_with_opcodes(&mut shuffled.as_slice(), &report.map);
+        assert!(decoded.is_ok());
+    }
+}
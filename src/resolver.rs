@@ -1,7 +1,10 @@
 use crate::disasm::disasm;
+use crate::instruction::Instruction;
 use crate::op::Op;
 use crate::{DecompileError, graph};
-use crate::graph::Graph;
+use crate::graph::{Graph, GraphFormatter};
+use std::collections::BTreeSet;
+use std::ops::Range;
 
 #[derive(Debug, Clone)]
 pub struct Block {
@@ -37,10 +40,18 @@ impl Block {
     }
 }
 
+/// Kind of the edge between basic blocks. Conditional edges carry polarity of the block
+/// condition, it is lifted in positive form (`ISGE a b` becomes `a < b` with jump on `False`).
 #[derive(Debug, Clone, Eq, PartialEq, Copy)]
 pub enum BranchKind {
+    /// taken when block condition is true
     True,
+    /// taken when block condition is false
     False,
+    /// `True` edge of `ISTC`, tested value is copied into destination slot
+    TrueCopy,
+    /// `False` edge of `ISFC`, tested value is copied into destination slot
+    FalseCopy,
     Unconditional,
     Loop,
     LoopOut,
@@ -48,273 +59,480 @@ pub enum BranchKind {
     LoopIter,
 }
 
-fn recurse_block(
-    graph: &mut Graph<Block, BranchKind>,
-    bc_raw: &[u32],
-    idx: u32,
-) -> Result<(), DecompileError> {
-    // checks if passed index is contained in existing node
-    let block_exists = if let Some(prev_idx) = graph.try_prev_node(idx) {
-        // block already exists (block already analyzed)
-        if idx == prev_idx {
-            return Ok(());
+// jump destination which has to be inside of prototype bytecode
+#[inline(always)]
+fn jump_target(instructions: &[Instruction], ins: &Instruction) -> Result<u32, DecompileError> {
+    match ins.jump_target() {
+        Some(dest) if (dest as usize) < instructions.len() => Ok(dest),
+        _ => Err(DecompileError::InvalidJumpTarget),
+    }
+}
+
+impl BranchKind {
+    /// Returns value of the block condition when edge is taken, `None` for non conditional edges
+    pub fn polarity(&self) -> Option<bool> {
+        match self {
+            BranchKind::True | BranchKind::TrueCopy => Some(true),
+            BranchKind::False | BranchKind::FalseCopy => Some(false),
+            _ => None,
         }
+    }
+
+    /// Checks if tested value is copied when edge is taken (`ISTC`/`ISFC` jump)
+    pub fn is_copy(&self) -> bool {
+        matches!(self, BranchKind::TrueCopy | BranchKind::FalseCopy)
+    }
 
-        let dist = (idx - prev_idx) as usize;
-        let block = graph.node_weight(prev_idx).unwrap();
-
-        // checks if current block contains passed index
-        if block.len() > dist {
-            graph.split_node(prev_idx, idx, |block| block.split(dist));
-            // add unconditional branch
-            graph.add_edge(BranchKind::Unconditional, prev_idx, idx);
-            true
-        } else {
-            // we need to create new block
-            false
+    /// Edge colour in exported graphs
+    pub fn color(&self) -> &'static str {
+        match self {
+            BranchKind::True | BranchKind::TrueCopy => "green",
+            BranchKind::False | BranchKind::FalseCopy => "red",
+            BranchKind::Unconditional => "black",
+            BranchKind::Loop | BranchKind::LoopIter => "blue",
+            BranchKind::LoopBody => "purple",
+            BranchKind::LoopOut => "orange",
         }
-    } else {
-        // we also need to create new block
-        false
-    };
+    }
+}
 
-    if !block_exists {
-        let block_start_idx = idx;
-        let next_block = graph.try_next_node(block_start_idx);
-        let mut prev_cond_idx = None;
+/// Formats basic blocks as disassembly listing, node index is the first instruction pc
+pub struct BasicBlockFormatter;
+
+impl GraphFormatter<Block, BranchKind> for BasicBlockFormatter {
+    fn node_label(&self, index: u32, node: &Block) -> String {
+        let lines: Vec<String> = node
+            .data()
+            .iter()
+            .enumerate()
+            .map(|(offset, &ins_raw)| match disasm(ins_raw) {
+                Ok(op) => format!("{:04} {:?}", index as usize + offset, op),
+                Err(_) => format!("{:04} ??? 0x{:08x}", index as usize + offset, ins_raw),
+            })
+            .collect();
+
+        lines.join("\n")
+    }
 
-        let block_size = bc_raw.len();
+    fn edge_label(&self, edge: &BranchKind) -> String {
+        format!("{:?}", edge)
+    }
 
-        for (idx, &ins_raw) in bc_raw.iter().enumerate().skip(block_start_idx as usize) {
-            // add block it has no jumps in the end
-            if Some(idx as u32) == next_block {
-                graph.add_node(
-                    block_start_idx,
-                    Block::from_ins_vec(bc_raw[block_start_idx as usize..idx as usize].to_vec()),
-                );
-                graph.add_edge(BranchKind::Unconditional, block_start_idx, idx as u32);
+    fn edge_color(&self, edge: &BranchKind) -> Option<&'static str> {
+        Some(edge.color())
+    }
+}
 
-                return Ok(());
-            }
+// conditional instructions are always followed by JMP, returns edge kinds for jump target
+// and fall through. Odd opcodes (ISGE, ISGT, ISNEV, ISF, ...) are negated even ones.
+fn condition_edges(ins: &Instruction) -> Option<(BranchKind, BranchKind)> {
+    match ins.op {
+        Op::ISLT(_, _)
+        | Op::ISLE(_, _)
+        | Op::ISEQV(_, _)
+        | Op::ISEQS(_, _)
+        | Op::ISEQN(_, _)
+        | Op::ISEQP(_, _)
+        | Op::IST(_) => Some((BranchKind::True, BranchKind::False)),
+        Op::ISGE(_, _)
+        | Op::ISGT(_, _)
+        | Op::ISNEV(_, _)
+        | Op::ISNES(_, _)
+        | Op::ISNEN(_, _)
+        | Op::ISNEP(_, _)
+        | Op::ISF(_) => Some((BranchKind::False, BranchKind::True)),
+        Op::ISTC(_, _) => Some((BranchKind::TrueCopy, BranchKind::False)),
+        Op::ISFC(_, _) => Some((BranchKind::FalseCopy, BranchKind::True)),
+        _ => None,
+    }
+}
 
-            match disasm(ins_raw)? {
-                // save conditional instruction index for determine unconditional jumps
-                Op::ISLT(_, _)
-                | Op::ISGE(_, _)
-                | Op::ISLE(_, _)
-                | Op::ISGT(_, _)
-                | Op::ISEQV(_, _)
-                | Op::ISTC(_, _)
-                | Op::ISNEV(_, _)
-                | Op::ISEQS(_, _)
-                | Op::ISNES(_, _)
-                | Op::ISEQN(_, _)
-                | Op::ISNEN(_, _)
-                | Op::ISEQP(_, _)
-                | Op::ISNEP(_, _)
-                | Op::IST(_)
-                | Op::ISF(_)
-                | Op::ISFC(_, _) => prev_cond_idx = Some(idx),
-                // pairs() or next() iterator "for" loop
-                // it is also unconditional branch
-                Op::ISNEXT(_, jump) => {
-                    // add block including current instruction
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(
-                            bc_raw[block_start_idx as usize..=idx as usize].to_vec(),
-                        ),
-                    );
-
-                    // calculate dest jump address
-                    let dest_block_idx = ((idx + 1) as i32 + jump.0 as i32) as u32;
-                    recurse_block(graph, bc_raw, dest_block_idx)?;
-
-                    // add edge for loop (unconditional)
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopIter, current_block_start_idx, dest_block_idx);
-
-                    return Ok(());
-                }
-                // branch to loop body in iterator "for" loop
-                // it is conditional branch
-                Op::ITERL(_, jump)
-                | Op::IITERL(_, jump) => {
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(
-                            bc_raw[block_start_idx as usize..=idx as usize].to_vec(),
-                        ),
-                    );
-
-                    // calculate dest jump address
-                    let dest_block_idx = ((idx + 1) as i32 + jump.0 as i32) as u32;
-                    recurse_block(graph, bc_raw, dest_block_idx)?;
-
-                    // add edge to loop body (like True condition)
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopBody, current_block_start_idx, dest_block_idx);
-
-                    // analyze loop out block
-                    let next_block_idx = (idx + 1) as u32;
-                    recurse_block(graph, bc_raw, next_block_idx)?;
-
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopOut, current_block_start_idx, next_block_idx);
-
-                    return Ok(());
-                }
-                // don't know what to do with this instructions, please create an issue if occurs
-                Op::JITERL(_, _) => { unimplemented!("JITERL instruction") }
-                Op::JFORL(_, _) => { unimplemented!("JFORL instruction") }
-                // numeric "for" loop initialization
-                // it is conditional branch
-                Op::FORI(_, jump)
-                | Op::JFORI(_, jump) => {
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(
-                            bc_raw[block_start_idx as usize..=idx as usize].to_vec(),
-                        ),
-                    );
-
-                    // calculate dest jump address
-                    let dest_block_idx = ((idx + 1) as i32 + jump.0 as i32) as u32;
-                    recurse_block(graph, bc_raw, dest_block_idx)?;
-
-                    // add edge to loop out (like False condition)
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopOut, current_block_start_idx, dest_block_idx);
-
-                    // analyze loop body block
-                    let next_block_idx = (idx + 1) as u32;
-                    recurse_block(graph, bc_raw, next_block_idx)?;
-
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopBody, current_block_start_idx, next_block_idx);
-
-                    return Ok(());
-                }
-                // branch to loop body in numeric "for" loop
-                // it is conditional
-                Op::FORL(_, jump)
-                | Op::IFORL(_, jump) => {
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(
-                            bc_raw[block_start_idx as usize..=idx as usize].to_vec(),
-                        ),
-                    );
-
-                    // calculate dest jump address
-                    let dest_block_idx = ((idx + 1) as i32 + jump.0 as i32) as u32;
-                    recurse_block(graph, bc_raw, dest_block_idx)?;
-
-                    // add edge to loop body (like True condition)
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopBody, current_block_start_idx, dest_block_idx);
-
-                    // analyze loop out block
-                    let next_block_idx = (idx + 1) as u32;
-                    recurse_block(graph, bc_raw, next_block_idx)?;
-
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::LoopOut, current_block_start_idx, next_block_idx);
-
-                    return Ok(());
+/// Returns outgoing edges in insertion order if instruction with passed index ends basic block
+fn block_exits(
+    instructions: &[Instruction],
+    idx: usize,
+) -> Result<Option<Vec<(BranchKind, u32)>>, DecompileError> {
+    let ins = &instructions[idx];
+    let next_idx = ins.pc + 1;
+
+    if !ins.is_terminator() {
+        return Ok(None);
+    }
+
+    Ok(match ins.op {
+        // pairs() or next() iterator "for" loop
+        // it is also unconditional branch
+        Op::ISNEXT(..) => Some(vec![(BranchKind::LoopIter, jump_target(instructions, ins)?)]),
+        // branch to loop body in iterator "for" loop
+        // it is conditional branch
+        Op::ITERL(..) | Op::IITERL(..) => Some(vec![
+            (BranchKind::LoopBody, jump_target(instructions, ins)?),
+            (BranchKind::LoopOut, next_idx),
+        ]),
+        // don't know what to do with this instructions, please create an issue if occurs
+        Op::JITERL(_, _) => unimplemented!("JITERL instruction"),
+        Op::JFORL(_, _) => unimplemented!("JFORL instruction"),
+        // numeric "for" loop initialization
+        // it is conditional branch
+        Op::FORI(..) | Op::JFORI(..) => Some(vec![
+            (BranchKind::LoopOut, jump_target(instructions, ins)?),
+            (BranchKind::LoopBody, next_idx),
+        ]),
+        // branch to loop body in numeric "for" loop
+        // it is conditional
+        Op::FORL(..) | Op::IFORL(..) => Some(vec![
+            (BranchKind::LoopBody, jump_target(instructions, ins)?),
+            (BranchKind::LoopOut, next_idx),
+        ]),
+        // if jump.0 == 0 => non branching
+        Op::UCLO(..) => Some(vec![(BranchKind::Unconditional, jump_target(instructions, ins)?)]),
+        Op::JMP(..) => {
+            let dest_idx = jump_target(instructions, ins)?;
+
+            // conditional JMP is also could be "while" or "until" loop part, but we can't
+            // determine this actually
+            let cond_edges = match idx {
+                0 => None,
+                _ => condition_edges(&instructions[idx - 1]),
+            };
+
+            match cond_edges {
+                Some((jump_kind, next_kind)) => {
+                    Some(vec![(jump_kind, dest_idx), (next_kind, next_idx)])
                 }
-                Op::UCLO(_, jump) => {
-                    // if jump.0 == 0 => non branching
+                None => Some(vec![(BranchKind::Unconditional, dest_idx)]),
+            }
+        }
+        Op::RET(_, _) | Op::RET0(_, _) | Op::RET1(_, _) | Op::RETM(_, _) => {
+            // analyze jump after RET1 case always next JMP in RET0
+            if let Some(Op::JMP(..)) = instructions.get(idx + 1).map(|next| next.op) {
+                return Ok(None);
+            }
+
+            Some(vec![])
+        }
+        // tail call leaves the function like return does
+        Op::CALLT(..) | Op::CALLMT(..) => Some(vec![]),
+        _ => None,
+    })
+}
 
-                    // add node end with current instruction
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(
-                            bc_raw[block_start_idx as usize..=idx as usize].to_vec(),
-                        ),
-                    );
+/// Collects indexes of the first instructions of all reachable basic blocks
+fn collect_leaders(instructions: &[Instruction]) -> Result<BTreeSet<u32>, DecompileError> {
+    let mut leaders = BTreeSet::new();
+    let mut scanned = vec![false; instructions.len()];
+    let mut worklist = vec![0u32];
 
-                    let dest_block_idx = ((idx + 1) as i32 + jump.0 as i32) as u32;
-                    recurse_block(graph, bc_raw, dest_block_idx)?;
+    while let Some(start) = worklist.pop() {
+        if !leaders.insert(start) || scanned[start as usize] {
+            // block already analyzed or passed index splits analyzed block
+            continue;
+        }
 
-                    let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                    graph.add_edge(BranchKind::Unconditional, current_block_start_idx, dest_block_idx);
+        for (idx, scanned) in scanned.iter_mut().enumerate().skip(start as usize) {
+            // fall through into already analyzed block, it starts with leader
+            if *scanned {
+                break;
+            }
+            *scanned = true;
 
-                    return Ok(());
-                }
-                Op::JMP(_, jump) => {
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(
-                            bc_raw[block_start_idx as usize..=idx as usize].to_vec(),
-                        ),
-                    );
-
-                    let dest_block_idx = ((idx + 1) as i32 + jump.0 as i32) as u32;
-                    recurse_block(graph, bc_raw, dest_block_idx)?;
-
-                    // conditional JMP is also could be "while" or "until" loop part, but we can't
-                    // determine this actually
-                    if prev_cond_idx.map(|v| v + 1) == Some(idx) {
-                        // block start idx can be changed in recurse_block() function
-                        let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                        graph.add_edge(BranchKind::True, current_block_start_idx, dest_block_idx);
-
-                        let next_block_idx = (idx + 1) as u32;
-                        recurse_block(graph, bc_raw, next_block_idx)?;
-
-                        let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                        graph.add_edge(BranchKind::False, current_block_start_idx, next_block_idx);
-                    } else {
-                        let current_block_start_idx = graph.try_prev_node(idx as u32).unwrap();
-                        graph.add_edge(BranchKind::Unconditional, current_block_start_idx, dest_block_idx);
-                    }
-
-                    return Ok(());
-                }
-                Op::RET(_, _) | Op::RET0(_, _) | Op::RET1(_, _) | Op::RETM(_, _) => {
-                    // analyze jump after RET1 case always next JMP in RET0
-                    if idx + 2 <= block_size {
-                        if let Op::JMP(..) = disasm(bc_raw[idx + 1])? {
-                            continue;
-                        }
-                    }
-
-                    graph.add_node(
-                        block_start_idx,
-                        Block::from_ins_vec(bc_raw[block_start_idx as usize..=idx].to_vec()),
-                    );
-
-                    return Ok(());
+            if let Some(exits) = block_exits(instructions, idx)? {
+                worklist.extend(exits.into_iter().rev().map(|(_, dest_idx)| dest_idx));
+                break;
+            }
+        }
+    }
+
+    Ok(leaders)
+}
+
+/// Builds control flow graph of prototype instructions. Works in two passes: the first one
+/// collects leaders (block starts) with worklist, the second one cuts blocks and adds edges.
+pub fn resolve_basic_blocks(bc_raw: &[u32]) -> Result<Graph<Block, BranchKind>, DecompileError> {
+    let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+    if bc_raw.is_empty() {
+        return Ok(graph);
+    }
+
+    let instructions = Instruction::decode_all(0, bc_raw)?;
+    let leaders = collect_leaders(&instructions)?;
+    let mut edges: Vec<(BranchKind, u32, u32)> = vec![];
+
+    for &block_start_idx in &leaders {
+        let next_block = leaders.range(block_start_idx + 1..).next().cloned();
+        let mut block_end_idx = bc_raw.len();
+
+        for idx in block_start_idx as usize..bc_raw.len() {
+            // block has no jumps in the end
+            if Some(idx as u32) == next_block {
+                edges.push((BranchKind::Unconditional, block_start_idx, idx as u32));
+                block_end_idx = idx;
+                break;
+            }
+
+            if let Some(exits) = block_exits(&instructions, idx)? {
+                for (kind, dest_idx) in exits {
+                    edges.push((kind, block_start_idx, dest_idx));
                 }
-                _ => {}
+                block_end_idx = idx + 1;
+                break;
             }
         }
 
         graph.add_node(
             block_start_idx,
-            Block::from_ins_vec(bc_raw[block_start_idx as usize..].to_vec()),
+            Block::from_ins_vec(bc_raw[block_start_idx as usize..block_end_idx].to_vec()),
         );
     }
 
-    Ok(())
+    for (kind, from, to) in edges {
+        graph.add_edge(kind, from, to);
+    }
+
+    Ok(graph)
 }
 
-pub fn resolve_basic_blocks(bc_raw: &[u32]) -> Result<Graph<Block, BranchKind>, DecompileError> {
-    let mut graph: Graph<Block, BranchKind> = Graph::new();
+/// Returns instruction ranges which aren't covered by basic blocks of the graph (dead code)
+pub fn find_unreachable_ranges(bc_raw: &[u32], graph: &Graph<Block, BranchKind>) -> Vec<Range<u32>> {
+    let mut ranges = vec![];
+    let mut idx = 0u32;
 
-    recurse_block(&mut graph, bc_raw, 0)?;
+    for (block_start_idx, block) in graph.iter_node_weights() {
+        if block_start_idx > idx {
+            ranges.push(idx..block_start_idx);
+        }
 
-    Ok(graph)
+        idx = idx.max(block_start_idx + block.len() as u32);
+    }
+
+    if idx < bc_raw.len() as u32 {
+        ranges.push(idx..bc_raw.len() as u32);
+    }
+
+    ranges
+}
+
+/// Adds every passed instruction range as node without edges, so dead code can be inspected.
+/// Ranges which are already in the graph are skipped.
+pub fn add_unreachable_blocks(
+    graph: &mut Graph<Block, BranchKind>,
+    bc_raw: &[u32],
+    ranges: &[Range<u32>],
+) {
+    for range in ranges {
+        if graph.node_weight(range.start).is_some() {
+            continue;
+        }
+
+        graph.add_node(
+            range.start,
+            Block::from_ins_vec(bc_raw[range.start as usize..range.end as usize].to_vec()),
+        );
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{DecompileError, read_bytecode_dump, read_uleb128};
+    use crate::disasm::ins_ad;
+    use crate::{ByteCodeProto, DecompileError, read_bytecode_dump};
+    use crate::graph::Graph;
+    use crate::resolver::{
+        add_unreachable_blocks, find_unreachable_ranges, resolve_basic_blocks, Block, BranchKind,
+    };
     use crate::utils::parse_luajit_bytecode_file;
 
     use std::fs::File;
+    use std::time::Instant;
+
+    // jump instruction with D operand pointing to passed target
+    fn ins_jump(op: u32, a: u32, idx: u32, target: u32) -> u32 {
+        ins_ad(op, a, (0x8000 + target as i64 - idx as i64 - 1) as u32)
+    }
+
+    fn block_edges(graph: &Graph<Block, BranchKind>, idx: u32) -> Vec<(BranchKind, u32)> {
+        let mut edges: Vec<(BranchKind, u32)> = graph
+            .outputs(idx)
+            .map(|e| (*graph.edge_weight(e).unwrap(), graph.edge_to(e)))
+            .collect();
+        // outputs are iterated in reverse order of addition
+        edges.reverse();
+        edges
+    }
+
+    #[test]
+    fn resolve_if_else() -> Result<(), DecompileError> {
+        let bc_raw = [
+            ins_ad(0x00, 0, 1),        // ISLT 0 1
+            ins_jump(0x58, 3, 1, 4),   // JMP => 4
+            ins_ad(0x29, 2, 1),        // KSHORT 2 1
+            ins_jump(0x58, 3, 3, 5),   // JMP => 5
+            ins_ad(0x29, 2, 2),        // KSHORT 2 2
+            ins_ad(0x4c, 2, 2),        // RET1 2 2
+        ];
+
+        let graph = resolve_basic_blocks(&bc_raw)?;
+
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.node_weight(0).unwrap().len(), 2);
+        assert_eq!(block_edges(&graph, 0), vec![(BranchKind::True, 4), (BranchKind::False, 2)]);
+        assert_eq!(block_edges(&graph, 2), vec![(BranchKind::Unconditional, 5)]);
+        assert_eq!(block_edges(&graph, 4), vec![(BranchKind::Unconditional, 5)]);
+        assert_eq!(block_edges(&graph, 5), vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_condition_polarity() -> Result<(), DecompileError> {
+        let bc_raw = [
+            ins_ad(0x01, 0, 1),        // ISGE 0 1
+            ins_jump(0x58, 3, 1, 4),   // JMP => 4
+            ins_ad(0x0d, 2, 1),        // ISFC 2 1
+            ins_jump(0x58, 3, 3, 4),   // JMP => 4
+            ins_ad(0x4c, 2, 2),        // RET1 2 2
+        ];
+
+        let graph = resolve_basic_blocks(&bc_raw)?;
+
+        // jump is taken when "v0 < v1" is false
+        assert_eq!(block_edges(&graph, 0), vec![(BranchKind::False, 4), (BranchKind::True, 2)]);
+        // jump is taken with copy when "v1" is false
+        assert_eq!(block_edges(&graph, 2), vec![(BranchKind::FalseCopy, 4), (BranchKind::True, 4)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_numeric_for() -> Result<(), DecompileError> {
+        let bc_raw = [
+            ins_ad(0x29, 0, 1),        // KSHORT 0 1
+            ins_ad(0x29, 1, 10),       // KSHORT 1 10
+            ins_ad(0x29, 2, 1),        // KSHORT 2 1
+            ins_jump(0x4d, 0, 3, 6),   // FORI 0 => 6
+            ins_ad(0x12, 4, 3),        // MOV 4 3
+            ins_jump(0x4f, 0, 5, 4),   // FORL 0 => 4
+            ins_ad(0x4b, 0, 1),        // RET0 0 1
+        ];
+
+        let graph = resolve_basic_blocks(&bc_raw)?;
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(block_edges(&graph, 0), vec![(BranchKind::LoopOut, 6), (BranchKind::LoopBody, 4)]);
+        assert_eq!(block_edges(&graph, 4), vec![(BranchKind::LoopBody, 4), (BranchKind::LoopOut, 6)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_tail_call() -> Result<(), DecompileError> {
+        let bc_raw = [
+            ins_ad(0x0f, 0, 0),        // ISF 0
+            ins_jump(0x58, 2, 1, 3),   // JMP => 3
+            ins_ad(0x44, 1, 1),        // CALLT 1 1
+            ins_ad(0x4b, 0, 1),        // RET0 0 1
+        ];
 
+        let graph = resolve_basic_blocks(&bc_raw)?;
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.node_weight(2).unwrap().len(), 1);
+        assert_eq!(block_edges(&graph, 2), vec![]);
+        assert_eq!(graph.inputs(3).count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_invalid_jump_target() {
+        let bc_raw = [ins_jump(0x58, 0, 0, 10), ins_ad(0x4b, 0, 1)];
+
+        assert!(matches!(
+            resolve_basic_blocks(&bc_raw),
+            Err(DecompileError::InvalidJumpTarget)
+        ));
+    }
+
+    #[test]
+    fn find_dead_code() -> Result<(), DecompileError> {
+        let bc_raw = [
+            ins_ad(0x29, 0, 1),        // KSHORT 0 1
+            ins_jump(0x58, 1, 1, 4),   // JMP => 4
+            ins_ad(0x29, 0, 2),        // KSHORT 0 2
+            ins_ad(0x29, 0, 3),        // KSHORT 0 3
+            ins_ad(0x4c, 0, 2),        // RET1 0 2
+            ins_ad(0x29, 0, 4),        // KSHORT 0 4
+        ];
+
+        let mut graph = resolve_basic_blocks(&bc_raw)?;
+        let ranges = find_unreachable_ranges(&bc_raw, &graph);
+
+        assert_eq!(ranges, vec![2..4, 5..6]);
+
+        add_unreachable_blocks(&mut graph, &bc_raw, &ranges);
+
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.node_weight(2).unwrap().len(), 2);
+        assert_eq!(graph.inputs(2).count(), 0);
+        assert_eq!(graph.outputs(2).count(), 0);
+        assert!(find_unreachable_ranges(&bc_raw, &graph).is_empty());
+
+        // keeping blocks twice doesn't change the graph
+        let mut proto =
+            ByteCodeProto::from_parts(0, 0, 1, vec![], vec![], vec![], bc_raw.to_vec())?;
+        proto.keep_unreachable_blocks();
+        proto.keep_unreachable_blocks();
+
+        let graph = proto.basic_block_graph_ref();
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.node_weight(2).unwrap().len(), 2);
+        assert_eq!(graph.node_weight(5).unwrap().len(), 1);
+        assert_eq!(proto.unreachable_ranges(), &[2..4, 5..6]);
+
+        Ok(())
+    }
+
+    // chain of comparisons like in generated state machines, each one is conditional branch
+    fn synthetic_prototype(conditions: u32) -> Vec<u32> {
+        let ret_idx = conditions * 2;
+        let mut bc_raw = Vec::with_capacity(ret_idx as usize + 1);
+
+        for i in 0..conditions {
+            bc_raw.push(ins_ad(0x08, 0, i % 0x100)); // ISEQN 0 i
+            bc_raw.push(ins_jump(0x58, 1, i * 2 + 1, ret_idx.min(i * 2 + 4))); // JMP => skip next
+        }
+        bc_raw.push(ins_ad(0x4b, 0, 1)); // RET0 0 1
+
+        bc_raw
+    }
+
+    #[test]
+    fn resolve_large_prototype() -> Result<(), DecompileError> {
+        let graph = resolve_basic_blocks(&synthetic_prototype(100_000))?;
+
+        assert_eq!(graph.node_count(), 100_001);
+        assert_eq!(block_edges(&graph, 0), vec![(BranchKind::True, 4), (BranchKind::False, 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn resolve_basic_blocks_bench() -> Result<(), DecompileError> {
+        let bc_raw = synthetic_prototype(1_000_000);
+
+        let start = Instant::now();
+        let graph = resolve_basic_blocks(&bc_raw)?;
+
+        println!(
+            "resolved {} instructions into {} blocks in {:?}",
+            bc_raw.len(),
+            graph.node_count(),
+            start.elapsed()
+        );
+
+        Ok(())
+    }
 
     #[test]
     fn resolve_basic_blocks_autotest() -> Result<(), DecompileError> {
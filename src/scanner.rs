@@ -0,0 +1,112 @@
+use crate::bytecode_reader::{
+    read_bytecode_dump_with_opcodes, GlobalConst, BC_HEAD1, BC_HEAD2, BC_HEAD3,
+};
+use crate::opcode_map::OpcodeMap;
+use crate::ByteCodeDump;
+
+/// Bytecode dump found inside of a larger buffer
+#[derive(Debug)]
+pub struct EmbeddedDump {
+    pub offset: usize,
+    /// length of the dump including its terminating zero
+    pub len: usize,
+    pub dump: ByteCodeDump,
+}
+
+impl EmbeddedDump {
+    /// Bytes of the dump in the scanned buffer
+    pub fn bytes<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[self.offset..self.offset + self.len]
+    }
+}
+
+// every prototype except the main one is a child of exactly one prototype
+fn is_complete(dump: &ByteCodeDump) -> bool {
+    let prototypes = dump.prototypes();
+    let children = prototypes
+        .iter()
+        .flat_map(|proto| proto.global_consts())
+        .filter(|global_const| matches!(global_const, GlobalConst::ProtoChild(_)))
+        .count();
+
+    !prototypes.is_empty() && children == prototypes.len() - 1
+}
+
+// dump at the start of `data`, returns it with its length
+fn read_embedded(data: &[u8], opcodes: &OpcodeMap) -> Option<(ByteCodeDump, usize)> {
+    let mut rest = data;
+    let dump = read_bytecode_dump_with_opcodes(&mut rest, opcodes).ok()?;
+    let len = data.len() - rest.len();
+
+    // dump ends with zero length prototype, otherwise it is cut by the end of buffer
+    if data[len - 1] != 0 || !is_complete(&dump) {
+        return None;
+    }
+
+    Some((dump, len))
+}
+
+/// Searches buffer for LuaJIT bytecode dumps, e.g. embedded into executables or archives.
+/// Candidates starting with `\x1bLJ` are accepted only when the whole dump is read, its
+/// bytecode is resolved and prototypes form a single tree. Dumps stored inside of string
+/// constants of other dumps are reported as well.
+pub fn scan(buf: &[u8]) -> Vec<EmbeddedDump> {
+    scan_with_opcodes(buf, &OpcodeMap::default())
+}
+
+/// Searches buffer for dumps of LuaJIT build with custom opcode numbering
+pub fn scan_with_opcodes(buf: &[u8], opcodes: &OpcodeMap) -> Vec<EmbeddedDump> {
+    buf.windows(3)
+        .enumerate()
+        .filter(|(_, magic)| magic == &[BC_HEAD1, BC_HEAD2, BC_HEAD3])
+        .filter_map(|(offset, _)| {
+            let (dump, len) = read_embedded(&buf[offset..], opcodes)?;
+            Some(EmbeddedDump { offset, len, dump })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_listing;
+    use crate::scanner::scan;
+
+    const LISTING: &str = "-- BYTECODE -- ?:0-0\n\
+        0001    GGET     0   0      ; \"print\"\n\
+        0002    KSHORT   1   1\n\
+        0003    CALL     0   1   2\n\
+        0004    RET0     0   1\n\
+        \n";
+
+    #[test]
+    fn scan_embedded_dumps() {
+        let chunk = assemble_listing(LISTING, None).unwrap();
+
+        // false positives: bare magic, truncated dump and dump with broken bytecode
+        let mut buf = b"\x7fELF\x1bLJ\x02garbage".to_vec();
+        buf.extend_from_slice(&chunk[..chunk.len() - 3]);
+        // header, flags, prototype length, prototype header and sizes precede bytecode
+        let mut broken = chunk.clone();
+        broken[13] = 0xff;
+        buf.extend_from_slice(&broken);
+        // table constant claims 0xffffffff array items
+        buf.extend_from_slice(b"\x1bLJ\x02\x02\x12\x00\x00\x01\x00\x01\x00\x01");
+        buf.extend_from_slice(b"\x4b\x00\x01\x00\x01\xff\xff\xff\xff\x0f\x00");
+
+        let first = buf.len();
+        buf.extend_from_slice(&chunk);
+        buf.extend_from_slice(b"\x00\x00trailer\x1bLJ");
+        let second = buf.len();
+        buf.extend_from_slice(&chunk);
+
+        let found = scan(&buf);
+        let offsets: Vec<usize> = found.iter().map(|dump| dump.offset).collect();
+        assert_eq!(offsets, vec![first, second]);
+
+        for dump in &found {
+            assert_eq!(dump.len, chunk.len());
+            assert_eq!(dump.bytes(&buf), chunk.as_slice());
+            assert_eq!(dump.dump.prototypes().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,752 @@
+use crate::graph::algo::{dominator_tree, post_dominator_tree, DominatorTree, VIRTUAL_EXIT};
+use crate::ir::{Block, Expr, FmtOptions, Insn, Pretty};
+use crate::resolver::BranchKind;
+use crate::Graph;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+/// Condition of the two-way branch
+#[derive(Debug, Clone)]
+pub enum Cond {
+    /// condition of the block, "then" branch is `True` edge
+    Expr(Box<Expr>),
+    /// loop instruction (`FORI`, `FORL`, `ITERL`) decides if loop body is entered
+    Loop,
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cond::Expr(expr) => write!(f, "{}", expr),
+            Cond::Loop => write!(f, "<loop>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Insn(Insn),
+    If(Cond, Vec<Stmt>, Vec<Stmt>),
+    /// `while true do ... end` loop, identified by header block index
+    Loop(u32, Vec<Stmt>),
+    Break,
+    /// jump to the end of the loop body, identified by header block index
+    Continue(u32),
+    Goto(u32),
+    Label(u32),
+}
+
+/// Structured function body. Every basic block is emitted exactly once, all control flow
+/// which can't be expressed with `if` and loops is kept as `goto` statements.
+#[derive(Debug, Clone)]
+pub struct FunctionBody {
+    stmts: Vec<Stmt>,
+}
+
+impl FunctionBody {
+    pub fn stmts(&self) -> &[Stmt] {
+        &self.stmts
+    }
+
+    pub fn display(&self, options: FmtOptions) -> Pretty<'_, FunctionBody> {
+        Pretty(self, options)
+    }
+}
+
+impl fmt::Display for FunctionBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_stmts(f, &self.stmts, 0, FmtOptions::default())
+    }
+}
+
+impl fmt::Display for Pretty<'_, FunctionBody> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_stmts(f, &self.0.stmts, 0, self.1)
+    }
+}
+
+fn write_stmts(
+    f: &mut fmt::Formatter,
+    stmts: &[Stmt],
+    indent: usize,
+    o: FmtOptions,
+) -> fmt::Result {
+    let pad = "    ".repeat(indent);
+
+    for (idx, stmt) in stmts.iter().enumerate() {
+        match stmt {
+            // "return" must be the last statement in lua block
+            Stmt::Insn(insn @ (Insn::Return(_) | Insn::TailCall(_))) if idx + 1 != stmts.len() => {
+                writeln!(f, "{}do {} end", pad, insn.display(o))?
+            }
+            Stmt::Insn(insn) => writeln!(f, "{}{}", pad, insn.display(o))?,
+            Stmt::If(cond, then_stmts, else_stmts) => {
+                let cond = match cond {
+                    Cond::Expr(expr) => expr.display(o).to_string(),
+                    Cond::Loop => cond.to_string(),
+                };
+
+                if then_stmts.is_empty() && !else_stmts.is_empty() {
+                    writeln!(f, "{}if not ({}) then", pad, cond)?;
+                    write_stmts(f, else_stmts, indent + 1, o)?;
+                } else {
+                    writeln!(f, "{}if {} then", pad, cond)?;
+                    write_stmts(f, then_stmts, indent + 1, o)?;
+
+                    if !else_stmts.is_empty() {
+                        writeln!(f, "{}else", pad)?;
+                        write_stmts(f, else_stmts, indent + 1, o)?;
+                    }
+                }
+
+                writeln!(f, "{}end", pad)?;
+            }
+            Stmt::Loop(header, body) => {
+                writeln!(f, "{}while true do", pad)?;
+                write_stmts(f, body, indent + 1, o)?;
+
+                if contains_continue(body, *header) {
+                    writeln!(f, "{}    ::continue_{}::", pad, header)?;
+                }
+
+                writeln!(f, "{}end", pad)?;
+            }
+            Stmt::Break => writeln!(f, "{}break", pad)?,
+            Stmt::Continue(header) => writeln!(f, "{}goto continue_{}", pad, header)?,
+            Stmt::Goto(target) => writeln!(f, "{}goto label_{}", pad, target)?,
+            Stmt::Label(target) => writeln!(f, "{}::label_{}::", pad, target)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn contains_continue(stmts: &[Stmt], header: u32) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Continue(target) => *target == header,
+        Stmt::If(_, then_stmts, else_stmts) => {
+            contains_continue(then_stmts, header) || contains_continue(else_stmts, header)
+        }
+        Stmt::Loop(_, body) => contains_continue(body, header),
+        _ => false,
+    })
+}
+
+/// Checks if execution can reach the end of statements list
+fn falls_through(stmts: &[Stmt]) -> bool {
+    match stmts.last() {
+        Some(Stmt::Insn(Insn::Return(_) | Insn::TailCall(_)))
+        | Some(Stmt::Break)
+        | Some(Stmt::Continue(_))
+        | Some(Stmt::Goto(_)) => false,
+        Some(Stmt::If(_, then_stmts, else_stmts)) => {
+            falls_through(then_stmts) || falls_through(else_stmts)
+        }
+        _ => true,
+    }
+}
+
+#[derive(Debug)]
+struct NaturalLoop {
+    nodes: BTreeSet<u32>,
+    parent: Option<u32>,
+    follow: Option<u32>,
+}
+
+struct Structurer<'a> {
+    graph: &'a Graph<Block, BranchKind>,
+    dominators: DominatorTree,
+    post_dominators: DominatorTree,
+    /// natural loops by header block index
+    loops: BTreeMap<u32, NaturalLoop>,
+
+    emitted: HashSet<u32>,
+    /// blocks emitted inside of `if` branches, labels placed there aren't visible for `goto`
+    nested: HashSet<u32>,
+    /// blocks which can be emitted only at the top level of their region
+    forced: HashSet<u32>,
+    gotos: BTreeSet<u32>,
+}
+
+impl<'a> Structurer<'a> {
+    fn new(graph: &'a Graph<Block, BranchKind>, entry: u32) -> Self {
+        let mut structurer = Self {
+            graph,
+            dominators: dominator_tree(graph, entry),
+            post_dominators: post_dominator_tree(graph),
+            loops: BTreeMap::new(),
+            emitted: HashSet::new(),
+            nested: HashSet::new(),
+            forced: HashSet::new(),
+            gotos: BTreeSet::new(),
+        };
+
+        structurer.find_loops();
+        structurer
+    }
+
+    fn find_loops(&mut self) {
+        let graph = self.graph;
+
+        for &node in graph.nodes().keys() {
+            if self.dominators.dominators(node).is_none() {
+                // unreachable block
+                continue;
+            }
+
+            for edge in graph.outputs(node) {
+                let header = graph.edge_to(edge);
+
+                // back edge
+                if self.dominators.dominates(header, node) {
+                    let natural_loop = self.loops.entry(header).or_insert_with(|| NaturalLoop {
+                        nodes: BTreeSet::new(),
+                        parent: None,
+                        follow: None,
+                    });
+
+                    natural_loop.nodes.insert(header);
+
+                    // collect all blocks which reach back edge without passing header
+                    let mut stack = vec![node];
+                    while let Some(idx) = stack.pop() {
+                        if natural_loop.nodes.insert(idx) {
+                            stack.extend(graph.inputs(idx).map(|e| graph.edge(e).unwrap().from()));
+                        }
+                    }
+                }
+            }
+        }
+
+        let headers: Vec<u32> = self.loops.keys().cloned().collect();
+
+        for &header in &headers {
+            // parent loop is the smallest loop containing header
+            let parent = headers
+                .iter()
+                .filter(|&&other| other != header && self.loops[&other].nodes.contains(&header))
+                .min_by_key(|&&other| self.loops[&other].nodes.len())
+                .cloned();
+
+            // prefer explicit loop out edge, otherwise take the closest exit
+            let mut follow = None;
+            for &idx in &self.loops[&header].nodes {
+                for edge in graph.outputs(idx) {
+                    let to = graph.edge_to(edge);
+
+                    if self.loops[&header].nodes.contains(&to) {
+                        continue;
+                    }
+
+                    if *graph.edge_weight(edge).unwrap() == BranchKind::LoopOut {
+                        follow = Some(to);
+                        break;
+                    }
+
+                    follow = Some(follow.map_or(to, |f: u32| f.min(to)));
+                }
+            }
+
+            let natural_loop = self.loops.get_mut(&header).unwrap();
+            natural_loop.parent = parent;
+            natural_loop.follow = follow;
+        }
+    }
+
+    fn innermost_loop(&self, node: u32) -> Option<u32> {
+        self.loops
+            .iter()
+            .filter(|(_, l)| l.nodes.contains(&node))
+            .min_by_key(|(_, l)| l.nodes.len())
+            .map(|(&header, _)| header)
+    }
+
+    /// Returns region (loop header or `None` for function body) where block is emitted
+    fn owner(&self, node: u32) -> Option<u32> {
+        match self.loops.get(&node) {
+            Some(natural_loop) => natural_loop.parent,
+            None => self.innermost_loop(node),
+        }
+    }
+
+    fn in_region(&self, node: u32, region: Option<u32>) -> bool {
+        region.is_none_or(|header| self.loops[&header].nodes.contains(&node))
+    }
+
+    fn can_inline(&self, node: u32, region: Option<u32>) -> bool {
+        !self.emitted.contains(&node)
+            && !self.forced.contains(&node)
+            && self.owner(node) == region
+            && self
+                .dominators
+                .idom(node)
+                .is_none_or(|idom| self.emitted.contains(&idom))
+    }
+
+    fn goto(&mut self, node: u32) -> Stmt {
+        self.gotos.insert(node);
+        Stmt::Goto(node)
+    }
+
+    fn structure(&mut self) -> Vec<Stmt> {
+        loop {
+            self.emitted.clear();
+            self.nested.clear();
+            self.gotos.clear();
+
+            let stmts = self.emit_region(None);
+
+            // labels inside of nested blocks aren't visible from outside, so their blocks
+            // have to be moved to the top level of region
+            let misplaced: Vec<u32> = self
+                .gotos
+                .iter()
+                .filter(|node| self.nested.contains(node))
+                .cloned()
+                .collect();
+
+            if misplaced.is_empty() {
+                return stmts;
+            }
+
+            self.forced.extend(misplaced);
+        }
+    }
+
+    fn emit_region(&mut self, region: Option<u32>) -> Vec<Stmt> {
+        let mut stmts = vec![];
+
+        let next = match region {
+            Some(header) => self.emit_node(header, &mut stmts, None, region, 0),
+            None => self.graph.nodes().keys().next().cloned(),
+        };
+        self.emit_seq(next, &mut stmts, None, region, 0);
+
+        // emit blocks which are reachable only with goto
+        while let Some(target) = self
+            .gotos
+            .iter()
+            .find(|&&node| !self.emitted.contains(&node) && self.owner(node) == region)
+            .cloned()
+        {
+            if falls_through(&stmts) {
+                stmts.push(match region {
+                    Some(header) => Stmt::Continue(header),
+                    None => Stmt::Insn(Insn::Return(vec![].into_boxed_slice())),
+                });
+            }
+
+            let next = self.emit_entry(target, &mut stmts, None, region, 0);
+            self.emit_seq(next, &mut stmts, None, region, 0);
+        }
+
+        if let (Some(header), Some(Stmt::Continue(target))) = (region, stmts.last()) {
+            if header == *target {
+                stmts.pop();
+            }
+        }
+
+        stmts
+    }
+
+    fn emit_seq(
+        &mut self,
+        start: Option<u32>,
+        stmts: &mut Vec<Stmt>,
+        follow: Option<u32>,
+        region: Option<u32>,
+        depth: usize,
+    ) {
+        let mut current = start;
+
+        while let Some(node) = current {
+            if Some(node) == follow {
+                break;
+            }
+
+            if let Some(header) = region {
+                if node == header {
+                    stmts.push(Stmt::Continue(header));
+                    break;
+                }
+
+                if !self.in_region(node, region) {
+                    if Some(node) == self.loops[&header].follow {
+                        stmts.push(Stmt::Break);
+                    } else {
+                        let goto = self.goto(node);
+                        stmts.push(goto);
+                    }
+                    break;
+                }
+            }
+
+            if !self.can_inline(node, region) {
+                let goto = self.goto(node);
+                stmts.push(goto);
+                break;
+            }
+
+            current = self.emit_entry(node, stmts, follow, region, depth);
+        }
+    }
+
+    fn emit_entry(
+        &mut self,
+        node: u32,
+        stmts: &mut Vec<Stmt>,
+        follow: Option<u32>,
+        region: Option<u32>,
+        depth: usize,
+    ) -> Option<u32> {
+        self.emitted.insert(node);
+
+        if depth > 0 {
+            self.nested.insert(node);
+        } else {
+            stmts.push(Stmt::Label(node));
+        }
+
+        if self.loops.contains_key(&node) {
+            let body = self.emit_region(Some(node));
+            stmts.push(Stmt::Loop(node, body));
+
+            self.loops[&node].follow
+        } else {
+            self.emit_node(node, stmts, follow, region, depth)
+        }
+    }
+
+    /// Emits block instructions and returns next block index in current sequence
+    fn emit_node(
+        &mut self,
+        node: u32,
+        stmts: &mut Vec<Stmt>,
+        follow: Option<u32>,
+        region: Option<u32>,
+        depth: usize,
+    ) -> Option<u32> {
+        let graph = self.graph;
+        let mut insns: Vec<Insn> = graph.node_weight(node).unwrap().iter_insn().cloned().collect();
+        let outputs: Vec<u32> = graph.outputs(node).collect();
+
+        match outputs.len() {
+            0 => {
+                stmts.extend(insns.into_iter().map(Stmt::Insn));
+                None
+            }
+            1 => {
+                stmts.extend(insns.into_iter().map(Stmt::Insn));
+                Some(graph.edge_to(outputs[0]))
+            }
+            2 => {
+                let block = graph.node_weight(node).unwrap();
+
+                // outputs are stored in reverse order of addition
+                let mut then_edge = outputs[1];
+                let mut else_edge = outputs[0];
+
+                for &edge in &outputs {
+                    match graph.edge_weight(edge).unwrap() {
+                        BranchKind::LoopBody => then_edge = edge,
+                        BranchKind::LoopOut => else_edge = edge,
+                        kind => match kind.polarity() {
+                            Some(true) => then_edge = edge,
+                            Some(false) => else_edge = edge,
+                            None => {}
+                        },
+                    }
+                }
+
+                let then_kind = *graph.edge_weight(then_edge).unwrap();
+                let else_kind = *graph.edge_weight(else_edge).unwrap();
+                let then_target = graph.edge_to(then_edge);
+                let else_target = graph.edge_to(else_edge);
+
+                let cond = match block.branch_condition(then_kind) {
+                    Some(expr) => {
+                        insns.pop();
+                        Cond::Expr(expr)
+                    }
+                    None => Cond::Loop,
+                };
+
+                stmts.extend(insns.into_iter().map(Stmt::Insn));
+
+                let branch_follow = self
+                    .post_dominators
+                    .idom(node)
+                    .filter(|&idx| idx != VIRTUAL_EXIT && self.in_region(idx, region));
+                let inner_follow = branch_follow.or(follow);
+
+                // ISTC/ISFC copy tested value only when jump is taken
+                let then_copy = block.branch_copy(then_kind).map(Stmt::Insn);
+                let else_copy = block.branch_copy(else_kind).map(Stmt::Insn);
+
+                let mut then_stmts: Vec<Stmt> = then_copy.into_iter().collect();
+                self.emit_seq(Some(then_target), &mut then_stmts, inner_follow, region, depth + 1);
+
+                // "if cond then break end" - rest of code doesn't need to be nested
+                if branch_follow.is_none() && !falls_through(&then_stmts) {
+                    stmts.push(Stmt::If(cond, then_stmts, vec![]));
+                    stmts.extend(else_copy);
+                    return Some(else_target);
+                }
+
+                let mut else_stmts: Vec<Stmt> = else_copy.into_iter().collect();
+                self.emit_seq(Some(else_target), &mut else_stmts, inner_follow, region, depth + 1);
+
+                stmts.push(Stmt::If(cond, then_stmts, else_stmts));
+
+                branch_follow
+            }
+            _ => unreachable!("basic block with more than two successors"),
+        }
+    }
+}
+
+/// Removes labels without `goto` and `goto` statements which jump to the next statement
+fn cleanup(stmts: &mut Vec<Stmt>, gotos: &mut BTreeSet<u32>) {
+    let mut idx = 0;
+    while idx < stmts.len() {
+        match (&stmts[idx], stmts.get(idx + 1)) {
+            (Stmt::Goto(target), Some(Stmt::Label(label))) if target == label => {
+                stmts.remove(idx);
+                continue;
+            }
+            _ => {}
+        }
+
+        match &mut stmts[idx] {
+            Stmt::If(_, then_stmts, else_stmts) => {
+                cleanup(then_stmts, gotos);
+                cleanup(else_stmts, gotos);
+            }
+            Stmt::Loop(_, body) => cleanup(body, gotos),
+            Stmt::Goto(target) => {
+                gotos.insert(*target);
+            }
+            _ => {}
+        }
+
+        idx += 1;
+    }
+}
+
+fn remove_unused_labels(stmts: &mut Vec<Stmt>, gotos: &BTreeSet<u32>) {
+    stmts.retain(|stmt| match stmt {
+        Stmt::Label(label) => gotos.contains(label),
+        _ => true,
+    });
+
+    for stmt in stmts.iter_mut() {
+        match stmt {
+            Stmt::If(_, then_stmts, else_stmts) => {
+                remove_unused_labels(then_stmts, gotos);
+                remove_unused_labels(else_stmts, gotos);
+            }
+            Stmt::Loop(_, body) => remove_unused_labels(body, gotos),
+            _ => {}
+        }
+    }
+}
+
+/// Builds structured function body from lifted control flow graph. Loop exits become `break`,
+/// edges which don't fit into `if`/`while` structure become `goto` with `::label::`.
+pub fn structure_graph(graph: &Graph<Block, BranchKind>) -> FunctionBody {
+    let entry = match graph.nodes().keys().next() {
+        Some(&entry) => entry,
+        None => return FunctionBody { stmts: vec![] },
+    };
+
+    let mut stmts = Structurer::new(graph, entry).structure();
+
+    let mut gotos = BTreeSet::new();
+    cleanup(&mut stmts, &mut gotos);
+    remove_unused_labels(&mut stmts, &gotos);
+
+    FunctionBody { stmts }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::Graph;
+    use crate::ir::test_utils::block;
+    use crate::ir::{Block, Expr, Insn, Var};
+    use crate::resolver::BranchKind;
+    use crate::structuring::structure_graph;
+
+    fn ret() -> Insn {
+        Insn::Return(vec![].into_boxed_slice())
+    }
+
+    #[test]
+    fn loop_exit_is_break() {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+        graph.add_node(0, block(vec![Insn::set_var(Var(0), Expr::short(0))]));
+        graph.add_node(1, block(vec![Insn::If(Expr::var(0))]));
+        graph.add_node(2, block(vec![Insn::set_var(Var(0), Expr::var(1))]));
+        graph.add_node(3, block(vec![ret()]));
+
+        graph.add_edge(BranchKind::Unconditional, 0, 1);
+        graph.add_edge(BranchKind::True, 1, 3);
+        graph.add_edge(BranchKind::False, 1, 2);
+        graph.add_edge(BranchKind::Unconditional, 2, 1);
+
+        let body = structure_graph(&graph);
+
+        assert_eq!(
+            "v0 = Short(0)\n\
+             while true do\n    \
+                 if v0 then\n        \
+                     break\n    \
+                 end\n    \
+                 v0 = v1\n\
+             end\n\
+             return\n",
+            format!("{}", body)
+        );
+    }
+
+    #[test]
+    fn irreducible_flow_uses_goto() {
+        // two entries into the cycle between blocks 1 and 2
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+        graph.add_node(0, block(vec![Insn::If(Expr::var(0))]));
+        graph.add_node(1, block(vec![Insn::set_var(Var(1), Expr::var(2))]));
+        graph.add_node(2, block(vec![Insn::If(Expr::var(1))]));
+        graph.add_node(3, block(vec![ret()]));
+
+        graph.add_edge(BranchKind::True, 0, 2);
+        graph.add_edge(BranchKind::False, 0, 1);
+        graph.add_edge(BranchKind::Unconditional, 1, 2);
+        graph.add_edge(BranchKind::True, 2, 3);
+        graph.add_edge(BranchKind::False, 2, 1);
+
+        assert_eq!(
+            "if not (v0) then\n    \
+                 goto label_1\n\
+             end\n\
+             ::label_2::\n\
+             if not (v1) then\n    \
+                 goto label_1\n\
+             end\n\
+             do return end\n\
+             ::label_1::\n\
+             v1 = v2\n\
+             goto label_2\n",
+            format!("{}", structure_graph(&graph))
+        );
+    }
+
+    #[test]
+    fn nested_loops_break_inside_if() {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+        graph.add_node(0, block(vec![Insn::set_var(Var(0), Expr::short(0))]));
+        graph.add_node(1, block(vec![Insn::If(Expr::var(1))]));
+        graph.add_node(2, block(vec![Insn::If(Expr::var(2))]));
+        graph.add_node(3, block(vec![Insn::set_var(Var(3), Expr::var(4))]));
+        graph.add_node(4, block(vec![Insn::set_var(Var(5), Expr::var(6))]));
+        graph.add_node(5, block(vec![ret()]));
+
+        graph.add_edge(BranchKind::Unconditional, 0, 1);
+        graph.add_edge(BranchKind::True, 1, 5);
+        graph.add_edge(BranchKind::False, 1, 2);
+        graph.add_edge(BranchKind::True, 2, 4);
+        graph.add_edge(BranchKind::False, 2, 3);
+        graph.add_edge(BranchKind::Unconditional, 3, 2);
+        graph.add_edge(BranchKind::Unconditional, 4, 1);
+
+        assert_eq!(
+            "v0 = Short(0)\n\
+             while true do\n    \
+                 if v1 then\n        \
+                     break\n    \
+                 end\n    \
+                 while true do\n        \
+                     if v2 then\n            \
+                         break\n        \
+                     end\n        \
+                     v3 = v4\n    \
+                 end\n    \
+                 v5 = v6\n\
+             end\n\
+             return\n",
+            format!("{}", structure_graph(&graph))
+        );
+    }
+
+    #[test]
+    fn loop_follow_reached_by_goto() {
+        // loop exit block is also a target of the branch before the loop
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+        graph.add_node(0, block(vec![Insn::If(Expr::var(0))]));
+        graph.add_node(1, block(vec![Insn::If(Expr::var(1))]));
+        graph.add_node(2, block(vec![Insn::If(Expr::var(2))]));
+        graph.add_node(4, block(vec![Insn::set_var(Var(3), Expr::var(4)), ret()]));
+        graph.add_node(5, block(vec![ret()]));
+
+        graph.add_edge(BranchKind::True, 0, 4);
+        graph.add_edge(BranchKind::False, 0, 1);
+        graph.add_edge(BranchKind::True, 1, 5);
+        graph.add_edge(BranchKind::False, 1, 2);
+        graph.add_edge(BranchKind::True, 2, 4);
+        graph.add_edge(BranchKind::False, 2, 1);
+
+        assert_eq!(
+            "if v0 then\n    \
+                 goto label_4\n\
+             end\n\
+             while true do\n    \
+                 if v1 then\n        \
+                     goto label_5\n    \
+                 end\n    \
+                 if v2 then\n        \
+                     break\n    \
+                 end\n\
+             end\n\
+             ::label_4::\n\
+             v3 = v4\n\
+             do return end\n\
+             ::label_5::\n\
+             return\n",
+            format!("{}", structure_graph(&graph))
+        );
+    }
+
+    #[test]
+    fn nested_goto_target_is_moved_to_top_level() {
+        // block 3 is emitted inside of the first branch, then the second one needs goto to it
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+
+        graph.add_node(0, block(vec![Insn::If(Expr::var(0))]));
+        graph.add_node(1, block(vec![Insn::If(Expr::var(1))]));
+        graph.add_node(2, block(vec![Insn::set_var(Var(2), Expr::var(3))]));
+        graph.add_node(3, block(vec![Insn::set_var(Var(4), Expr::var(5)), ret()]));
+        graph.add_node(4, block(vec![ret()]));
+
+        graph.add_edge(BranchKind::True, 0, 1);
+        graph.add_edge(BranchKind::False, 0, 2);
+        graph.add_edge(BranchKind::True, 1, 3);
+        graph.add_edge(BranchKind::False, 1, 4);
+        graph.add_edge(BranchKind::Unconditional, 2, 3);
+
+        assert_eq!(
+            "if v0 then\n    \
+                 if v1 then\n        \
+                     goto label_3\n    \
+                 end\n    \
+                 return\n\
+             end\n\
+             v2 = v3\n\
+             ::label_3::\n\
+             v4 = v5\n\
+             return\n",
+            format!("{}", structure_graph(&graph))
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use crate::graph::dataflow::{solve, DataflowAnalysis, DataflowResult, Direction};
+use crate::ir::{Block, Expr, Insn, ENTRY_BLOCK};
+use crate::resolver::BranchKind;
+use crate::Graph;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Lua type of value, `Unknown` is the top of the lattice
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Type {
+    Nil,
+    Boolean,
+    Number,
+    String,
+    Table,
+    Function,
+    CData,
+    Unknown,
+}
+
+impl Type {
+    /// Maps operand of `ISTYPE`, it holds negated internal type tag
+    pub fn from_type_tag(tag: u8) -> Type {
+        match tag {
+            1 => Type::Nil,
+            2 | 3 => Type::Boolean,
+            5 => Type::String,
+            9 => Type::Function,
+            11 => Type::CData,
+            12 => Type::Table,
+            14 => Type::Number,
+            _ => Type::Unknown,
+        }
+    }
+
+    /// Least upper bound of two types
+    pub fn join(self, other: Type) -> Type {
+        match self == other {
+            true => self,
+            false => Type::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Type::Nil => "nil",
+                Type::Boolean => "boolean",
+                Type::Number => "number",
+                Type::String => "string",
+                Type::Table => "table",
+                Type::Function => "function",
+                Type::CData => "cdata",
+                Type::Unknown => "unknown",
+            }
+        )
+    }
+}
+
+/// Known types of slots, missing slots have `Unknown` type
+pub type TypeEnv = BTreeMap<u16, Type>;
+
+// first result of standard library functions which always return the same type
+fn stdlib_return_type(name: &str) -> Type {
+    match name {
+        "tostring" | "type" | "string.format" | "string.rep" | "string.sub" | "string.upper"
+        | "string.lower" | "string.char" | "string.reverse" | "string.gsub" | "table.concat"
+        | "os.date" => Type::String,
+        "string.len" | "math.floor" | "math.ceil" | "math.abs" | "math.sqrt" | "math.sin"
+        | "math.cos" | "math.tan" | "math.exp" | "math.log" | "math.pow" | "math.fmod"
+        | "math.random" | "math.max" | "math.min" | "os.time" | "os.clock" | "bit.band"
+        | "bit.bor" | "bit.bxor" | "bit.bnot" | "bit.lshift" | "bit.rshift" | "bit.arshift"
+        | "bit.tobit" => Type::Number,
+        "setmetatable" => Type::Table,
+        "pairs" | "ipairs" | "loadstring" => Type::Function,
+        _ => Type::Unknown,
+    }
+}
+
+// arithmetic of numbers and strings coercible to them gives number or raises error
+fn arith_type(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (Type::Number | Type::String, Type::Number | Type::String) => Type::Number,
+        _ => Type::Unknown,
+    }
+}
+
+/// Type of expression in passed environment
+pub fn expr_type(expr: &Expr, env: &TypeEnv) -> Type {
+    match expr {
+        Expr::Var(var) => env.get(&var.0).cloned().unwrap_or(Type::Unknown),
+        Expr::Str(_) => Type::String,
+        Expr::Num(_) | Expr::Lit(_) | Expr::Short(_) | Expr::Number(_) => Type::Number,
+        Expr::Bool(_) => Type::Boolean,
+        Expr::Nil => Type::Nil,
+        Expr::Cdata(_) => Type::CData,
+        Expr::Closure(_) => Type::Function,
+        Expr::GlobalTable => Type::Table,
+        Expr::Lt(_) | Expr::Ge(_) | Expr::Le(_) | Expr::Gt(_) | Expr::Eq(_) | Expr::Ne(_) => {
+            Type::Boolean
+        }
+        Expr::Not(_) => Type::Boolean,
+        // other types can have `__len` metamethod
+        Expr::Len(a) => match expr_type(a, env) {
+            Type::String | Type::Table => Type::Number,
+            _ => Type::Unknown,
+        },
+        Expr::Minus(a) => arith_type(expr_type(a, env), Type::Number),
+        Expr::Add([a, b])
+        | Expr::Sub([a, b])
+        | Expr::Mul([a, b])
+        | Expr::Div([a, b])
+        | Expr::Mod([a, b])
+        | Expr::Pow([a, b]) => arith_type(expr_type(a, env), expr_type(b, env)),
+        Expr::Uv(_) | Expr::Table(_) => Type::Unknown,
+    }
+}
+
+// name of global function or library field, e.g. `string.format`
+fn global_name(expr: &Expr, names: &BTreeMap<u16, String>) -> Option<String> {
+    match expr {
+        Expr::Table([table, field]) => match (table.as_ref(), field.as_ref()) {
+            (Expr::GlobalTable, Expr::Str(name)) => Some(name.clone()),
+            (Expr::Var(var), Expr::Str(name)) => Some(format!("{}.{}", names.get(&var.0)?, name)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// types after unlifted instruction
+fn unlifted_type(name: &str) -> Type {
+    match name {
+        "TNEW" | "TDUP" => Type::Table,
+        "FORI" | "JFORI" | "FORL" | "IFORL" | "JFORL" => Type::Number,
+        _ => Type::Unknown,
+    }
+}
+
+// state of block walk, global names of loaded functions are tracked within the block only
+struct BlockState<'a> {
+    types: TypeEnv,
+    names: BTreeMap<u16, String>,
+    captured: &'a BTreeSet<u16>,
+}
+
+impl BlockState<'_> {
+    fn set(&mut self, slot: u16, ty: Type) {
+        if ty == Type::Unknown || self.captured.contains(&slot) {
+            self.types.remove(&slot);
+        } else {
+            self.types.insert(slot, ty);
+        }
+    }
+
+    fn step(&mut self, insn: &Insn) {
+        let ty = match insn {
+            Insn::SetVars(_, expr) => expr_type(expr, &self.types),
+            Insn::Call(_, args) => match args.first() {
+                Some(Expr::Var(func)) => self
+                    .names
+                    .get(&func.0)
+                    .map_or(Type::Unknown, |name| stdlib_return_type(name)),
+                _ => Type::Unknown,
+            },
+            Insn::Cat(_, exprs) => match exprs
+                .iter()
+                .all(|expr| matches!(expr_type(expr, &self.types), Type::String | Type::Number))
+            {
+                true => Type::String,
+                false => Type::Unknown,
+            },
+            // copy is done on one edge only
+            Insn::IfCopy(var, expr) => {
+                expr_type(expr, &self.types).join(expr_type(&Expr::Var(var.clone()), &self.types))
+            }
+            Insn::For(_) => Type::Number,
+            Insn::Unlifted(name, ..) => unlifted_type(name),
+            _ => Type::Unknown,
+        };
+
+        let name = match insn {
+            Insn::SetVars(_, expr) => global_name(expr, &self.names),
+            _ => None,
+        };
+
+        let defs = insn.defs();
+
+        for (idx, &slot) in defs.iter().enumerate() {
+            self.names.remove(&slot);
+
+            // only the first result of call has known type
+            match (insn, idx) {
+                (Insn::Call(..), 1..) => self.set(slot, Type::Unknown),
+                _ => self.set(slot, ty),
+            }
+        }
+
+        if let Insn::TypeGuard(var, ty) = insn {
+            if *ty != Type::Unknown {
+                self.set(var.0, *ty);
+            }
+        }
+
+        if let (Some(name), [slot]) = (name, defs.as_slice()) {
+            self.names.insert(*slot, name);
+        }
+    }
+}
+
+/// Forward analysis of slot types, `None` is a fact of block which isn't reached yet.
+/// Slots `captured` by child closures can be changed by any call and aren't tracked.
+pub struct TypeInference<'a> {
+    captured: &'a BTreeSet<u16>,
+}
+
+impl<'a> TypeInference<'a> {
+    pub fn new(captured: &'a BTreeSet<u16>) -> Self {
+        TypeInference { captured }
+    }
+
+    /// Returns slot types at the start and at the end of every block
+    pub fn analyze(
+        graph: &Graph<Block, BranchKind>,
+        captured: &BTreeSet<u16>,
+    ) -> DataflowResult<Option<TypeEnv>> {
+        solve(graph, ENTRY_BLOCK, &TypeInference::new(captured))
+    }
+
+    /// Returns slot types before every instruction of the block, `entry` is the block entry fact
+    pub fn types_before(&self, block: &Block, entry: &Option<TypeEnv>) -> Vec<TypeEnv> {
+        let mut state = self.state(entry.clone().unwrap_or_default());
+
+        block
+            .iter_insn()
+            .map(|insn| {
+                let types = state.types.clone();
+                state.step(insn);
+                types
+            })
+            .collect()
+    }
+
+    fn state(&self, types: TypeEnv) -> BlockState<'a> {
+        BlockState {
+            types,
+            names: BTreeMap::new(),
+            captured: self.captured,
+        }
+    }
+}
+
+impl DataflowAnalysis<Block, BranchKind> for TypeInference<'_> {
+    type Fact = Option<TypeEnv>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self) -> Self::Fact {
+        Some(TypeEnv::new())
+    }
+
+    fn initial(&self) -> Self::Fact {
+        None
+    }
+
+    fn meet(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        if let Some(other) = other {
+            match fact {
+                None => *fact = Some(other.clone()),
+                Some(types) => types.retain(|slot, ty| other.get(slot) == Some(ty)),
+            }
+        }
+    }
+
+    fn transfer(&self, _: u32, node: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut state = self.state(fact.clone()?);
+
+        for insn in node.iter_insn() {
+            state.step(insn);
+        }
+
+        Some(state.types)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::Graph;
+    use crate::ir::test_utils::block;
+    use crate::ir::{Block, Expr, Insn, Var};
+    use crate::resolver::BranchKind;
+    use crate::type_inference::{Type, TypeInference};
+    use std::collections::BTreeSet;
+
+    fn global(name: &str) -> Box<Expr> {
+        Expr::table(Box::new(Expr::GlobalTable), Expr::str(name.to_string()))
+    }
+
+    //      0
+    //     / \
+    //    1   2
+    //     \ /
+    //      3
+    fn test_graph() -> Graph<Block, BranchKind> {
+        let mut graph: Graph<Block, BranchKind> = Graph::new();
+        graph.add_node(
+            0,
+            block(vec![
+                Insn::set_var(Var(0), Expr::str("10".to_string())),
+                Insn::set_var(Var(1), Expr::short(1)),
+                Insn::set_var(Var(2), Expr::add(Expr::var(1), Expr::var(0))),
+                Insn::Unlifted("TNEW", vec![Var(3)].into(), vec![].into()),
+                Insn::If(Expr::var(4)),
+            ]),
+        );
+        graph.add_node(
+            1,
+            block(vec![
+                Insn::set_var(Var(5), Expr::short(1)),
+                Insn::set_var(Var(6), global("string")),
+                Insn::set_var(
+                    Var(6),
+                    Expr::table(Expr::var(6), Expr::str("rep".to_string())),
+                ),
+                Insn::Call(
+                    vec![Var(6), Var(7)].into(),
+                    vec![Expr::Var(Var(6)), Expr::Var(Var(0)), Expr::Var(Var(1))].into(),
+                ),
+            ]),
+        );
+        graph.add_node(
+            2,
+            block(vec![
+                Insn::set_var(Var(5), Expr::str("x".to_string())),
+                Insn::set_var(Var(6), Expr::str("y".to_string())),
+                Insn::TypeGuard(Var(4), Type::Number),
+            ]),
+        );
+        graph.add_node(3, block(vec![Insn::Return(vec![].into())]));
+        graph.add_edge(BranchKind::True, 0, 1);
+        graph.add_edge(BranchKind::False, 0, 2);
+        graph.add_edge(BranchKind::Unconditional, 1, 3);
+        graph.add_edge(BranchKind::Unconditional, 2, 3);
+
+        graph
+    }
+
+    #[test]
+    fn infer_slot_types() {
+        let graph = test_graph();
+        let result = TypeInference::analyze(&graph, &BTreeSet::new());
+
+        let exit = result.exit[&1].as_ref().unwrap();
+        assert_eq!(exit.get(&6), Some(&Type::String));
+        assert_eq!(exit.get(&7), None);
+        assert_eq!(
+            result.exit[&2].as_ref().unwrap().get(&4),
+            Some(&Type::Number)
+        );
+
+        let merged: Vec<(u16, Type)> = result.entry[&3]
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(&slot, &ty)| (slot, ty))
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                (0, Type::String),
+                (1, Type::Number),
+                (2, Type::Number),
+                (3, Type::Table),
+                (6, Type::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_captured_slots() {
+        let graph = test_graph();
+        let captured: BTreeSet<u16> = [0].into_iter().collect();
+        let result = TypeInference::analyze(&graph, &captured);
+
+        let types = TypeInference::new(&captured)
+            .types_before(graph.node_weight(0).unwrap(), &result.entry[&0]);
+        assert_eq!(types.len(), 5);
+        assert_eq!(types[2].get(&0), None);
+        assert_eq!(types[2].get(&1), Some(&Type::Number));
+        assert_eq!(types[4].get(&2), None);
+    }
+
+    #[test]
+    fn istype_tags() {
+        assert_eq!(Type::from_type_tag(5), Type::String);
+        assert_eq!(Type::from_type_tag(12), Type::Table);
+        assert_eq!(Type::from_type_tag(14), Type::Number);
+        assert_eq!(Type::from_type_tag(4), Type::Unknown);
+        assert_eq!(Type::String.join(Type::Number), Type::Unknown);
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Var(pub u16);
 
@@ -16,7 +16,7 @@ impl From<u16> for Var {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Dst(pub u16);
 
@@ -34,7 +34,7 @@ impl From<u16> for Dst {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Base(pub u16);
 
@@ -52,7 +52,7 @@ impl From<u16> for Base {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct RBase(pub u16);
 
@@ -70,7 +70,7 @@ impl From<u16> for RBase {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct UV(pub u16);
 
@@ -88,7 +88,7 @@ impl From<u16> for UV {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Lit(pub u8);
 
@@ -105,7 +105,7 @@ impl From<u16> for Lit {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct LitS(pub i16);
 
@@ -122,7 +122,7 @@ impl From<u16> for LitS {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Str(pub u16);
 
@@ -140,7 +140,7 @@ impl From<u16> for Str {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Num(pub u16);
 
@@ -158,7 +158,7 @@ impl From<u16> for Num {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum Pri {
     Nil = 0,
@@ -188,7 +188,7 @@ impl From<u16> for Pri {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Tab(pub u16);
 
@@ -206,7 +206,7 @@ impl From<u16> for Tab {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Func(pub u16);
 
@@ -224,7 +224,7 @@ impl From<u16> for Func {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct CData(pub u16);
 
@@ -242,7 +242,7 @@ impl From<u16> for CData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Jump(pub i16);
 
@@ -0,0 +1,338 @@
+use crate::instruction::{Instruction, Operand};
+use crate::op::Op;
+use crate::{ByteCodeDump, ByteCodeProto, DecompileError};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Instruction of the dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Location {
+    /// index of the prototype in the dump
+    pub proto: usize,
+    /// 1-based instruction number as in `luajit -bl` listing
+    pub pc: u32,
+}
+
+/// Read or write of a global
+#[derive(Debug, Clone, Serialize)]
+pub struct GlobalAccess {
+    #[serde(flatten)]
+    pub location: Location,
+    pub write: bool,
+}
+
+/// Read or write of a table field by constant name
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldAccess {
+    #[serde(flatten)]
+    pub location: Location,
+    pub write: bool,
+    /// dotted path of the table when it is known, e.g. `os` or `lib.sys`
+    pub object: Option<String>,
+}
+
+/// Closure created by `FNEW`
+#[derive(Debug, Clone, Serialize)]
+pub struct ClosureRef {
+    #[serde(flatten)]
+    pub location: Location,
+    /// index of the closure prototype in the dump
+    pub child: usize,
+}
+
+/// Value of slot known from instructions of the current basic block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SlotValue {
+    /// global or field path, e.g. `string.format` after `GGET` and `TGETS`
+    Name(String),
+    /// closure of the prototype
+    Closure(usize),
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct SlotValues {
+    values: BTreeMap<u16, SlotValue>,
+}
+
+impl SlotValues {
+    pub(crate) fn get(&self, slot: u16) -> Option<&SlotValue> {
+        self.values.get(&slot)
+    }
+
+    pub(crate) fn insert(&mut self, slot: u16, value: SlotValue) {
+        self.values.insert(slot, value);
+    }
+
+    pub(crate) fn name(&self, slot: u16) -> Option<&str> {
+        match self.get(slot)? {
+            SlotValue::Name(name) => Some(name),
+            SlotValue::Closure(_) => None,
+        }
+    }
+
+    fn update<U>(&mut self, ins: &Instruction, proto: &ByteCodeProto, up_value: &U)
+    where
+        U: Fn(u16) -> Option<SlotValue>,
+    {
+        let value = match ins.op {
+            Op::GGET(_, d) => proto.str_from_global_table(d.0).map(SlotValue::Name),
+            Op::TGETS(_, b, c) => self
+                .name(b.0)
+                .zip(proto.str_from_global_table(c.0))
+                .map(|(object, field)| SlotValue::Name(format!("{}.{}", object, field))),
+            Op::MOV(_, d) => self.get(d.0).cloned(),
+            Op::FNEW(_, d) => proto.child_from_global_table(d.0).map(SlotValue::Closure),
+            Op::UGET(_, d) => up_value(d.0),
+            _ => None,
+        };
+
+        for slot in ins.defs() {
+            self.values.remove(&slot);
+        }
+
+        match ins.op {
+            // calls and varargs can write any number of slots above the base
+            Op::CALL(a, ..)
+            | Op::CALLM(a, ..)
+            | Op::ITERC(a, ..)
+            | Op::ITERN(a, ..)
+            | Op::VARG(a, ..) => {
+                self.values.split_off(&a.0);
+            }
+            Op::GGET(a, _) | Op::TGETS(a, ..) | Op::MOV(a, _) | Op::FNEW(a, _) | Op::UGET(a, _) => {
+                if let Some(value) = value {
+                    self.values.insert(a.0, value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Visits instructions of prototype with slot values known before each of them. Values are
+/// tracked inside of basic blocks only, every block starts with `initial` values of its first
+/// pc. Up values read by `UGET` are resolved by `up_value`.
+pub(crate) fn visit_slot_values<I, U, F>(
+    proto: &ByteCodeProto,
+    initial: I,
+    up_value: U,
+    mut visit: F,
+) -> Result<(), DecompileError>
+where
+    I: Fn(u32) -> SlotValues,
+    U: Fn(u16) -> Option<SlotValue>,
+    F: FnMut(&Instruction, &SlotValues),
+{
+    let insns = Instruction::decode_all(0, proto.bc_raw())?;
+    let targets: BTreeSet<u32> = insns.iter().filter_map(|ins| ins.jump_target()).collect();
+    let mut values = initial(0);
+
+    for (pc, ins) in insns.iter().enumerate() {
+        if targets.contains(&ins.pc) || pc > 0 && insns[pc - 1].is_terminator() {
+            values = initial(ins.pc);
+        }
+
+        visit(ins, &values);
+        values.update(ins, proto, &up_value);
+    }
+
+    Ok(())
+}
+
+/// Cross references of all prototypes of a dump: globals, string constants, table fields,
+/// closures and calls of named functions
+#[derive(Debug, Default, Serialize)]
+pub struct XrefDb {
+    globals: BTreeMap<String, Vec<GlobalAccess>>,
+    strings: BTreeMap<String, Vec<Location>>,
+    fields: BTreeMap<String, Vec<FieldAccess>>,
+    closures: Vec<ClosureRef>,
+    calls: BTreeMap<String, Vec<Location>>,
+}
+
+impl XrefDb {
+    pub fn build(dump: &ByteCodeDump) -> Result<Self, DecompileError> {
+        let mut db = XrefDb::default();
+
+        for (proto_idx, proto) in dump.prototypes().iter().enumerate() {
+            db.add_proto(proto_idx, proto)?;
+        }
+
+        Ok(db)
+    }
+
+    fn add_proto(&mut self, proto_idx: usize, proto: &ByteCodeProto) -> Result<(), DecompileError> {
+        let str_const = |idx: u16| proto.str_from_global_table(idx);
+
+        visit_slot_values(
+            proto,
+            |_| SlotValues::default(),
+            |_| None,
+            |ins, values| {
+                let location = Location {
+                    proto: proto_idx,
+                    pc: ins.pc + 1,
+                };
+
+                for operand in ins.operands() {
+                    if let Operand::Str(idx) = operand {
+                        if let Some(str) = str_const(idx) {
+                            self.strings.entry(str).or_default().push(location);
+                        }
+                    }
+                }
+
+                match ins.op {
+                    Op::GGET(_, d) | Op::GSET(_, d) => {
+                        if let Some(name) = str_const(d.0) {
+                            let write = matches!(ins.op, Op::GSET(..));
+                            let access = GlobalAccess { location, write };
+                            self.globals.entry(name).or_default().push(access);
+                        }
+                    }
+                    Op::TGETS(_, b, c) | Op::TSETS(_, b, c) => {
+                        if let Some(name) = str_const(c.0) {
+                            let access = FieldAccess {
+                                location,
+                                write: matches!(ins.op, Op::TSETS(..)),
+                                object: values.name(b.0).map(|object| object.to_string()),
+                            };
+                            self.fields.entry(name).or_default().push(access);
+                        }
+                    }
+                    Op::FNEW(_, d) => {
+                        if let Some(child) = proto.child_from_global_table(d.0) {
+                            self.closures.push(ClosureRef { location, child });
+                        }
+                    }
+                    Op::CALL(a, ..) | Op::CALLM(a, ..) | Op::CALLT(a, _) | Op::CALLMT(a, _) => {
+                        if let Some(name) = values.name(a.0) {
+                            self.calls
+                                .entry(name.to_string())
+                                .or_default()
+                                .push(location);
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        )
+    }
+
+    /// Globals used by the dump
+    pub fn globals(&self) -> impl Iterator<Item = &str> {
+        self.globals.keys().map(|name| name.as_str())
+    }
+
+    pub fn global_accesses(&self, name: &str) -> &[GlobalAccess] {
+        self.globals.get(name).map_or(&[], |accesses| accesses)
+    }
+
+    pub fn global_reads(&self, name: &str) -> impl Iterator<Item = Location> + '_ {
+        self.global_accesses(name)
+            .iter()
+            .filter(|access| !access.write)
+            .map(|access| access.location)
+    }
+
+    pub fn global_writes(&self, name: &str) -> impl Iterator<Item = Location> + '_ {
+        self.global_accesses(name)
+            .iter()
+            .filter(|access| access.write)
+            .map(|access| access.location)
+    }
+
+    /// Instructions using string constant, e.g. `KSTR`, `GGET` or `TGETS`
+    pub fn string_uses(&self, str: &str) -> &[Location] {
+        self.strings.get(str).map_or(&[], |locations| locations)
+    }
+
+    pub fn field_accesses(&self, name: &str) -> &[FieldAccess] {
+        self.fields.get(name).map_or(&[], |accesses| accesses)
+    }
+
+    pub fn closures(&self) -> &[ClosureRef] {
+        &self.closures
+    }
+
+    /// `FNEW` creating closure of the prototype, `None` for the main prototype
+    pub fn closure_creation(&self, child: usize) -> Option<Location> {
+        self.closures
+            .iter()
+            .find(|closure| closure.child == child)
+            .map(|closure| closure.location)
+    }
+
+    /// Named functions called by the dump
+    pub fn callees(&self) -> impl Iterator<Item = &str> {
+        self.calls.keys().map(|name| name.as_str())
+    }
+
+    /// Call sites of named function, e.g. `os.execute`
+    pub fn callers(&self, callee: &str) -> &[Location] {
+        self.calls.get(callee).map_or(&[], |locations| locations)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_listing;
+    use crate::bytecode_reader::read_bytecode_dump;
+    use crate::xref::{Location, XrefDb};
+
+    // local cmd = "password"; os.execute(cmd); config.secret = cmd; if x then print(cmd) end
+    const LISTING: &str = "-- BYTECODE -- ?:0-0\n\
+        0001    KSTR     0   0      ; \"password\"\n\
+        0002    GGET     1   1      ; \"os\"\n\
+        0003    TGETS    1   1   2  ; \"execute\"\n\
+        0004    MOV      2   0\n\
+        0005    CALL     1   1   2\n\
+        0006    GGET     1   3      ; \"config\"\n\
+        0007    TSETS    0   1   4  ; \"secret\"\n\
+        0008    GGET     1   5      ; \"x\"\n\
+        0009    ISF          1\n\
+        0010    JMP      1 => 0014\n\
+        0011    GGET     1   6      ; \"print\"\n\
+        0012    MOV      2   0\n\
+        0013    CALL     1   1   2\n\
+        0014 => RET0     0   1\n\
+        \n";
+
+    fn loc(pc: u32) -> Location {
+        Location { proto: 0, pc }
+    }
+
+    #[test]
+    fn query_xrefs() {
+        let data = assemble_listing(LISTING, None).unwrap();
+        let dump = read_bytecode_dump(&mut data.as_slice()).unwrap();
+        let db = XrefDb::build(&dump).unwrap();
+
+        assert_eq!(db.callers("os.execute"), &[loc(5)]);
+        assert_eq!(db.callers("print"), &[loc(13)]);
+        assert_eq!(
+            db.callees().collect::<Vec<&str>>(),
+            vec!["os.execute", "print"]
+        );
+
+        assert_eq!(db.string_uses("password"), &[loc(1)]);
+        assert_eq!(
+            db.global_reads("os").collect::<Vec<Location>>(),
+            vec![loc(2)]
+        );
+        assert_eq!(db.global_writes("os").count(), 0);
+
+        let secret = db.field_accesses("secret");
+        assert_eq!(secret.len(), 1);
+        assert!(secret[0].write);
+        assert_eq!(secret[0].object.as_deref(), Some("config"));
+
+        let json: serde_json::Value = serde_json::from_str(&db.to_json()).unwrap();
+        assert_eq!(json["calls"]["os.execute"][0]["pc"], 5);
+        assert_eq!(json["globals"]["config"][0]["write"], false);
+    }
+}